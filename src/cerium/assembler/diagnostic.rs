@@ -0,0 +1,51 @@
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+/// An error in an assembly source file, pointing at the offending part of a line
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Diagnostic {
+    pub file: String,
+    /// The 1-based line number
+    pub line: usize,
+    /// The byte range of the offending text within the line
+    pub columns: Range<usize>,
+    pub message: String,
+    /// The full text of the line, used to render a snippet
+    pub source_line: String,
+    /// Other places that explain the error, like the macro invocations that an error inside a
+    /// macro was expanded from, innermost first
+    pub notes: Vec<Diagnostic>,
+}
+
+impl Diagnostic {
+    /// Renders the diagnostic along with a snippet of the source line that underlines the
+    /// offending text with carets, followed by its notes
+    pub fn render(&self) -> String {
+        let mut rendered = self.render_as("error");
+        for note in &self.notes {
+            rendered += &note.render_as("note");
+        }
+        rendered
+    }
+
+    fn render_as(&self, severity: &str) -> String {
+        let line_number = self.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+        let underline_length = self.columns.len().max(1);
+
+        format!(
+            "{}: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}\n",
+            severity, self.message,
+            gutter, self.file, self.line, self.columns.start + 1,
+            gutter,
+            line_number, self.source_line,
+            gutter, " ".repeat(self.columns.start), "^".repeat(underline_length),
+        )
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.render())
+    }
+}
@@ -0,0 +1,426 @@
+use super::Section;
+use std::ops::Range;
+
+/// A constant expression, like `TABLE + 4 * ENTRY_SIZE`
+#[derive(Clone, Debug)]
+pub(super) struct Expression {
+    pub kind: ExpressionKind,
+    /// Where the expression is on its line
+    pub columns: Range<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub(super) enum ExpressionKind {
+    Number(i64),
+    /// A label or a constant defined with `.equ`
+    Symbol(String),
+    Negate(Box<Expression>),
+    Not(Box<Expression>),
+    Binary(Operator, Box<Expression>, Box<Expression>),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(super) enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    ShiftLeft,
+    ShiftRight,
+    And,
+    Or,
+}
+
+impl Operator {
+    /// How tightly the operator binds, following C: `|` loosest, then `&`, shifts, `+ -` and
+    /// `* /`
+    fn precedence(self) -> u8 {
+        match self {
+            Operator::Or => 1,
+            Operator::And => 2,
+            Operator::ShiftLeft | Operator::ShiftRight => 3,
+            Operator::Add | Operator::Subtract => 4,
+            Operator::Multiply | Operator::Divide => 5,
+        }
+    }
+}
+
+/// What an address in an object file is relative to, since it is only known once the object is
+/// linked
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(super) enum Base {
+    /// The start of the object's code or data
+    Section(Section),
+    /// A symbol that another object exports
+    External(String),
+}
+
+/// The value of an expression, which is a number, or an offset from a base in an object file
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(super) struct Value {
+    pub offset: i64,
+    pub base: Option<Base>,
+}
+
+impl Value {
+    pub fn number(offset: i64) -> Self {
+        Value { offset, base: None }
+    }
+}
+
+/// Gives the value of a symbol that an expression uses, or an error if it has none
+pub(super) type Lookup<'a> = dyn Fn(&str, Range<usize>) -> Result<Value, ExpressionError> + 'a;
+
+/// An error in an expression, pointing at columns of its line
+#[derive(Debug)]
+pub(super) struct ExpressionError {
+    pub columns: Range<usize>,
+    pub message: String,
+}
+
+impl ExpressionError {
+    fn new(columns: Range<usize>, message: String) -> Self {
+        ExpressionError { columns, message }
+    }
+}
+
+impl Expression {
+    /// Computes the value of the expression, with `lookup` giving the values of symbols.
+    /// Arithmetic wraps around at 64 bits. A value with a base can only be offset by a number or
+    /// subtracted from a value with the same base, so that the linker can fill it in.
+    pub fn evaluate(&self, lookup: &Lookup) -> Result<Value, ExpressionError> {
+        let not_relocatable = || ExpressionError::new(
+            self.columns.clone(),
+            "this expression cannot be relocated: an address in an object file can only have a number added to or subtracted from it, or be subtracted from an address in the same section".to_owned(),
+        );
+
+        Ok(match &self.kind {
+            ExpressionKind::Number(value) => Value::number(*value),
+            ExpressionKind::Symbol(name) => lookup(name, self.columns.clone())?,
+            ExpressionKind::Negate(operand) | ExpressionKind::Not(operand) => {
+                let operand = operand.evaluate(lookup)?;
+                if operand.base.is_some() {
+                    return Err(not_relocatable());
+                }
+                Value::number(match self.kind {
+                    ExpressionKind::Negate(_) => operand.offset.wrapping_neg(),
+                    _ => !operand.offset,
+                })
+            }
+            ExpressionKind::Binary(operator, left, right) => {
+                let (a, b) = (left.evaluate(lookup)?, right.evaluate(lookup)?);
+                let base = match (operator, a.base, b.base) {
+                    (_, None, None) => None,
+                    (Operator::Add, base, None) | (Operator::Add, None, base) => base,
+                    (Operator::Subtract, base, None) => base,
+                    (Operator::Subtract, Some(a), Some(b)) if a == b => None,
+                    _ => return Err(not_relocatable()),
+                };
+                let (a, b) = (a.offset, b.offset);
+                let offset = match operator {
+                    Operator::Add => a.wrapping_add(b),
+                    Operator::Subtract => a.wrapping_sub(b),
+                    Operator::Multiply => a.wrapping_mul(b),
+                    Operator::Divide if b == 0 => {
+                        return Err(ExpressionError::new(right.columns.clone(), "division by zero".to_owned()));
+                    }
+                    Operator::Divide => a.wrapping_div(b),
+                    Operator::ShiftLeft | Operator::ShiftRight if !(0..64).contains(&b) => {
+                        return Err(ExpressionError::new(right.columns.clone(), format!(
+                            "shift amount {} is out of range (expected 0 to 63)", b
+                        )));
+                    }
+                    Operator::ShiftLeft => a << b,
+                    Operator::ShiftRight => a >> b,
+                    Operator::And => a & b,
+                    Operator::Or => a | b,
+                };
+                Value { offset, base }
+            }
+        })
+    }
+
+    /// The names of the symbols that the expression uses
+    pub fn symbols(&self) -> Vec<&str> {
+        match &self.kind {
+            ExpressionKind::Number(_) => vec![],
+            ExpressionKind::Symbol(name) => vec![name],
+            ExpressionKind::Negate(operand) | ExpressionKind::Not(operand) => operand.symbols(),
+            ExpressionKind::Binary(_, left, right) => {
+                let mut symbols = left.symbols();
+                symbols.extend(right.symbols());
+                symbols
+            }
+        }
+    }
+
+    /// Replaces the columns of the expression and its parts, like when they are mapped back to
+    /// the line a macro expansion came from
+    pub fn map_columns(&mut self, map: &dyn Fn(Range<usize>) -> Range<usize>) {
+        self.columns = map(self.columns.clone());
+        match &mut self.kind {
+            ExpressionKind::Number(_) | ExpressionKind::Symbol(_) => {}
+            ExpressionKind::Negate(operand) | ExpressionKind::Not(operand) => operand.map_columns(map),
+            ExpressionKind::Binary(_, left, right) => {
+                left.map_columns(map);
+                right.map_columns(map);
+            }
+        }
+    }
+}
+
+/// The smallest pieces of an expression
+#[derive(Clone, Debug, PartialEq)]
+enum Lexeme {
+    Number(i64),
+    Symbol(String),
+    Operator(Operator),
+    /// `~`, which is only ever unary
+    Tilde,
+    OpenParenthesis,
+    CloseParenthesis,
+    /// Anything else, which ends the expression
+    Other,
+}
+
+/// Parses the longest expression that starts at byte `start` of `line`, returning it along with
+/// the offset just past its end. Whatever follows the expression is left for the caller.
+pub(super) fn parse(line: &str, start: usize) -> Result<(Expression, usize), ExpressionError> {
+    let mut parser = Parser { line, position: start };
+    let expression = parser.parse_binary(0)?;
+    Ok((expression, parser.position))
+}
+
+/// Parses an integer literal: decimal, or hexadecimal, binary or octal with the prefixes `0x`,
+/// `0b` and `0o`. Literals up to `u64::MAX` are accepted, and wrap around to negative values.
+pub(super) fn parse_integer(text: &str) -> Option<i64> {
+    let (digits, radix) = match text.get(..2) {
+        Some("0x") => (&text[2..], 16),
+        Some("0b") => (&text[2..], 2),
+        Some("0o") => (&text[2..], 8),
+        _ => (text, 10),
+    };
+    // `from_str_radix` would accept a sign, which is an operator here
+    if digits.starts_with(['+', '-']) {
+        return None;
+    }
+    u64::from_str_radix(digits, radix).ok().map(|value| value as i64)
+}
+
+/// Whether `c` can be part of a symbol name
+fn is_symbol_character(c: char) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_'
+}
+
+struct Parser<'a> {
+    line: &'a str,
+    /// The byte offset just past what has been parsed
+    position: usize,
+}
+
+impl Parser<'_> {
+    /// Where the next lexeme starts
+    fn next_start(&self) -> usize {
+        let rest = &self.line[self.position..];
+        self.position + rest.len() - rest.trim_start().len()
+    }
+
+    /// Reads the next lexeme without consuming it, returning it along with where it ends
+    fn peek(&self) -> Result<(Lexeme, Range<usize>), ExpressionError> {
+        let start = self.next_start();
+        let rest = &self.line[start..];
+        let Some(first) = rest.chars().next() else { return Ok((Lexeme::Other, start..start)) };
+
+        let single = |lexeme| Ok((lexeme, start..start + 1));
+        let double = |lexeme| Ok((lexeme, start..start + 2));
+        match first {
+            '+' => single(Lexeme::Operator(Operator::Add)),
+            '-' => single(Lexeme::Operator(Operator::Subtract)),
+            '*' => single(Lexeme::Operator(Operator::Multiply)),
+            '/' => single(Lexeme::Operator(Operator::Divide)),
+            '&' => single(Lexeme::Operator(Operator::And)),
+            '|' => single(Lexeme::Operator(Operator::Or)),
+            '~' => single(Lexeme::Tilde),
+            '(' => single(Lexeme::OpenParenthesis),
+            ')' => single(Lexeme::CloseParenthesis),
+            _ if rest.starts_with("<<") => double(Lexeme::Operator(Operator::ShiftLeft)),
+            _ if rest.starts_with(">>") => double(Lexeme::Operator(Operator::ShiftRight)),
+            '\'' => self.character_literal(start),
+            _ if first.is_ascii_digit() => {
+                let length = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+                let columns = start..start + length;
+                let value = parse_integer(&rest[..length]).ok_or_else(|| ExpressionError::new(
+                    columns.clone(),
+                    format!("invalid integer `{}`", &rest[..length]),
+                ))?;
+                Ok((Lexeme::Number(value), columns))
+            }
+            _ if is_symbol_character(first) => {
+                let length = rest.find(|c: char| !is_symbol_character(c)).unwrap_or(rest.len());
+                Ok((Lexeme::Symbol(rest[..length].to_owned()), start..start + length))
+            }
+            _ => Ok((Lexeme::Other, start..start)),
+        }
+    }
+
+    /// Reads a character literal like `'a'` or `'\n'`, whose value is the character's code point
+    fn character_literal(&self, start: usize) -> Result<(Lexeme, Range<usize>), ExpressionError> {
+        let rest = &self.line[start + 1..];
+        let mut chars = rest.char_indices();
+        let invalid = |end: usize| ExpressionError::new(
+            start..end,
+            "invalid character literal: expected a single character or escape between `'`s".to_owned(),
+        );
+
+        let value = match chars.next() {
+            Some((_, '\\')) => match chars.next() {
+                Some((_, escape @ ('\\' | '\'' | '"'))) => escape as i64,
+                Some((_, 'n')) => '\n' as i64,
+                Some((_, 'r')) => '\r' as i64,
+                Some((_, 't')) => '\t' as i64,
+                Some((_, '0')) => 0,
+                Some((index, 'x')) => {
+                    let digits = rest.get(index + 1..index + 3)
+                        .filter(|digits| digits.chars().all(|c| c.is_ascii_hexdigit()))
+                        .ok_or_else(|| ExpressionError::new(
+                            start..start + 1 + rest.len().min(index + 3),
+                            "invalid escape: expected `\\x` followed by two hex digits".to_owned(),
+                        ))?;
+                    chars.nth(1);
+                    i64::from_str_radix(digits, 16).unwrap()
+                }
+                Some((index, escape)) => {
+                    return Err(ExpressionError::new(
+                        start + index..start + 1 + index + escape.len_utf8(),
+                        format!("unknown escape `\\{}`", escape),
+                    ));
+                }
+                None => return Err(invalid(self.line.len())),
+            },
+            Some((_, '\'')) | None => return Err(invalid(start + 1 + rest.len().min(1))),
+            Some((_, c)) => c as i64,
+        };
+
+        match chars.next() {
+            Some((index, '\'')) => Ok((Lexeme::Number(value), start..start + index + 2)),
+            Some((index, _)) => Err(invalid(start + 1 + index)),
+            None => Err(invalid(self.line.len())),
+        }
+    }
+
+    /// Parses operators that bind at least as tightly as `min_precedence`, and their operands
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Expression, ExpressionError> {
+        let mut left = self.parse_unary()?;
+        while let (Lexeme::Operator(operator), columns) = self.peek()? {
+            if operator.precedence() <= min_precedence {
+                break;
+            }
+            self.position = columns.end;
+            let right = self.parse_binary(operator.precedence())?;
+            left = Expression {
+                columns: left.columns.start..right.columns.end,
+                kind: ExpressionKind::Binary(operator, Box::new(left), Box::new(right)),
+            };
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, ExpressionError> {
+        let (lexeme, columns) = self.peek()?;
+        self.position = columns.end;
+        let kind = match lexeme {
+            Lexeme::Number(value) => ExpressionKind::Number(value),
+            Lexeme::Symbol(name) => ExpressionKind::Symbol(name),
+            Lexeme::Operator(Operator::Add) => return self.parse_unary(),
+            Lexeme::Operator(Operator::Subtract) | Lexeme::Tilde => {
+                let operand = Box::new(self.parse_unary()?);
+                let columns = columns.start..operand.columns.end;
+                let kind = if lexeme == Lexeme::Tilde {
+                    ExpressionKind::Not(operand)
+                } else {
+                    ExpressionKind::Negate(operand)
+                };
+                return Ok(Expression { kind, columns });
+            }
+            Lexeme::OpenParenthesis => {
+                let mut inner = self.parse_binary(0)?;
+                let (close, close_columns) = self.peek()?;
+                if close != Lexeme::CloseParenthesis {
+                    return Err(ExpressionError::new(
+                        columns,
+                        "unclosed `(`: expected a matching `)`".to_owned(),
+                    ));
+                }
+                self.position = close_columns.end;
+                inner.columns = columns.start..close_columns.end;
+                return Ok(inner);
+            }
+            Lexeme::Operator(_) | Lexeme::CloseParenthesis | Lexeme::Other => {
+                let rest = &self.line[columns.start..];
+                let found = rest.split_whitespace().next();
+                return Err(match found {
+                    Some(found) => ExpressionError::new(
+                        columns.start..columns.start + found.len(),
+                        format!("expected a value, found `{}`", found),
+                    ),
+                    None => ExpressionError::new(
+                        columns.start..columns.start + 1,
+                        "expected a value, found end of line".to_owned(),
+                    ),
+                });
+            }
+        };
+        Ok(Expression { kind, columns })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Evaluates `text` with the symbols `TABLE` = 0x100 and `SIZE` = 12
+    fn evaluate(text: &str) -> Result<i64, ExpressionError> {
+        let lookup = |name: &str, columns: Range<usize>| match name {
+            "TABLE" => Ok(Value::number(0x100)),
+            "SIZE" => Ok(Value::number(12)),
+            _ => Err(ExpressionError::new(columns, format!("unknown symbol `{}`", name))),
+        };
+        let (expression, end) = parse(text, 0)?;
+        assert_eq!(end, text.len(), "`{}` was not parsed completely", text);
+        Ok(expression.evaluate(&lookup)?.offset)
+    }
+
+    #[test]
+    fn operators_bind_like_in_c() {
+        assert_eq!(evaluate("1 + 2 * 3").unwrap(), 7);
+        assert_eq!(evaluate("(1 + 2) * 3").unwrap(), 9);
+        assert_eq!(evaluate("10 - 3 - 2").unwrap(), 5);
+        assert_eq!(evaluate("100 / 10 / 5").unwrap(), 2);
+        assert_eq!(evaluate("1 << 2 + 1").unwrap(), 8);
+        assert_eq!(evaluate("6 | 1 & 2").unwrap(), 6);
+        assert_eq!(evaluate("0x30 >> 4 | 1 << 3").unwrap(), 11);
+        assert_eq!(evaluate("-2 * 3").unwrap(), -6);
+        assert_eq!(evaluate("~0 & 0xff").unwrap(), 0xff);
+        assert_eq!(evaluate("-(2 - 5)").unwrap(), 3);
+        assert_eq!(evaluate("TABLE + 4 * SIZE").unwrap(), 0x130);
+        assert_eq!(evaluate("'a' + 0b1 + 0o10").unwrap(), 106);
+    }
+
+    #[test]
+    fn parsing_stops_at_the_end_of_the_expression() {
+        let line = "  SIZE * 2, r1";
+        let (expression, end) = parse(line, 2).unwrap();
+        assert_eq!(&line[end..], ", r1");
+        assert_eq!(expression.columns, 2..10);
+    }
+
+    #[test]
+    fn errors_point_at_their_cause() {
+        let error = evaluate("SIZE / (2 - 2)").unwrap_err();
+        assert_eq!((error.columns, error.message.as_str()), (7..14, "division by zero"));
+        let error = evaluate("1 << 64").unwrap_err();
+        assert_eq!(error.columns, 5..7);
+        let error = evaluate("TABLE + OTHER").unwrap_err();
+        assert_eq!(error.columns, 8..13);
+    }
+}
@@ -0,0 +1,56 @@
+use crate::cerium::vm::CeWord;
+use std::fmt::{Display, Formatter};
+
+/// A line of the source along with the bytes that it was assembled into
+pub(super) struct ListingLine {
+    /// The index of the line's file in [`Listing::files`]
+    pub file: usize,
+    /// The 1-based number of the line within its file
+    pub line: usize,
+    pub source: String,
+    /// The address and contents of each run of bytes the line emitted, in code or data
+    pub output: Vec<(CeWord, Vec<u8>)>,
+}
+
+/// The source of an assembled program next to the address and encoded bytes of every line
+pub struct Listing {
+    pub(super) files: Vec<String>,
+    pub(super) lines: Vec<ListingLine>,
+}
+
+impl Listing {
+    /// How many bytes are shown on each line of the listing
+    const BYTES_PER_LINE: usize = 8;
+}
+
+impl Display for Listing {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let bytes_width = Self::BYTES_PER_LINE * 3 - 1;
+        let mut file = None;
+        for line in &self.lines {
+            // Included files are spliced in, so each change of file gets a heading
+            if file != Some(line.file) {
+                file = Some(line.file);
+                writeln!(f, "// {}", self.files[line.file])?;
+            }
+
+            let mut rows = line.output.iter()
+                .flat_map(|(address, bytes)| bytes.chunks(Self::BYTES_PER_LINE).enumerate().map(
+                    move |(index, chunk)| (address + (index * Self::BYTES_PER_LINE) as CeWord, chunk)
+                ));
+            let first = match rows.next() {
+                Some((address, chunk)) => format!("0x{:08x}  {:<bytes_width$}", address, hex(chunk)),
+                None => " ".repeat(12 + bytes_width),
+            };
+            writeln!(f, "{}", format!("{:>5}  {}  {}", line.line, first, line.source).trim_end())?;
+            for (address, chunk) in rows {
+                writeln!(f, "{:>5}  0x{:08x}  {}", "", address, hex(chunk))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(" ")
+}
@@ -0,0 +1,151 @@
+use std::collections::HashSet;
+use std::ops::Range;
+
+/// A macro defined with `.macro NAME params ... .endm`
+#[derive(Clone)]
+pub(super) struct Macro {
+    pub name: String,
+    pub parameters: Vec<String>,
+    /// The numbers of the lines between `.macro` and `.endm`
+    pub body: Vec<usize>,
+    /// The line of the `.macro` directive
+    pub line: usize,
+    /// Where the name is on the line of the `.macro` directive
+    pub columns: Range<usize>,
+    /// The labels defined in the body, which are renamed in every expansion so that a macro can
+    /// be invoked more than once
+    pub local_labels: HashSet<String>,
+    /// Whether the definition is free of errors. Invalid macros expand to nothing, so that their
+    /// errors are only reported once.
+    pub valid: bool,
+}
+
+/// A line of a macro's body with the arguments of an invocation substituted in
+pub(super) struct Expansion {
+    pub text: String,
+    /// The byte range of the body line that each byte of `text` came from
+    pub origins: Vec<Range<usize>>,
+}
+
+impl Expansion {
+    fn push(&mut self, text: &str, origin: Range<usize>) {
+        self.text.push_str(text);
+        self.origins.extend(std::iter::repeat_n(origin, text.len()));
+    }
+
+    /// Copies `text`, which starts at `start` in the body line, unchanged
+    fn push_verbatim(&mut self, text: &str, start: usize) {
+        for (offset, c) in text.char_indices() {
+            let origin = start + offset..start + offset + c.len_utf8();
+            self.push(c.encode_utf8(&mut [0; 4]), origin);
+        }
+    }
+}
+
+impl Macro {
+    /// Expands a line of the body for the invocation numbered `expansion`. Every `\name` is
+    /// replaced with the argument given for the parameter `name`, and local labels get the
+    /// suffix `__<expansion>`.
+    pub fn expand_line(&self, line: &str, arguments: &[String], expansion: usize) -> Expansion {
+        let mut expanded = Expansion { text: String::with_capacity(line.len()), origins: vec![] };
+
+        let mut copied = 0;
+        for (columns, name) in parameter_references(line) {
+            self.push_renaming_labels(&mut expanded, line, copied..columns.start, expansion);
+            let index = self.parameters.iter().position(|parameter| parameter == name);
+            let argument = index.map_or(&line[columns.clone()], |index| arguments[index].as_str());
+            expanded.push(argument, columns.clone());
+            copied = columns.end;
+        }
+        self.push_renaming_labels(&mut expanded, line, copied..line.len(), expansion);
+
+        expanded
+    }
+
+    /// Copies the bytes `columns` of a body line, renaming every word that is a local label, so
+    /// that operands like `LOOP+4` and `[LOOP]` refer to the label of the same expansion. Words
+    /// inside string and character literals are left alone.
+    fn push_renaming_labels(&self, expanded: &mut Expansion, line: &str, columns: Range<usize>, expansion: usize) {
+        let mut position = columns.start;
+        while position < columns.end {
+            let rest = &line[position..columns.end];
+            let word_length = rest.find(|c: char| !is_name_character(c)).unwrap_or(rest.len());
+            if word_length == 0 {
+                let c = rest.chars().next().unwrap();
+                expanded.push_verbatim(&rest[..c.len_utf8()], position);
+                position += c.len_utf8();
+                continue;
+            }
+
+            let word = &rest[..word_length];
+            if self.local_labels.contains(word) && !super::ends_in_literal(&line[..position]) {
+                expanded.push(&format!("{}__{}", word, expansion), position..position + word_length);
+            } else {
+                expanded.push_verbatim(word, position);
+            }
+            position += word_length;
+        }
+    }
+}
+
+fn is_name_character(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Finds the `\name` parameter references in `text`, returning where each is along with the
+/// name
+pub(super) fn parameter_references(text: &str) -> Vec<(Range<usize>, &str)> {
+    let mut references = vec![];
+
+    let mut position = 0;
+    while let Some(offset) = text[position..].find('\\') {
+        let start = position + offset;
+        let name_length = text[start + 1..].find(|c: char| !is_name_character(c)).unwrap_or(text.len() - start - 1);
+        let end = start + 1 + name_length;
+        if name_length > 0 {
+            references.push((start..end, &text[start + 1..end]));
+        }
+        position = end;
+    }
+
+    references
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spin() -> Macro {
+        Macro {
+            name: "SPIN".to_owned(),
+            parameters: vec!["reg".to_owned()],
+            body: vec![],
+            line: 1,
+            columns: 7..11,
+            local_labels: HashSet::from(["LOOP".to_owned()]),
+            valid: true,
+        }
+    }
+
+    #[test]
+    fn local_labels_are_renamed_on_word_boundaries() {
+        let spin = spin();
+        let expand = |line: &str, expansion| spin.expand_line(line, &["r3".to_owned()], expansion).text;
+        assert_eq!(expand("LOOP:", 1), "LOOP__1:");
+        assert_eq!(expand("LOOP:", 2), "LOOP__2:");
+        assert_eq!(expand("    jmp LOOP+4 if i \\reg != 0", 1), "    jmp LOOP__1+4 if i r3 != 0");
+        assert_eq!(expand("    lod r1 <- i [LOOP]", 7), "    lod r1 <- i [LOOP__7]");
+        assert_eq!(expand("    jmp LOOPS always // LOOP_END, MY_LOOP", 1), "    jmp LOOPS always // LOOP_END, MY_LOOP");
+        assert_eq!(expand("    .string \"LOOP\"", 1), "    .string \"LOOP\"");
+    }
+
+    #[test]
+    fn expansions_remember_where_each_byte_came_from() {
+        let expanded = spin().expand_line("jmp LOOP \\reg", &["r3".to_owned()], 12);
+        assert_eq!(expanded.text, "jmp LOOP__12 r3");
+        assert_eq!(expanded.origins.len(), expanded.text.len());
+        assert_eq!(expanded.origins[4], 4..8);
+        assert_eq!(expanded.origins[11], 4..8);
+        assert_eq!(expanded.origins[13], 9..13);
+    }
+}
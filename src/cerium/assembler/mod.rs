@@ -1,207 +1,1079 @@
-use crate::try_do;
+mod diagnostic;
+mod expression;
+mod listing;
+mod macros;
+
+pub use diagnostic::Diagnostic;
+pub use listing::Listing;
+
+use expression::{Base, Expression, ExpressionError, Value};
+use listing::ListingLine;
+use macros::Macro;
 use std::collections::HashMap;
-use std::mem;
+use std::ops::Range;
+use std::path::Path;
 use crate::cerium::instruction::Instruction;
-use crate::cerium::instruction::instruction_parts::{BinOp, UnOp, Condition, Location, Register, Type};
+use crate::cerium::instruction::instruction_parts::{BinOp, UnOp, Condition, Immediate, JumpTarget, Location, Register, Type};
+use crate::cerium::object::{ObjectFile, ObjectSection, Relocation, RelocationTarget};
+use crate::cerium::program::{LineInfo, Program, Symbol};
+use crate::cerium::vm::RAM;
+
+/// A whitespace-separated piece of a source line
+#[derive(Copy, Clone)]
+struct Token<'a> {
+    text: &'a str,
+    /// The byte offset of the token within its line
+    start: usize,
+}
+
+impl Token<'_> {
+    fn span(&self) -> Range<usize> {
+        self.start..self.start + self.text.len()
+    }
+}
+
+/// The tokens of a single source line
+struct Tokens<'a> {
+    line: &'a str,
+    tokens: Vec<Token<'a>>,
+    position: usize,
+    /// The byte offset just past the last token, where "missing operand" errors point
+    end: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(line: &'a str) -> Tokens<'a> {
+        let tokens: Vec<Token> = line.split_whitespace()
+            .map(|text| Token { text, start: text.as_ptr() as usize - line.as_ptr() as usize })
+            .collect();
+        let end = tokens.last().map_or(0, |token| token.start + token.text.len());
+
+        Tokens { line, tokens, position: 0, end }
+    }
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let token = self.tokens.get(self.position).copied();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.position).copied()
+    }
+
+    /// Takes the rest of the line as a single token, whitespace included
+    fn rest(&mut self) -> Option<Token<'a>> {
+        let start = self.peek()?.start;
+        self.position = self.tokens.len();
+        Some(Token { text: &self.line[start..self.end], start })
+    }
+
+    /// Moves to the first token that ends after byte `offset` of the line, which is split if
+    /// `offset` falls inside of it
+    fn seek(&mut self, offset: usize) {
+        self.position = self.tokens.iter()
+            .position(|token| token.start + token.text.len() > offset)
+            .unwrap_or(self.tokens.len());
+        if let Some(token) = self.tokens.get_mut(self.position) {
+            if token.start < offset {
+                *token = Token { text: &token.text[offset - token.start..], start: offset };
+            }
+        }
+    }
+}
+
+/// Removes the `//` comment from a line, if there is one outside of a string or character
+/// literal
+fn strip_comment(line: &str) -> &str {
+    line.match_indices("//")
+        .map(|(index, _)| index)
+        .find(|&index| !ends_in_literal(&line[..index]))
+        .map_or(line, |index| &line[..index])
+}
+
+/// Whether the end of `text` lies inside a string or character literal
+fn ends_in_literal(text: &str) -> bool {
+    let mut quote = None;
+    let mut escaped = false;
+    for c in text.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quote.is_some() => escaped = true,
+            '"' | '\'' if quote.is_none() => quote = Some(c),
+            _ if quote == Some(c) => quote = None,
+            _ => {}
+        }
+    }
+    quote.is_some()
+}
+
+/// Where the assembler places what it emits
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Section {
+    /// Instructions, from the start of the file or after `.code`
+    Code,
+    /// Read-only data, after `.data`, which the VM maps at [`RAM::STATIC_PTR_BIT`]
+    Data,
+}
+
+impl Section {
+    fn in_object(self) -> ObjectSection {
+        match self {
+            Section::Code => ObjectSection::Code,
+            Section::Data => ObjectSection::Data,
+        }
+    }
+}
+
+/// Where a line of the source came from, since `.include` splices other files into it
+struct LineOrigin {
+    /// The index of the file in `files`
+    file: usize,
+    /// The 1-based number of the line within its file
+    line: usize,
+    /// The number of the line in the top-level file that the line is in or was included from,
+    /// which is what the debug information refers to
+    top_level_line: usize,
+    /// The line of the `.include` directive that the line's file was spliced in by
+    included_by: Option<usize>,
+}
+
+/// The bytes that a line of the source emitted, for the listing
+struct ListedLine {
+    /// The 1-based number of the line within `source_lines`
+    line: usize,
+    code: Range<usize>,
+    data: Range<usize>,
+}
+
+/// A use of a label whose address is filled in once every label is known
+struct LabelReference {
+    name: String,
+    line: usize,
+    columns: Range<usize>,
+    /// The macro invocations that the use was expanded from, for diagnostics
+    notes: Vec<Diagnostic>,
+}
+
+/// A placeholder for a value that uses labels, which is filled in once every label is known
+struct LabelFixup {
+    /// Where the placeholder starts in its section
+    location: usize,
+    section: Section,
+    /// `Int8`, `Int16` or `UInt32`, for a placeholder of 1, 2 or 4 bytes
+    ty: Type,
+    expression: Expression,
+    /// The text of the expression, for diagnostics
+    text: String,
+    /// What the value is used for, like "`.byte`", for diagnostics
+    context: String,
+    line: usize,
+    /// The macro invocations that the value was expanded from, for diagnostics
+    notes: Vec<Diagnostic>,
+    /// For PC-relative targets, the address that the offset is relative to
+    relative_to: Option<usize>,
+}
+
+/// The target operand of a `jmp` or `call`
+enum Target {
+    Register(Location),
+    Direct(JumpTarget),
+}
 
 pub struct CasmAssembler {
+    /// The names of the source files, with the top-level file first
+    files: Vec<String>,
+    /// The lines of the source, with the lines of included files spliced in
+    source_lines: Vec<String>,
+    line_origins: Vec<LineOrigin>,
+    /// What each line of the source emitted
+    listed_lines: Vec<ListedLine>,
+    /// The 1-based number of the line being assembled within `source_lines`
+    line_number: usize,
+    /// Whether the output is an object file, whose addresses are relocated when it is linked
+    object: bool,
     output_buffer: Vec<u8>,
-    label_placeholder_locations: Vec<(usize, String)>,
+    /// The contents of the data section
+    data: Vec<u8>,
+    section: Section,
+    /// The largest alignment that the data section asks for with `.align`
+    data_alignment: usize,
+    label_placeholder_locations: Vec<LabelFixup>,
+    /// The fixups of the line being assembled, which are only kept if the line has no errors
+    pending_fixups: Vec<LabelFixup>,
     label_locations: HashMap<String, usize>,
+    /// The values of the constants defined with `.equ`
+    constants: HashMap<String, i64>,
+    /// The lines that each label and constant is defined on
+    label_definition_lines: HashMap<String, usize>,
+    entry_label: Option<LabelReference>,
+    /// The labels named by `.global`, which other object files can refer to
+    exports: Vec<LabelReference>,
+    /// The symbols declared with `.extern`, which other object files define
+    externs: Vec<String>,
+    /// The values that the linker fills in, in an object file
+    relocations: Vec<Relocation>,
+    /// The relocations of the line being assembled, which are only kept if the line has no errors
+    pending_relocations: Vec<Relocation>,
+    line_info: Vec<LineInfo>,
+    imports: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+    macros: HashMap<String, Macro>,
+    /// The macro whose body is being read, between its `.macro` and `.endm` lines
+    macro_being_defined: Option<Macro>,
+    /// Notes pointing at the macro invocations being expanded, outermost first
+    expansion_sites: Vec<Diagnostic>,
+    /// Inside a macro expansion, the columns of the macro's definition that each byte of the
+    /// line being assembled came from
+    column_origins: Vec<Range<usize>>,
+    /// How many macro invocations have been expanded, which numbers their local labels
+    expansion_count: usize,
 }
 
 impl CasmAssembler {
-    pub fn assemble(source: &str) -> Box<[u8]> {
+    /// How deeply macro invocations can be nested, which stops macros that invoke themselves
+    const MAX_MACRO_DEPTH: usize = 64;
+
+    /// Assembles CASM source code into a program. `file_name` is used in diagnostics, and to find
+    /// the files that the source includes.
+    pub fn assemble(file_name: &str, source: &str) -> Result<Program, Vec<Diagnostic>> {
+        Self::assemble_with_listing(file_name, source).map(|(program, _)| program)
+    }
+
+    /// Assembles CASM source code into a program, along with a listing of the source next to the
+    /// bytes that each line was assembled into
+    pub fn assemble_with_listing(file_name: &str, source: &str) -> Result<(Program, Listing), Vec<Diagnostic>> {
+        let mut assembler = Self::assemble_source(file_name, source, false);
+        let entry_point = match assembler.entry_label.take() {
+            Some(label) => assembler.resolve_code_label(&label).unwrap_or(0) as u32,
+            None => 0,
+        };
+        assembler.exported_symbols();
+
+        if !assembler.diagnostics.is_empty() {
+            return Err(assembler.diagnostics);
+        }
+
+        let listing = assembler.listing();
+        let symbols = assembler.sorted_labels().into_iter()
+            .map(|(name, address)| Symbol { name, address })
+            .collect();
+
+        Ok((Program {
+            entry_point,
+            code: assembler.output_buffer,
+            rodata: assembler.data,
+            symbols,
+            line_info: assembler.line_info,
+            imports: assembler.imports,
+        }, listing))
+    }
+
+    /// Assembles CASM source code into a relocatable object file, which can refer to the symbols
+    /// that other object files export with `.global` once they are linked together
+    pub fn assemble_object(file_name: &str, source: &str) -> Result<ObjectFile, Vec<Diagnostic>> {
+        Self::assemble_object_with_listing(file_name, source).map(|(object, _)| object)
+    }
+
+    /// Assembles CASM source code into a relocatable object file, along with a listing whose
+    /// addresses are the ones the object has before it is linked
+    pub fn assemble_object_with_listing(
+        file_name: &str,
+        source: &str,
+    ) -> Result<(ObjectFile, Listing), Vec<Diagnostic>> {
+        let mut assembler = Self::assemble_source(file_name, source, true);
+        let entry_point = assembler.entry_label.take()
+            .and_then(|label| assembler.resolve_code_label(&label))
+            .map(|address| address as u32);
+        let exports = assembler.exported_symbols();
+
+        if !assembler.diagnostics.is_empty() {
+            return Err(assembler.diagnostics);
+        }
+
+        let listing = assembler.listing();
+        let symbols = assembler.sorted_labels();
+
+        Ok((ObjectFile {
+            entry_point,
+            code: assembler.output_buffer,
+            rodata: assembler.data,
+            data_alignment: assembler.data_alignment as u32,
+            symbols,
+            exports,
+            externs: assembler.externs,
+            imports: assembler.imports,
+            relocations: assembler.relocations,
+        }, listing))
+    }
+
+    /// Assembles every line of the source and of the files it includes, and fills in the fixups
+    fn assemble_source(file_name: &str, source: &str, object: bool) -> CasmAssembler {
+        let source_lines = Self::split_lines(source);
+        let line_origins = (1..=source_lines.len())
+            .map(|line| LineOrigin { file: 0, line, top_level_line: line, included_by: None })
+            .collect();
         let mut assembler = CasmAssembler {
+            files: vec![file_name.to_owned()],
+            source_lines,
+            line_origins,
+            listed_lines: vec![],
+            line_number: 0,
+            object,
             output_buffer: vec![],
+            data: vec![],
+            section: Section::Code,
+            data_alignment: 1,
             label_placeholder_locations: Default::default(),
+            pending_fixups: vec![],
             label_locations: Default::default(),
+            constants: Default::default(),
+            label_definition_lines: Default::default(),
+            entry_label: None,
+            exports: vec![],
+            externs: vec![],
+            relocations: vec![],
+            pending_relocations: vec![],
+            line_info: vec![],
+            imports: vec![],
+            diagnostics: vec![],
+            macros: Default::default(),
+            macro_being_defined: None,
+            expansion_sites: vec![],
+            column_origins: vec![],
+            expansion_count: 0,
         };
 
-        for line in source.split("\n") {
-            let line = line.trim();
-            if line.starts_with("//") || line.is_empty() {
-                continue;
+        // Included files are spliced in after their `.include` line, so the number of lines grows
+        while assembler.line_number < assembler.source_lines.len() {
+            assembler.line_number += 1;
+            let line = assembler.source_lines[assembler.line_number - 1].clone();
+            let (code_start, data_start) = (assembler.output_buffer.len(), assembler.data.len());
+            assembler.assemble_line(&line);
+            assembler.listed_lines.push(ListedLine {
+                line: assembler.line_number,
+                code: code_start..assembler.output_buffer.len(),
+                data: data_start..assembler.data.len(),
+            });
+        }
+        if let Some(definition) = assembler.macro_being_defined.take() {
+            let diagnostic = assembler.diagnostic(
+                definition.line,
+                definition.columns,
+                format!("macro `{}` has no `.endm`", definition.name),
+            );
+            assembler.diagnostics.push(diagnostic);
+        }
+
+        assembler.insert_labels();
+        assembler
+    }
+
+    /// The labels and their addresses, sorted by address. Labels at the same address are in the
+    /// order they are defined in, so the last one is the closest to the code that follows them.
+    fn sorted_labels(&self) -> Vec<(String, u32)> {
+        let mut labels: Vec<(String, u32)> = self.label_locations.iter()
+            .map(|(name, &address)| (name.clone(), address as u32))
+            .collect();
+        labels.sort_by(|(a, a_address), (b, b_address)| a_address.cmp(b_address)
+            .then_with(|| self.label_definition_lines.get(a).cmp(&self.label_definition_lines.get(b)))
+            .then_with(|| a.cmp(b)));
+        labels
+    }
+
+    /// Pairs every line of the source with the bytes it emitted, once the fixups are filled in
+    fn listing(&self) -> Listing {
+        let lines = self.listed_lines.iter()
+            .map(|listed| {
+                let origin = &self.line_origins[listed.line - 1];
+                let mut output = vec![];
+                if !listed.code.is_empty() {
+                    output.push((listed.code.start as u32, self.output_buffer[listed.code.clone()].to_vec()));
+                }
+                if !listed.data.is_empty() {
+                    let address = (listed.data.start as u32) | RAM::STATIC_PTR_BIT;
+                    output.push((address, self.data[listed.data.clone()].to_vec()));
+                }
+                ListingLine {
+                    file: origin.file,
+                    line: origin.line,
+                    source: self.source_lines[listed.line - 1].clone(),
+                    output,
+                }
+            })
+            .collect();
+        Listing { files: self.files.clone(), lines }
+    }
+
+    fn split_lines(source: &str) -> Vec<String> {
+        source.split('\n').map(|line| line.trim_end().to_owned()).collect()
+    }
+
+    /// Assembles a line of the source, or of a macro expansion
+    fn assemble_line(&mut self, line: &str) {
+        let line = strip_comment(line);
+        let mut tokens = Tokens::new(line);
+        if tokens.peek().is_none() {
+            return;
+        }
+
+        if self.macro_being_defined.is_some() {
+            if let Err(diagnostic) = self.record_macro_line(&mut tokens) {
+                self.diagnostics.push(diagnostic);
+            }
+            return;
+        }
+
+        let address = self.output_buffer.len();
+        match self.parse_line(&mut tokens) {
+            Ok(()) => {
+                self.label_placeholder_locations.append(&mut self.pending_fixups);
+                self.relocations.append(&mut self.pending_relocations);
+            }
+            Err(diagnostic) => {
+                self.pending_fixups.clear();
+                self.pending_relocations.clear();
+                self.diagnostics.push(diagnostic);
+            }
+        }
+        // Code expanded from a macro belongs to the line that invoked it, and code from an
+        // included file to the `.include` line
+        if self.output_buffer.len() > address && self.expansion_sites.is_empty() {
+            self.line_info.push(LineInfo {
+                address: address as u32,
+                line: self.line_origins[self.line_number - 1].top_level_line as u32,
+            });
+        }
+    }
+
+    fn diagnostic(&self, line: usize, columns: Range<usize>, message: String) -> Diagnostic {
+        let origin = &self.line_origins[line - 1];
+        Diagnostic {
+            file: self.files[origin.file].clone(),
+            line: origin.line,
+            columns,
+            message,
+            source_line: self.source_lines[line - 1].clone(),
+            notes: vec![],
+        }
+    }
+
+    /// Describes where a line is for a message, naming its file if it is not the file of the line
+    /// being assembled
+    fn describe_line(&self, line: usize) -> String {
+        let origin = &self.line_origins[line - 1];
+        if origin.file == self.line_origins[self.line_number - 1].file {
+            format!("line {}", origin.line)
+        } else {
+            format!("{}:{}", self.files[origin.file], origin.line)
+        }
+    }
+
+    /// Creates a diagnostic pointing at columns of the line being assembled. Inside a macro
+    /// expansion it points into the macro's definition, with notes pointing at the invocations.
+    fn diagnostic_here(&self, columns: Range<usize>, message: String) -> Diagnostic {
+        let mut diagnostic = self.diagnostic(self.line_number, self.source_columns(columns), message);
+        diagnostic.notes = self.expansion_notes();
+        diagnostic
+    }
+
+    /// Maps columns of the line being assembled to the columns of the source line they came from,
+    /// which differ inside a macro expansion
+    fn source_columns(&self, columns: Range<usize>) -> Range<usize> {
+        if self.expansion_sites.is_empty() {
+            return columns;
+        }
+
+        let origins = &self.column_origins;
+        match origins.get(columns.start) {
+            Some(first) => {
+                let last = &origins[columns.end.clamp(columns.start + 1, origins.len()) - 1];
+                first.start..last.end
+            }
+            None => {
+                let end = origins.last().map_or(0, |origin| origin.end);
+                end..end + 1
             }
+        }
+    }
+
+    /// Notes pointing at the macro invocations being expanded, innermost first
+    fn expansion_notes(&self) -> Vec<Diagnostic> {
+        self.expansion_sites.iter().rev().cloned().collect()
+    }
+
+    /// Creates a diagnostic pointing at a token on the current line
+    fn error_at(&self, token: Token, message: String) -> Diagnostic {
+        self.diagnostic_here(token.span(), message)
+    }
 
-            if let None = assembler.parse_line(line.split_whitespace()) {
-                println!("Invalid line: {}", line)
+    /// Takes the next token, reporting what was expected if the line has ended
+    fn expect_token<'a>(&self, items: &mut Tokens<'a>, expected: &str) -> Result<Token<'a>, Diagnostic> {
+        items.next().ok_or_else(|| self.end_of_line_error(items, expected))
+    }
+
+    fn end_of_line_error(&self, items: &Tokens, expected: &str) -> Diagnostic {
+        self.diagnostic_here(items.end..items.end + 1, format!("expected {}, found end of line", expected))
+    }
+
+    fn expect_symbol(&self, items: &mut Tokens, symbol: &str) -> Result<(), Diagnostic> {
+        let token = self.expect_token(items, &format!("`{}`", symbol))?;
+        if token.text != symbol {
+            return Err(self.error_at(token, format!("expected `{}`, found `{}`", symbol, token.text)));
+        }
+        Ok(())
+    }
+
+    fn expect_ty(&self, items: &mut Tokens) -> Result<Type, Diagnostic> {
+        let token = self.expect_token(items, "a type")?;
+        Self::parse_ty(token.text).ok_or_else(|| self.error_at(
+            token,
+            format!("expected a type (`b`, `s`, `i`, `l`, `f`, `d`, `ub`, `us` or `ui`), found `{}`", token.text),
+        ))
+    }
+
+    fn expect_location(&self, items: &mut Tokens) -> Result<Location, Diagnostic> {
+        let token = self.expect_token(items, "a register")?;
+        Self::parse_location(token.text).ok_or_else(|| self.error_at(
+            token,
+            format!(
+                "invalid register `{}`: expected `sp` or `r1` to `r7`, optionally prefixed with `@`",
+                token.text
+            ),
+        ))
+    }
+
+    fn expect_condition(&self, items: &mut Tokens) -> Result<Condition, Diagnostic> {
+        let token = self.expect_token(items, "a condition")?;
+        let condition = Self::parse_condition(token.text).ok_or_else(|| self.error_at(
+            token,
+            format!(
+                "expected a condition (`>`, `==`, `>=`, `<`, `!=` or `<=`), found `{}`",
+                token.text
+            ),
+        ))?;
+        Ok(condition)
+    }
+
+    /// Parses the optional value that a condition compares against, returning `None` if it is
+    /// zero or left out
+    fn parse_compare_value<'a>(&self, items: &mut Tokens<'a>, ty: Type) -> Result<Option<(Token<'a>, Immediate)>, Diagnostic> {
+        if items.peek().is_none() {
+            return Ok(None);
+        }
+        let (token, imm) = self.expect_immediate(items, ty)?;
+        Ok(if imm.sign_extended() == 0 { None } else { Some((token, imm)) })
+    }
+
+    /// Whether an operand is a constant rather than a register, since registers are lowercase or
+    /// start with `@`
+    fn is_immediate(text: &str) -> bool {
+        !text.starts_with(|c: char| c.is_ascii_lowercase() || c == '@')
+    }
+
+    /// Parses a constant operand of the given type. Integer operands may be expressions, whose
+    /// symbols must be defined on earlier lines.
+    fn expect_immediate<'a>(&self, items: &mut Tokens<'a>, ty: Type) -> Result<(Token<'a>, Immediate), Diagnostic> {
+        if ty == Type::Float || ty == Type::Double {
+            let token = self.expect_token(items, "a value")?;
+            let invalid = || self.error_at(token, format!("invalid float `{}`", token.text));
+            let value: f32 = token.text.parse().map_err(|_| invalid())?;
+
+            // A double immediate is stored as a 32-bit float, so it must convert back exactly
+            if ty == Type::Double {
+                let exact: f64 = token.text.parse().map_err(|_| invalid())?;
+                if value as f64 != exact && !exact.is_nan() {
+                    return Err(self.error_at(token, format!(
+                        "value `{}` is not exactly representable as an immediate of type `d`; load it with `lod d` instead",
+                        token.text
+                    )));
+                }
             }
+
+            // Small whole numbers fit in fewer bytes as integers
+            return Ok((token, if (value as i16 as f32).to_bits() == value.to_bits() {
+                Immediate::from_i32(value as i16 as i32)
+            } else {
+                Immediate::Imm32(value.to_bits())
+            }));
         }
 
-        assembler.insert_labels();
+        let (token, value) = self.expect_constant(items, "a value")?;
+        if ty == Type::Int64 {
+            let value = i32::try_from(value).map_err(|_| self.error_at(token, format!(
+                "value `{}` is out of range for an immediate of type `l` (expected -2147483648 to 2147483647); load it with `lod l` instead",
+                token.text
+            )))?;
+            return Ok((token, Immediate::from_i32(value)));
+        }
+
+        let value = self.check_integer_range(token, value, ty, &format!("type `{}`", ty))?;
+        Ok((token, Immediate::from_i32(match ty {
+            Type::Int8 | Type::UInt8 => value as i8 as i32,
+            Type::Int16 | Type::UInt16 => value as i16 as i32,
+            _ => value as i32,
+        })))
+    }
+
+    /// Checks that a value fits in a 32-bit or smaller integer type, either as a signed or an
+    /// unsigned number, and truncates it to 32 bits
+    fn check_integer_range(&self, token: Token, value: i64, ty: Type, context: &str) -> Result<u32, Diagnostic> {
+        Self::integer_in_range(value, ty).map_err(|range| self.error_at(token, format!(
+            "value `{}` is out of range for {} (expected {})", token.text, context, range
+        )))
+    }
+
+    /// Truncates a value to 32 bits if it fits in an integer type, or else returns the range that
+    /// the type allows
+    fn integer_in_range(value: i64, ty: Type) -> Result<u32, &'static str> {
+        let (min, max, range) = match ty {
+            Type::Int8 | Type::UInt8 => (-0x80, 0xff, "-128 to 255"),
+            Type::Int16 | Type::UInt16 => (-0x8000, 0xffff, "-32768 to 65535"),
+            _ => (-0x8000_0000, 0xffff_ffff, "-2147483648 to 4294967295"),
+        };
+        if !(min..=max).contains(&value) {
+            return Err(range);
+        }
+        Ok(value as u32)
+    }
+
+    /// Parses an expression that starts at the next token, leaving whatever follows it
+    fn expect_expression<'a>(&self, items: &mut Tokens<'a>, expected: &str) -> Result<(Token<'a>, Expression), Diagnostic> {
+        let start = items.peek().ok_or_else(|| self.end_of_line_error(items, expected))?.start;
+        let (expression, end) = expression::parse(items.line, start)
+            .map_err(|error| self.expression_error(error))?;
+        items.seek(end);
+        Ok((Token { text: &items.line[start..end], start }, expression))
+    }
+
+    /// Parses an expression whose value is needed right away
+    fn expect_constant<'a>(&self, items: &mut Tokens<'a>, expected: &str) -> Result<(Token<'a>, i64), Diagnostic> {
+        let (token, expression) = self.expect_expression(items, expected)?;
+        Ok((token, self.evaluate_now(&expression)?))
+    }
+
+    /// Parses the count of a `.zero` or `.align` directive
+    fn expect_count<'a>(&self, items: &mut Tokens<'a>, directive: Token) -> Result<(Token<'a>, usize), Diagnostic> {
+        let (token, count) = self.expect_constant(items, "a count")?;
+        let count = u32::try_from(count).map_err(|_| self.error_at(token, format!(
+            "value `{}` is out of range for `{}` (expected 0 to 4294967295)", token.text, directive.text
+        )))?;
+        Ok((token, count as usize))
+    }
+
+    /// Evaluates an expression on the line being assembled, so every symbol it uses must be
+    /// defined on an earlier line
+    fn evaluate_now(&self, expression: &Expression) -> Result<i64, Diagnostic> {
+        let lookup = |name: &str, columns| self.symbol_value(name).ok_or_else(|| ExpressionError {
+            columns,
+            message: format!("the value of `{}` is needed here, so it must be defined before this line", name),
+        });
+        let value = expression.evaluate(&lookup).map_err(|error| self.expression_error(error))?;
+        if value.base.is_some() {
+            return Err(self.diagnostic_here(
+                expression.columns.clone(),
+                "this value is an address that is only known once the object file is linked, so it cannot be used here".to_owned(),
+            ));
+        }
+        Ok(value.offset)
+    }
+
+    /// The value of a constant, or the address of a label. In an object file, addresses are
+    /// relative to where the linker places the object's sections, or to external symbols.
+    fn symbol_value(&self, name: &str) -> Option<Value> {
+        if let Some(&value) = self.constants.get(name) {
+            return Some(Value::number(value));
+        }
+        if self.externs.iter().any(|external| external == name) {
+            return Some(Value { offset: 0, base: Some(Base::External(name.to_owned())) });
+        }
+
+        let address = *self.label_locations.get(name)?;
+        let section = if Self::is_data_address(address) { Section::Data } else { Section::Code };
+        Some(Value { offset: address as i64, base: self.object.then_some(Base::Section(section)) })
+    }
 
-        assembler.output_buffer.into_boxed_slice()
+    fn expression_error(&self, error: ExpressionError) -> Diagnostic {
+        self.diagnostic_here(error.columns, error.message)
+    }
+
+    /// Produces the value of an expression that is emitted as an integer of type `ty` at
+    /// `location`. Expressions that only use constants are evaluated right away, while ones that
+    /// use labels get a fixup, and 0 is returned as their placeholder.
+    fn value_or_fixup(&mut self, location: usize, section: Section, ty: Type, value: (Token, Expression), context: &str) -> Result<u32, Diagnostic> {
+        let (token, expression) = value;
+        if expression.symbols().iter().all(|name| self.constants.contains_key(*name)) {
+            let value = self.evaluate_now(&expression)?;
+            return self.check_integer_range(token, value, ty, context);
+        }
+        self.add_fixup(location, section, ty, (token, expression), context, None);
+        Ok(0)
     }
 
-    fn parse_line<'a>(&mut self, mut items: impl Iterator<Item = &'a str>) -> Option<()> {
+    /// Leaves a placeholder for the value of an expression, to be filled in by `insert_labels`
+    fn add_fixup(&mut self, location: usize, section: Section, ty: Type, value: (Token, Expression), context: &str, relative_to: Option<usize>) {
+        let (token, mut expression) = value;
+        expression.map_columns(&|columns| self.source_columns(columns));
+        self.pending_fixups.push(LabelFixup {
+            location,
+            section,
+            ty,
+            expression,
+            text: token.text.to_owned(),
+            context: context.to_owned(),
+            line: self.line_number,
+            notes: self.expansion_notes(),
+            relative_to,
+        });
+    }
+
+    /// Parses the target of a `jmp` or `call`, which is either a register or a direct target: a
+    /// label, an absolute address, or an offset from the instruction like `$+8`. Labels are
+    /// encoded as offsets, with the placeholder `placeholder_offset` bytes into the instruction.
+    fn expect_target(&mut self, items: &mut Tokens, placeholder_offset: usize) -> Result<Target, Diagnostic> {
+        let token = items.peek().ok_or_else(|| self.end_of_line_error(items, "a jump target"))?;
+        if let Some(location) = Self::parse_location(token.text) {
+            items.next();
+            return Ok(Target::Register(location));
+        }
+
+        if let Some(offset) = token.text.strip_prefix('$') {
+            items.next();
+            let (negative, magnitude) = match (offset.strip_prefix('+'), offset.strip_prefix('-')) {
+                (Some(magnitude), _) => (false, magnitude),
+                (_, Some(magnitude)) => (true, magnitude),
+                _ => return Err(self.error_at(token, format!(
+                    "invalid relative target `{}`: expected `$+N` or `$-N`", token.text
+                ))),
+            };
+            let magnitude = expression::parse_integer(magnitude)
+                .and_then(|magnitude| u32::try_from(magnitude).ok())
+                .ok_or_else(|| self.error_at(token, format!("invalid relative target `{}`", token.text)))?;
+            let offset = if negative { (magnitude as i32).wrapping_neg() } else { magnitude as i32 };
+            return Ok(Target::Direct(JumpTarget::Relative(offset)));
+        }
+
+        // Targets that only use constants are absolute addresses, while labels are encoded as
+        // offsets from the instruction
+        let (token, expression) = self.expect_expression(items, "a jump target")?;
+        if expression.symbols().iter().all(|name| self.constants.contains_key(*name)) {
+            let address = self.evaluate_now(&expression)?;
+            let address = self.check_integer_range(token, address, Type::UInt32, "a jump target")?;
+            return Ok(Target::Direct(JumpTarget::Absolute(address)));
+        }
+
+        let start = self.output_buffer.len();
+        self.add_fixup(start + placeholder_offset, Section::Code, Type::UInt32, (token, expression), "a jump target", Some(start));
+        Ok(Target::Direct(JumpTarget::Relative(0)))
+    }
+
+    fn expect_end(&self, items: &mut Tokens) -> Result<(), Diagnostic> {
+        match items.next() {
+            Some(token) => Err(self.error_at(token, format!("unexpected `{}` after instruction", token.text))),
+            None => Ok(()),
+        }
+    }
+
+    fn parse_line(&mut self, items: &mut Tokens) -> Result<(), Diagnostic> {
         let command = items.next().unwrap();
 
         use BinOp::*;
         use UnOp::*;
 
-        match command {
+        let instruction = match command.text {
             // Labels
-            _ if command.chars().last().unwrap() == ':' && command.chars().rev().skip(1).all(
-                Self::is_label_character
-            ) => {
-                self.set_label_value(command);
-                return Some(());
+            _ if command.text.ends_with(':') => {
+                self.set_label_value(command)?;
+                if items.peek().is_some() {
+                    return self.parse_line(items);
+                }
+                return Ok(());
             }
 
+            // Directives
+            ".entry" => {
+                let label = self.expect_token(items, "a label")?;
+                self.expect_end(items)?;
+                self.entry_label = Some(self.label_reference(label)?);
+                return Ok(());
+            }
+            ".macro" => return self.begin_macro(items),
+            ".equ" => return self.define_constant(items),
+            ".include" => return self.include_file(command, items),
+            ".global" => {
+                let names = Self::split_list(items);
+                if names.is_empty() {
+                    return Err(self.end_of_line_error(items, "a label"));
+                }
+                for name in names {
+                    let label = self.label_reference(name)?;
+                    self.exports.push(label);
+                }
+                return Ok(());
+            }
+            ".extern" => return self.declare_externs(command, items),
+            ".code" | ".data" => {
+                self.expect_end(items)?;
+                self.section = if command.text == ".code" { Section::Code } else { Section::Data };
+                return Ok(());
+            }
+            ".byte" | ".short" | ".int" | ".float" | ".string" | ".zero" | ".align" => {
+                return self.parse_data_directive(command, items);
+            }
+            ".endm" => return Err(self.error_at(command, "`.endm` without a matching `.macro`".to_owned())),
+
             // Arithmetic operations
-            "xor" => self.parse_and_emit_binop(&mut items, XOR)?,
-            "or" => self.parse_and_emit_binop(&mut items, OR)?,
-            "and" => self.parse_and_emit_binop(&mut items, AND)?,
-            "shl" => self.parse_and_emit_binop(&mut items, SHL)?,
-            "shr" => self.parse_and_emit_binop(&mut items, SHR)?,
-            "mul" => self.parse_and_emit_binop(&mut items, MUL)?,
-            "add" => self.parse_and_emit_binop(&mut items, ADD)?,
-            "sub" => self.parse_and_emit_binop(&mut items, SUB)?,
-            "div" => self.parse_and_emit_binop(&mut items, DIV)?,
-            "mod" => self.parse_and_emit_binop(&mut items, MOD)?,
+            "xor" => self.parse_and_emit_binop(items, XOR)?,
+            "or" => self.parse_and_emit_binop(items, OR)?,
+            "and" => self.parse_and_emit_binop(items, AND)?,
+            "shl" => self.parse_and_emit_binop(items, SHL)?,
+            "shr" => self.parse_and_emit_binop(items, SHR)?,
+            "mul" => self.parse_and_emit_binop(items, MUL)?,
+            "add" => self.parse_and_emit_binop(items, ADD)?,
+            "sub" => self.parse_and_emit_binop(items, SUB)?,
+            "div" => self.parse_and_emit_binop(items, DIV)?,
+            "mod" => self.parse_and_emit_binop(items, MOD)?,
 
             // Other operations
             "jmp" => {
-                let tgt = Self::parse_location(items.next()?)?;
-                let (ty, src, cnd) = match items.next()? {
+                let tgt = self.expect_target(items, 4)?;
+                let keyword = self.expect_token(items, "`always` or `if`")?;
+                let (ty, src, cnd, imm) = match keyword.text {
                     "always" => (
                         Type::Int8,
                         Location { register: Register::SP, indirect: false },
-                        Condition::ALWAYS
-                    ),
-                    "if" => (
-                        Self::parse_ty(items.next()?)?,
-                        Self::parse_location(items.next()?)?,
-                        Self::parse_condition(items.next()?)?
+                        Condition::ALWAYS,
+                        None
                     ),
-                    _ => return None
+                    "if" => {
+                        let ty = self.expect_ty(items)?;
+                        let src = self.expect_location(items)?;
+                        let cnd = self.expect_condition(items)?;
+                        (ty, src, cnd, self.parse_compare_value(items, ty)?)
+                    }
+                    _ => return Err(self.error_at(
+                        keyword,
+                        format!("expected `always` or `if`, found `{}`", keyword.text),
+                    ))
                 };
-                Instruction::Jmp { ty, src, tgt, cnd }
+
+                match (tgt, imm) {
+                    (Target::Register(_), Some((token, _))) => {
+                        return Err(self.error_at(token, format!(
+                            "a jump through a register can only compare against 0, found `{}`", token.text
+                        )));
+                    }
+                    (Target::Register(tgt), None) => Instruction::Jmp { ty, src, tgt, cnd },
+                    (Target::Direct(target), imm) => Instruction::JmpTo {
+                        ty,
+                        src,
+                        target,
+                        cnd,
+                        imm: imm.map(|(_, imm)| imm),
+                    },
+                }
             }
             "cmp" => {
-                let dst = Self::parse_location(items.next()?)?;
-
-                items.next()?;
-
-                let ty = Self::parse_ty(items.next()?)?;
-                let src = Self::parse_location(items.next()?)?;
-                let cnd = Self::parse_condition(items.next()?)?;
+                let dst = self.expect_location(items)?;
+                self.expect_symbol(items, "<-")?;
+                let ty = self.expect_ty(items)?;
+                let src = self.expect_location(items)?;
+                let cnd = self.expect_condition(items)?;
 
-                Instruction::Cmp { ty, src, dst, cnd }
+                match self.parse_compare_value(items, ty)? {
+                    Some((_, imm)) => Instruction::CmpImm { ty, src, imm, dst, cnd },
+                    None => Instruction::Cmp { ty, src, dst, cnd },
+                }
             }
             "mov" => {
-                let dst_ty = Self::parse_ty(items.next()?)?;
-                let dst = Self::parse_location(items.next()?)?;
-                items.next()?;
-                let src_ty = Self::parse_ty(items.next()?)?;
-                let src = Self::parse_location(items.next()?)?;
+                let dst_ty = self.expect_ty(items)?;
+                let dst = self.expect_location(items)?;
+                self.expect_symbol(items, "<-")?;
+                let src_ty = self.expect_ty(items)?;
+                let src = self.expect_location(items)?;
 
                 Instruction::Mov { src_ty, dst_ty, src, dst }
             }
             "lod" => {
-                let dest = Self::parse_location(items.next()?)?;
-                items.next()?;
-                match items.next()? {
-                    "b" => {
-                        let value = self.parse_integral_value(items.next()?)?;
-                        if (value & 0xffffff00) != 0 && (value & 0xffffff00) != 0xffffff00 {
-                            return None;
-                        }
+                let dest = self.expect_location(items)?;
+                self.expect_symbol(items, "<-")?;
+                let kind = self.expect_token(items, "a type or a label")?;
+                match kind.text {
+                    "b" | "ub" => {
+                        let (token, value) = self.expect_constant(items, "a value")?;
+                        let value = self.check_integer_range(token, value, Type::Int8, &format!("`lod {}`", kind.text))?;
 
                         Instruction::Lod8(dest, value as u8)
                     }
-                    "s" => {
-                        let value = self.parse_integral_value(items.next()?)?;
-                        if (value & 0xffff0000) != 0 && (value & 0xffff0000) != 0xffff0000 {
-                            return None;
-                        }
+                    "s" | "us" => {
+                        let (token, value) = self.expect_constant(items, "a value")?;
+                        let value = self.check_integer_range(token, value, Type::Int16, &format!("`lod {}`", kind.text))?;
 
                         Instruction::Lod16(dest, value as u16)
                     }
-                    "i" => {
-                        let value = self.parse_integral_value(items.next()?)?;
+                    "i" | "ui" => {
+                        let value = self.expect_expression(items, "a value")?;
+                        let location = self.output_buffer.len() + 1;
+                        let context = format!("`lod {}`", kind.text);
+                        let value = self.value_or_fixup(location, Section::Code, Type::UInt32, value, &context)?;
 
                         Instruction::Lod32(dest, value)
                     }
                     "f" => {
-                        let value: f32 = try_do!(result items.next()?.parse());
-                        let value = unsafe { mem::transmute::<f32, u32>(value) };
+                        let token = self.expect_token(items, "a value")?;
+                        let value: f32 = token.text.parse().map_err(|_| self.error_at(
+                            token,
+                            format!("invalid float `{}`", token.text),
+                        ))?;
 
-                        Instruction::Lod32(dest, value)
+                        Instruction::Lod32(dest, value.to_bits())
                     }
-                    label => {
-                        if !label.chars().all(Self::is_label_character) {
-                            return None;
-                        }
+                    "l" => {
+                        let (_, value) = self.expect_constant(items, "a value")?;
 
-                        self.label_placeholder_locations.push((
-                            self.output_buffer.len() + 1,
-                            label.to_owned()
-                        ));
+                        Instruction::Lod64(dest, value as u64)
+                    }
+                    "d" => {
+                        let token = self.expect_token(items, "a value")?;
+                        let value: f64 = token.text.parse().map_err(|_| self.error_at(
+                            token,
+                            format!("invalid float `{}`", token.text),
+                        ))?;
 
-                        Instruction::Lod32(dest, 0)
+                        Instruction::Lod64(dest, value.to_bits())
+                    }
+                    // Types are lowercase, so anything else is an expression like `TABLE + 4`
+                    _ if !kind.text.starts_with(|c: char| c.is_ascii_lowercase()) => {
+                        items.seek(kind.start);
+                        let value = self.expect_expression(items, "a label")?;
+                        let location = self.output_buffer.len() + 1;
+                        let value = self.value_or_fixup(location, Section::Code, Type::UInt32, value, "`lod`")?;
+
+                        Instruction::Lod32(dest, value)
+                    }
+                    _ => {
+                        return Err(self.error_at(kind, format!(
+                            "expected a type (`b`, `s`, `i`, `l`, `f` or `d`) or a label, found `{}`", kind.text
+                        )));
                     }
                 }
             }
             "halt" => {
                 Instruction::Halt
             }
+            "call" => match self.expect_target(items, 3)? {
+                Target::Register(tgt) => Instruction::Call(tgt),
+                Target::Direct(target) => Instruction::CallTo(target),
+            },
+            "ret" => {
+                Instruction::Ret
+            }
+            "gc" => {
+                Instruction::Gc
+            }
+            "push" => {
+                let ty = self.expect_ty(items)?;
+                let src = self.expect_location(items)?;
+                Instruction::Push(ty, src)
+            }
+            "pop" => {
+                let ty = self.expect_ty(items)?;
+                let dst = self.expect_location(items)?;
+                Instruction::Pop(ty, dst)
+            }
             "memcpy" => {
                 // memcpy dst <- src ; size
-                let dst = Self::parse_location(items.next()?)?;
-                items.next()?;
-                let src = Self::parse_location(items.next()?)?;
-                items.next()?;
-                let size = Self::parse_location(items.next()?)?;
+                let dst = self.expect_location(items)?;
+                self.expect_symbol(items, "<-")?;
+                let src = self.expect_location(items)?;
+                self.expect_symbol(items, ";")?;
+                let size = self.expect_location(items)?;
 
                 Instruction::Memcpy { src, dst, size }
             }
             "new" => {
-                let dst = Self::parse_location(items.next()?)?;
-                items.next()?;
-                let size = Self::parse_location(items.next()?)?;
+                let dst = self.expect_location(items)?;
+                self.expect_symbol(items, "<-")?;
+                let size = self.expect_location(items)?;
 
                 Instruction::New { size, dst }
             }
             "del" => {
-                let src = Self::parse_location(items.next()?)?;
+                let src = self.expect_location(items)?;
                 Instruction::Del { src }
             }
-            "neg" => self.parse_and_emit_unop(&mut items, NEG)?,
-            "not" => self.parse_and_emit_unop(&mut items, NOT)?,
+            "neg" => self.parse_and_emit_unop(items, NEG)?,
+            "not" => self.parse_and_emit_unop(items, NOT)?,
             "input" => {
-                items.next()?;
-                let location = Self::parse_location(items.next()?)?;
+                self.expect_symbol(items, "->")?;
+                let location = self.expect_location(items)?;
                 Instruction::Input(location)
             }
             "output" => {
-                items.next()?;
-                let location = Self::parse_location(items.next()?)?;
+                self.expect_symbol(items, "<-")?;
+                let location = self.expect_location(items)?;
                 Instruction::Output(location)
             }
-            _ => return None
-        }.output_to(|x| self.write(x));
+            "syscall" => {
+                // Numbered host functions are called directly, and named ones through the import table
+                let token = items.peek()
+                    .ok_or_else(|| self.end_of_line_error(items, "a syscall number or a host function name"))?;
+                if !Self::is_host_function_name(token.text) {
+                    let (token, number) = self.expect_constant(items, "a syscall number")?;
+                    let number = u16::try_from(number).map_err(|_| self.error_at(token, format!(
+                        "syscall number `{}` is out of range (expected 0 to 65535)", token.text
+                    )))?;
+                    Instruction::Syscall(number)
+                } else {
+                    items.next();
+                    let index = match self.imports.iter().position(|name| name == token.text) {
+                        Some(index) => index,
+                        None => {
+                            self.imports.push(token.text.to_owned());
+                            self.imports.len() - 1
+                        }
+                    };
+                    // Linking merges the import tables of the objects, which renumbers them
+                    if self.object {
+                        self.pending_relocations.push(Relocation {
+                            section: ObjectSection::Code,
+                            location: self.output_buffer.len() as u32 + 2,
+                            size: 2,
+                            target: RelocationTarget::HostFunction(token.text.to_owned()),
+                            addend: 0,
+                            relative_to: None,
+                        });
+                    }
+                    Instruction::CallHost(index as u16)
+                }
+            }
+            _ if self.macros.contains_key(command.text) => return self.expand_macro(command, items),
+            _ => return Err(self.error_at(command, format!("unknown instruction `{}`", command.text)))
+        };
+
+        self.expect_end(items)?;
+        if self.section == Section::Data {
+            return Err(self.error_at(command, format!(
+                "instruction `{}` in the data section; switch back to code with `.code`", command.text
+            )));
+        }
+        instruction.output_to(|x| self.write(x));
 
-        Some(())
+        Ok(())
     }
 
     fn write(&mut self, x: u8) {
         self.output_buffer.push(x)
     }
 
-    fn parse_and_emit_unop<'a>(
-        &mut self,
-        items: &mut impl Iterator<Item = &'a str>,
-        op: UnOp,
-    ) -> Option<Instruction> {
-        let ty = Self::parse_ty(items.next()?)?;
-        let dst = Self::parse_location(items.next()?)?;
-        items.next()?;
-        items.next()?;
-        let src = Self::parse_location(items.next()?)?;
+    fn parse_and_emit_unop(&mut self, items: &mut Tokens, op: UnOp) -> Result<Instruction, Diagnostic> {
+        let ty = self.expect_ty(items)?;
+        let dst = self.expect_location(items)?;
+        self.expect_symbol(items, "<-")?;
+        self.expect_symbol(items, op.symbol())?;
+        let src = self.expect_location(items)?;
 
-        Some(Instruction::UnOp {
+        Ok(Instruction::UnOp {
             op,
             ty,
             src,
@@ -209,35 +1081,20 @@ impl CasmAssembler {
         })
     }
 
-    fn parse_integral_value(&self, x: &str) -> Option<u32> {
-        if let Ok(value) = x.parse::<u32>() {
-            return Some(value);
-        }
-        if let Ok(value) = x.parse::<i32>() {
-            return Some(value as u32);
-        }
-        if x.starts_with("0x") {
-            if let Ok(value) = u32::from_str_radix(&x[2..], 16) {
-                return Some(value);
-            }
-        }
-
-        None
-    }
+    fn parse_and_emit_binop(&mut self, items: &mut Tokens, op: BinOp) -> Result<Instruction, Diagnostic> {
+        let ty = self.expect_ty(items)?;
+        let dst = self.expect_location(items)?;
+        self.expect_symbol(items, "<-")?;
+        let src1 = self.expect_location(items)?;
+        self.expect_symbol(items, op.symbol())?;
 
-    fn parse_and_emit_binop<'a>(
-        &mut self,
-        items: &mut impl Iterator<Item = &'a str>,
-        op: BinOp,
-    ) -> Option<Instruction> {
-        let ty = Self::parse_ty(items.next()?)?;
-        let dst = Self::parse_location(items.next()?)?;
-        items.next()?;
-        let src1 = Self::parse_location(items.next()?)?;
-        items.next()?;
-        let src2 = Self::parse_location(items.next()?)?;
+        if items.peek().is_some_and(|token| Self::is_immediate(token.text)) {
+            let (_, imm) = self.expect_immediate(items, ty)?;
+            return Ok(Instruction::BinOpImm { op, ty, src: src1, imm, dst });
+        }
+        let src2 = self.expect_location(items)?;
 
-        Some(Instruction::BinOp { op, ty, src1, src2, dst })
+        Ok(Instruction::BinOp { op, ty, src1, src2, dst })
     }
 
     fn parse_ty(x: &str) -> Option<Type> {
@@ -247,13 +1104,239 @@ impl CasmAssembler {
             "s" => Int16,
             "i" => Int32,
             "f" => Float,
+            "ub" => UInt8,
+            "us" => UInt16,
+            "ui" => UInt32,
+            "l" => Int64,
+            "d" => Double,
             _ => return None
         })
     }
 
-    fn set_label_value(&mut self, command: &str) {
-        let label_name = command[..command.len() - 1].to_string();
-        self.label_locations.insert(label_name, self.output_buffer.len());
+    fn set_label_value(&mut self, command: Token) -> Result<(), Diagnostic> {
+        let label_name = &command.text[..command.text.len() - 1];
+        if label_name.is_empty() || !label_name.chars().all(Self::is_label_character) {
+            return Err(self.error_at(command, format!(
+                "invalid label name `{}`: labels may only contain uppercase letters, digits and underscores",
+                label_name
+            )));
+        }
+        if let Some(&line) = self.label_definition_lines.get(label_name) {
+            return Err(self.error_at(command, format!(
+                "label `{}` is already defined on {}", label_name, self.describe_line(line)
+            )));
+        }
+
+        let address = match self.section {
+            Section::Code => self.output_buffer.len(),
+            Section::Data => RAM::STATIC_PTR_BIT as usize | self.data.len(),
+        };
+        self.label_locations.insert(label_name.to_owned(), address);
+        self.label_definition_lines.insert(label_name.to_owned(), self.line_number);
+        Ok(())
+    }
+
+    fn label_reference(&self, token: Token) -> Result<LabelReference, Diagnostic> {
+        if token.text.is_empty() || !token.text.chars().all(Self::is_label_character) {
+            return Err(self.error_at(token, format!("invalid label name `{}`", token.text)));
+        }
+
+        Ok(LabelReference {
+            name: token.text.to_owned(),
+            line: self.line_number,
+            columns: self.source_columns(token.span()),
+            notes: self.expansion_notes(),
+        })
+    }
+
+    /// Looks up the address of a label, recording a diagnostic if it is not defined
+    fn resolve_label(&mut self, label: &LabelReference) -> Option<usize> {
+        let address = self.label_locations.get(&label.name).copied();
+        if address.is_none() {
+            let mut diagnostic = self.diagnostic(
+                label.line,
+                label.columns.clone(),
+                format!("undefined label `{}`", label.name),
+            );
+            diagnostic.notes = label.notes.clone();
+            self.diagnostics.push(diagnostic);
+        }
+        address
+    }
+
+    /// Looks up the address of a label that code jumps to, recording a diagnostic if it is not
+    /// defined or labels data
+    fn resolve_code_label(&mut self, label: &LabelReference) -> Option<usize> {
+        let address = self.resolve_label(label)?;
+        if Self::is_data_address(address) {
+            let mut diagnostic = self.diagnostic(
+                label.line,
+                label.columns.clone(),
+                format!("`{}` labels data, not code", label.name),
+            );
+            diagnostic.notes = label.notes.clone();
+            self.diagnostics.push(diagnostic);
+            return None;
+        }
+        Some(address)
+    }
+
+    fn is_data_address(address: usize) -> bool {
+        address & RAM::STATIC_PTR_BIT as usize != 0
+    }
+
+    /// Emits the values of a data directive into the data section
+    fn parse_data_directive(&mut self, directive: Token, items: &mut Tokens) -> Result<(), Diagnostic> {
+        if self.section != Section::Data {
+            return Err(self.error_at(directive, format!(
+                "`{}` can only be used in the data section, after `.data`", directive.text
+            )));
+        }
+
+        match directive.text {
+            ".byte" | ".short" | ".int" => {
+                let ty = match directive.text {
+                    ".byte" => Type::Int8,
+                    ".short" => Type::Int16,
+                    _ => Type::UInt32,
+                };
+                loop {
+                    let value = self.expect_expression(items, "a value")?;
+                    let value = self.value_or_fixup(self.data.len(), Section::Data, ty, value, &format!("`{}`", directive.text))?;
+                    self.data.extend(&value.to_be_bytes()[4 - ty.size() as usize..]);
+
+                    // Values are separated by commas, or by whitespace where that is unambiguous
+                    match items.peek() {
+                        Some(token) if token.text.starts_with(',') => items.seek(token.start + 1),
+                        Some(_) => {}
+                        None => break,
+                    }
+                }
+            }
+            ".float" => {
+                let values = Self::split_list(items);
+                if values.is_empty() {
+                    return Err(self.end_of_line_error(items, "a value"));
+                }
+                for value in values {
+                    let float: f32 = value.text.parse().map_err(|_| self.error_at(
+                        value,
+                        format!("invalid float `{}`", value.text),
+                    ))?;
+                    self.data.extend(float.to_bits().to_be_bytes());
+                }
+            }
+            ".string" => {
+                let literal = items.rest().ok_or_else(|| self.end_of_line_error(items, "a string literal"))?;
+                let mut bytes = self.parse_string_literal(literal)?;
+                bytes.push(0);
+                self.data.extend(bytes);
+            }
+            ".zero" => {
+                let (token, count) = self.expect_count(items, directive)?;
+                self.expect_end(items)?;
+                self.reserve_data(token, count)?;
+            }
+            ".align" => {
+                let (token, alignment) = self.expect_count(items, directive)?;
+                self.expect_end(items)?;
+                if !alignment.is_power_of_two() {
+                    return Err(self.error_at(token, format!(
+                        "invalid alignment `{}`: expected a power of two", token.text
+                    )));
+                }
+                self.data_alignment = self.data_alignment.max(alignment);
+                let padding = self.data.len().next_multiple_of(alignment) - self.data.len();
+                self.reserve_data(token, padding)?;
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    /// Defines a constant: `.equ NAME value`. The value may use constants and labels defined on
+    /// earlier lines.
+    fn define_constant(&mut self, items: &mut Tokens) -> Result<(), Diagnostic> {
+        let name = self.expect_token(items, "a constant name")?;
+        if !Self::is_identifier(name.text) {
+            return Err(self.error_at(name, format!(
+                "invalid constant name `{}`: constant names may only contain uppercase letters, digits and underscores, and cannot start with a digit",
+                name.text
+            )));
+        }
+        if let Some(&line) = self.label_definition_lines.get(name.text) {
+            return Err(self.error_at(name, format!("`{}` is already defined on {}", name.text, self.describe_line(line))));
+        }
+        let (_, value) = self.expect_constant(items, "a value")?;
+        self.expect_end(items)?;
+
+        self.constants.insert(name.text.to_owned(), value);
+        self.label_definition_lines.insert(name.text.to_owned(), self.line_number);
+        Ok(())
+    }
+
+    /// Appends `count` zero bytes to the data section, which must stay within the static region
+    fn reserve_data(&mut self, token: Token, count: usize) -> Result<(), Diagnostic> {
+        if self.data.len().saturating_add(count) > RAM::STATIC_PTR_BIT as usize {
+            return Err(self.error_at(token, format!(
+                "the data section would grow past its limit of {} bytes", RAM::STATIC_PTR_BIT
+            )));
+        }
+        self.data.resize(self.data.len() + count, 0);
+        Ok(())
+    }
+
+    /// Parses a double-quoted string literal, which may contain the escapes `\\`, `\"`, `\n`,
+    /// `\r`, `\t`, `\0` and `\xNN`
+    fn parse_string_literal(&self, literal: Token) -> Result<Vec<u8>, Diagnostic> {
+        let at = |start: usize, end: usize| Token { text: &literal.text[start..end], start: literal.start + start };
+        if !literal.text.starts_with('"') {
+            return Err(self.error_at(literal, format!("expected a string literal, found `{}`", literal.text)));
+        }
+
+        let mut bytes = vec![];
+        let mut chars = literal.text.char_indices().skip(1);
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '"' => {
+                    let rest = literal.text[index + 1..].trim_start();
+                    if !rest.is_empty() {
+                        let start = literal.text.len() - rest.len();
+                        return Err(self.error_at(at(start, literal.text.len()), format!(
+                            "unexpected `{}` after string literal", rest
+                        )));
+                    }
+                    return Ok(bytes);
+                }
+                '\\' => {
+                    let Some((_, escape)) = chars.next() else { break };
+                    match escape {
+                        '\\' | '"' => bytes.push(escape as u8),
+                        'n' => bytes.push(b'\n'),
+                        'r' => bytes.push(b'\r'),
+                        't' => bytes.push(b'\t'),
+                        '0' => bytes.push(0),
+                        'x' => {
+                            let digits = literal.text.get(index + 2..index + 4).filter(|digits| digits.chars().all(|c| c.is_ascii_hexdigit()));
+                            let Some(digits) = digits else {
+                                let end = literal.text.len().min(index + 4);
+                                return Err(self.error_at(at(index, end), "invalid escape: expected `\\x` followed by two hex digits".to_owned()));
+                            };
+                            bytes.push(u8::from_str_radix(digits, 16).unwrap());
+                            chars.nth(1);
+                        }
+                        _ => {
+                            let end = index + 1 + escape.len_utf8();
+                            return Err(self.error_at(at(index, end), format!("unknown escape `\\{}`", escape)));
+                        }
+                    }
+                }
+                _ => bytes.extend(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            }
+        }
+
+        Err(self.error_at(literal, "unterminated string literal".to_owned()))
     }
 
     fn parse_location(location: &str) -> Option<Location> {
@@ -292,18 +1375,320 @@ impl CasmAssembler {
         })
     }
 
-    fn insert_labels(&mut self) -> Option<()> {
-        for (label_location, label_name) in &self.label_placeholder_locations {
-            let label_value = self.label_locations.get(label_name).expect(
-                format!("Could not find label {}", label_name.as_str()).as_str()
-            );
-            self.output_buffer[*label_location + 3] = *label_value as u8;
-            self.output_buffer[*label_location + 2] = (*label_value >> 8) as u8;
-            self.output_buffer[*label_location + 1] = (*label_value >> 16) as u8;
-            self.output_buffer[*label_location + 0] = (*label_value >> 24) as u8;
+    fn insert_labels(&mut self) {
+        for fixup in std::mem::take(&mut self.label_placeholder_locations) {
+            let value = match self.evaluate_fixup(&fixup) {
+                Ok((value, relocation)) => {
+                    self.relocations.extend(relocation);
+                    value
+                }
+                Err(diagnostic) => {
+                    self.diagnostics.push(diagnostic);
+                    continue;
+                }
+            };
+            let buffer = match fixup.section {
+                Section::Code => &mut self.output_buffer,
+                Section::Data => &mut self.data,
+            };
+
+            let size = fixup.ty.size() as usize;
+            buffer[fixup.location..fixup.location + size].copy_from_slice(&value.to_be_bytes()[4 - size..]);
+        }
+    }
+
+    /// Computes the value of a fixup now that every label is known. In an object file, the value
+    /// may need a relocation for the linker to fill it in, and is only a placeholder.
+    fn evaluate_fixup(&self, fixup: &LabelFixup) -> Result<(u32, Option<Relocation>), Diagnostic> {
+        let error = |columns, message| {
+            let mut diagnostic = self.diagnostic(fixup.line, columns, message);
+            diagnostic.notes = fixup.notes.clone();
+            diagnostic
+        };
+        let lookup = |name: &str, columns| self.symbol_value(name).ok_or_else(|| ExpressionError {
+            columns,
+            message: format!("undefined symbol `{}`", name),
+        });
+
+        let value = fixup.expression.evaluate(&lookup).map_err(|e| error(e.columns, e.message))?;
+        let relocation = |target| Relocation {
+            section: fixup.section.in_object(),
+            location: fixup.location as u32,
+            size: fixup.ty.size() as u8,
+            target,
+            addend: value.offset,
+            relative_to: fixup.relative_to.map(|base| base as u32),
+        };
+        let target = match &value.base {
+            Some(Base::External(name)) => return Ok((0, Some(relocation(RelocationTarget::Symbol(name.clone()))))),
+            Some(Base::Section(section)) => Some(RelocationTarget::Section(section.in_object())),
+            None => None,
+        };
+
+        let offset = Self::integer_in_range(value.offset, fixup.ty).map_err(|range| error(
+            fixup.expression.columns.clone(),
+            format!("value `{}` is out of range for {} (expected {})", fixup.text, fixup.context, range),
+        ))?;
+        match fixup.relative_to {
+            Some(_) if Self::is_data_address(offset as usize) => Err(error(
+                fixup.expression.columns.clone(),
+                format!("`{}` is a data address, not code", fixup.text),
+            )),
+            // Offsets between places in the same code stay the same wherever the code is placed,
+            // but offsets to absolute addresses do not
+            Some(_) if self.object && value.base.is_none() => Ok((0, Some(relocation(RelocationTarget::Absolute)))),
+            Some(base) => Ok((offset.wrapping_sub(base as u32), None)),
+            None => Ok((offset, target.map(relocation))),
+        }
+    }
+
+    /// Checks the labels named by `.global`, returning their names
+    fn exported_symbols(&mut self) -> Vec<String> {
+        let mut exports: Vec<String> = vec![];
+        for label in std::mem::take(&mut self.exports) {
+            if self.resolve_label(&label).is_some() && !exports.contains(&label.name) {
+                exports.push(label.name);
+            }
+        }
+        exports
+    }
+
+    /// Declares symbols that other object files define: `.extern NAME1, NAME2, ...`
+    fn declare_externs(&mut self, directive: Token, items: &mut Tokens) -> Result<(), Diagnostic> {
+        if !self.object {
+            return Err(self.error_at(directive, "`.extern` can only be used when assembling an object file".to_owned()));
+        }
+        let names = Self::split_list(items);
+        if names.is_empty() {
+            return Err(self.end_of_line_error(items, "a symbol name"));
+        }
+
+        for name in names {
+            if !Self::is_identifier(name.text) {
+                return Err(self.error_at(name, format!(
+                    "invalid symbol name `{}`: symbol names may only contain uppercase letters, digits and underscores, and cannot start with a digit",
+                    name.text
+                )));
+            }
+            if let Some(&line) = self.label_definition_lines.get(name.text) {
+                return Err(self.error_at(name, format!("`{}` is already defined on {}", name.text, self.describe_line(line))));
+            }
+            self.externs.push(name.text.to_owned());
+            self.label_definition_lines.insert(name.text.to_owned(), self.line_number);
+        }
+        Ok(())
+    }
+
+    /// Splices a file into the source after the line being assembled: `.include "path"`. The
+    /// path is relative to the directory of the file that includes it.
+    fn include_file(&mut self, directive: Token, items: &mut Tokens) -> Result<(), Diagnostic> {
+        if !self.expansion_sites.is_empty() {
+            return Err(self.error_at(directive, "`.include` cannot be used inside a macro".to_owned()));
+        }
+        let literal = items.rest().ok_or_else(|| self.end_of_line_error(items, "a file name"))?;
+        let name = String::from_utf8(self.parse_string_literal(literal)?)
+            .map_err(|_| self.error_at(literal, "file name is not valid UTF-8".to_owned()))?;
+
+        let origin = &self.line_origins[self.line_number - 1];
+        let directory = Path::new(&self.files[origin.file]).parent().unwrap_or(Path::new(""));
+        let path = directory.join(&name);
+        let source = std::fs::read_to_string(&path).map_err(|error| self.error_at(literal, format!(
+            "cannot read `{}`: {}", path.display(), error
+        )))?;
+
+        // A file that includes itself, directly or not, would be spliced in forever
+        let canonical = std::fs::canonicalize(&path).ok();
+        let mut including = Some(self.line_number);
+        while let Some(line) = including {
+            let origin = &self.line_origins[line - 1];
+            if canonical.is_some() && std::fs::canonicalize(&self.files[origin.file]).ok() == canonical {
+                return Err(self.error_at(literal, format!("`{}` includes itself", path.display())));
+            }
+            including = origin.included_by;
+        }
+
+        let file = self.files.len();
+        self.files.push(path.display().to_string());
+        let top_level_line = origin.top_level_line;
+        let lines = Self::split_lines(&source);
+        let origins: Vec<LineOrigin> = (1..=lines.len())
+            .map(|line| LineOrigin { file, line, top_level_line, included_by: Some(self.line_number) })
+            .collect();
+        self.source_lines.splice(self.line_number..self.line_number, lines);
+        self.line_origins.splice(self.line_number..self.line_number, origins);
+        Ok(())
+    }
+
+    /// Starts the definition of a macro: `.macro NAME param1, param2, ...`. The following lines
+    /// up to `.endm` are its body, in which `\param` stands for an argument.
+    fn begin_macro(&mut self, items: &mut Tokens) -> Result<(), Diagnostic> {
+        let name = self.expect_token(items, "a macro name")?;
+        let mut definition = Macro {
+            name: name.text.to_owned(),
+            parameters: vec![],
+            body: vec![],
+            line: self.line_number,
+            columns: name.span(),
+            local_labels: Default::default(),
+            valid: false,
+        };
+
+        let mut error = None;
+        if !Self::is_identifier(name.text) {
+            error = Some(self.error_at(name, format!(
+                "invalid macro name `{}`: macro names may only contain uppercase letters, digits and underscores, and cannot start with a digit",
+                name.text
+            )));
+        } else if let Some(existing) = self.macros.get(name.text) {
+            error = Some(self.error_at(name, format!(
+                "macro `{}` is already defined on {}", name.text, self.describe_line(existing.line)
+            )));
+        }
+        for parameter in Self::split_list(items) {
+            if error.is_some() {
+                break;
+            }
+            if !parameter.text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                || !parameter.text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                error = Some(self.error_at(parameter, format!(
+                    "invalid parameter name `{}`: parameter names may only contain letters, digits and underscores, and cannot start with a digit",
+                    parameter.text
+                )));
+            } else if definition.parameters.iter().any(|existing| existing == parameter.text) {
+                error = Some(self.error_at(parameter, format!("duplicate parameter `{}`", parameter.text)));
+            } else {
+                definition.parameters.push(parameter.text.to_owned());
+            }
+        }
+
+        // The body is read even if the definition is invalid, so that it is not assembled as code
+        definition.valid = error.is_none();
+        self.macro_being_defined = Some(definition);
+        error.map_or(Ok(()), Err)
+    }
+
+    /// Adds a line to the body of the macro being defined, or ends its definition at `.endm`
+    fn record_macro_line(&mut self, items: &mut Tokens) -> Result<(), Diagnostic> {
+        let first = items.peek().unwrap();
+        match first.text {
+            ".endm" => {
+                items.next();
+                let definition = self.macro_being_defined.take().unwrap();
+                if Self::is_identifier(&definition.name) && !self.macros.contains_key(&definition.name) {
+                    self.macros.insert(definition.name.clone(), definition);
+                }
+                self.expect_end(items)
+            }
+            ".macro" => {
+                self.macro_being_defined.as_mut().unwrap().valid = false;
+                Err(self.error_at(first, "macros cannot be defined inside other macros".to_owned()))
+            }
+            _ => {
+                let line = &self.source_lines[self.line_number - 1];
+                let code = strip_comment(line);
+                let definition = self.macro_being_defined.as_ref().unwrap();
+                // Backslashes in string and character literals are escapes if they do not name a parameter
+                let unknown_parameter = macros::parameter_references(code).into_iter()
+                    .filter(|(columns, _)| !ends_in_literal(&code[..columns.start]))
+                    .find(|(_, name)| !definition.parameters.iter().any(|parameter| parameter == name));
+                let error = unknown_parameter.map(|(columns, name)| self.diagnostic(
+                    self.line_number,
+                    columns,
+                    format!("macro `{}` has no parameter `{}`", definition.name, name),
+                ));
+
+                let definition = self.macro_being_defined.as_mut().unwrap();
+                while let Some(label) = items.next().and_then(|token| token.text.strip_suffix(':')) {
+                    definition.local_labels.insert(label.to_owned());
+                }
+                definition.body.push(self.line_number);
+                if let Some(error) = error {
+                    definition.valid = false;
+                    return Err(error);
+                }
+                Ok(())
+            }
         }
+    }
+
+    /// Assembles the body of a macro in place of its invocation, `NAME arg1, arg2, ...`
+    fn expand_macro(&mut self, name: Token, items: &mut Tokens) -> Result<(), Diagnostic> {
+        let definition = self.macros[name.text].clone();
+        let arguments: Vec<String> = Self::split_list(items).iter()
+            .map(|argument| argument.text.to_owned())
+            .collect();
+        if arguments.len() != definition.parameters.len() {
+            return Err(self.error_at(name, format!(
+                "macro `{}` takes {} argument{}, found {}",
+                name.text,
+                definition.parameters.len(),
+                if definition.parameters.len() == 1 { "" } else { "s" },
+                arguments.len()
+            )));
+        }
+        if !definition.valid {
+            return Ok(());
+        }
+        if self.expansion_sites.len() >= Self::MAX_MACRO_DEPTH {
+            // Only the outermost invocation is noted, rather than every level of the nesting
+            let mut diagnostic = self.error_at(name, format!(
+                "macro invocations are nested more than {} levels deep; does `{}` invoke itself?",
+                Self::MAX_MACRO_DEPTH, name.text
+            ));
+            diagnostic.notes.drain(..diagnostic.notes.len() - 1);
+            return Err(diagnostic);
+        }
+
+        self.expansion_count += 1;
+        let expansion = self.expansion_count;
+        let site = self.diagnostic(
+            self.line_number,
+            self.source_columns(name.start..items.end.max(name.start + name.text.len())),
+            format!("in this expansion of macro `{}`", name.text),
+        );
+
+        let line_number = self.line_number;
+        let column_origins = std::mem::take(&mut self.column_origins);
+        self.expansion_sites.push(site);
+        for &body_line in &definition.body {
+            let expanded = definition.expand_line(&self.source_lines[body_line - 1], &arguments, expansion);
+            self.line_number = body_line;
+            self.column_origins = expanded.origins;
+            self.assemble_line(&expanded.text);
+        }
+        self.expansion_sites.pop();
+        self.line_number = line_number;
+        self.column_origins = column_origins;
+
+        Ok(())
+    }
+
+    /// Splits the rest of a line into the items of a list, like macro arguments or the values of a
+    /// data directive, which are separated by commas or whitespace
+    fn split_list<'a>(items: &mut Tokens<'a>) -> Vec<Token<'a>> {
+        let mut arguments = vec![];
+        while let Some(token) = items.next() {
+            let mut start = 0;
+            for piece in token.text.split(',') {
+                if !piece.is_empty() {
+                    arguments.push(Token { text: piece, start: token.start + start });
+                }
+                start += piece.len() + 1;
+            }
+        }
+        arguments
+    }
+
+    /// Macro and constant names follow the rules for labels, but cannot start with a digit
+    fn is_identifier(name: &str) -> bool {
+        !name.is_empty() && !name.starts_with(|c: char| c.is_numeric()) && name.chars().all(Self::is_label_character)
+    }
 
-        Some(())
+    /// Host function names are made of lowercase letters, digits and underscores, and start with a
+    /// letter so that they cannot be mistaken for syscall numbers
+    fn is_host_function_name(name: &str) -> bool {
+        name.starts_with(|c: char| c.is_ascii_lowercase())
+            && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
     }
 
     fn is_label_character(c: char) -> bool {
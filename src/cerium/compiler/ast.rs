@@ -0,0 +1,195 @@
+use crate::cerium::instruction::instruction_parts::Type;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+/// Where a piece of syntax appears in the source
+#[derive(Clone, Debug)]
+pub struct Span {
+    /// The 1-based line number
+    pub line: usize,
+    /// The byte range within the line
+    pub columns: Range<usize>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Ty {
+    Void,
+    Bool,
+    Char,
+    Short,
+    Int,
+    Float,
+    Pointer(Box<Ty>),
+}
+
+impl Ty {
+    /// The VM type that values of this type are operated on as
+    pub fn vm_type(&self) -> Type {
+        match self {
+            Ty::Bool => Type::Int8,
+            Ty::Char | Ty::Short => Type::Int16,
+            Ty::Int | Ty::Pointer(_) | Ty::Void => Type::Int32,
+            Ty::Float => Type::Float,
+        }
+    }
+
+    /// The size of a value of this type in memory
+    pub fn size(&self) -> u32 {
+        self.vm_type().size()
+    }
+
+    pub fn is_arithmetic(&self) -> bool {
+        matches!(self, Ty::Bool | Ty::Char | Ty::Short | Ty::Int | Ty::Float)
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(self, Ty::Bool | Ty::Char | Ty::Short | Ty::Int)
+    }
+
+    pub fn pointee(&self) -> Option<&Ty> {
+        match self {
+            Ty::Pointer(pointee) => Some(pointee),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Ty {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Ty::Void => f.write_str("void"),
+            Ty::Bool => f.write_str("bool"),
+            Ty::Char => f.write_str("char"),
+            Ty::Short => f.write_str("short"),
+            Ty::Int => f.write_str("int"),
+            Ty::Float => f.write_str("float"),
+            Ty::Pointer(pointee) => write!(f, "{}*", pointee),
+        }
+    }
+}
+
+pub struct Function {
+    pub name: String,
+    pub span: Span,
+    pub return_ty: Ty,
+    pub params: Vec<Param>,
+    pub body: Vec<Stmt>,
+}
+
+pub struct Param {
+    pub ty: Ty,
+    pub name: String,
+    pub span: Span,
+}
+
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+pub enum StmtKind {
+    Block(Vec<Stmt>),
+    Declare { ty: Ty, name: String, init: Option<Expr> },
+    If { condition: Expr, then: Box<Stmt>, otherwise: Option<Box<Stmt>> },
+    While { condition: Expr, body: Box<Stmt> },
+    Break,
+    Continue,
+    Return(Option<Expr>),
+    Del(Expr),
+    Expr(Expr),
+}
+
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+pub enum ExprKind {
+    Integer(u32),
+    Float(f32),
+    Char(u16),
+    Bool(bool),
+    Null,
+    Variable(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Assign(Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    Cast(Ty, Box<Expr>),
+    /// Allocates a value of the given type, or an array of them if there is a length
+    New(Ty, Option<Box<Expr>>),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    /// Logical negation, `!`
+    Not,
+    /// Bitwise negation, `~`
+    BitNot,
+    Deref,
+    AddressOf,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    pub fn symbol(&self) -> &'static str {
+        use BinaryOp::*;
+        match self {
+            Add => "+",
+            Sub => "-",
+            Mul => "*",
+            Div => "/",
+            Mod => "%",
+            BitAnd => "&",
+            BitOr => "|",
+            BitXor => "^",
+            Shl => "<<",
+            Shr => ">>",
+            Lt => "<",
+            Le => "<=",
+            Gt => ">",
+            Ge => ">=",
+            Eq => "==",
+            Ne => "!=",
+            And => "&&",
+            Or => "||",
+        }
+    }
+
+    /// Operators from the loosest binding precedence level to the tightest
+    pub const PRECEDENCE: [&'static [BinaryOp]; 10] = [
+        &[BinaryOp::Or],
+        &[BinaryOp::And],
+        &[BinaryOp::BitOr],
+        &[BinaryOp::BitXor],
+        &[BinaryOp::BitAnd],
+        &[BinaryOp::Eq, BinaryOp::Ne],
+        &[BinaryOp::Lt, BinaryOp::Le, BinaryOp::Gt, BinaryOp::Ge],
+        &[BinaryOp::Shl, BinaryOp::Shr],
+        &[BinaryOp::Add, BinaryOp::Sub],
+        &[BinaryOp::Mul, BinaryOp::Div, BinaryOp::Mod],
+    ];
+}
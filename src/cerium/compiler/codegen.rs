@@ -0,0 +1,913 @@
+use super::ast::*;
+use super::CompileError;
+use crate::cerium::instruction::instruction_parts::{BinOp, Condition, Immediate, JumpTarget, Location, Register, Type, UnOp};
+use crate::cerium::instruction::Instruction;
+use crate::cerium::program::{LineInfo, Program, Symbol};
+use std::collections::{HashMap, HashSet};
+
+const R1: Location = Location { register: Register::R1, indirect: false };
+const R2: Location = Location { register: Register::R2, indirect: false };
+const R3: Location = Location { register: Register::R3, indirect: false };
+const SP: Location = Location { register: Register::SP, indirect: false };
+const FP: Location = Location { register: Register::R7, indirect: false };
+const AT_R1: Location = Location { register: Register::R1, indirect: true };
+const AT_R3: Location = Location { register: Register::R3, indirect: true };
+
+/// The bytes between a function's arguments and its frame pointer: the return address and the
+/// caller's frame pointer
+const FRAME_HEADER_SIZE: i32 = 8;
+
+type Label = usize;
+
+enum Item {
+    Instruction {
+        instruction: Instruction,
+        /// The label that the direct target of a `JmpTo` or `CallTo` is patched to point at
+        target: Option<Label>,
+        line: usize,
+    },
+    Label(Label),
+}
+
+#[derive(Clone)]
+struct Variable {
+    ty: Ty,
+    /// The offset of the variable from the frame pointer
+    offset: i32,
+}
+
+#[derive(Clone)]
+struct Signature {
+    return_ty: Ty,
+    params: Vec<Ty>,
+    label: Label,
+}
+
+struct Loop {
+    start: Label,
+    end: Label,
+}
+
+/// A literal operand that can be encoded as an immediate
+#[derive(Copy, Clone)]
+enum Constant {
+    Int(i32),
+    Float(f32),
+}
+
+impl Constant {
+    fn of(expr: &Expr) -> Option<Constant> {
+        match expr.kind {
+            ExprKind::Integer(value) => Some(Constant::Int(value as i32)),
+            ExprKind::Float(value) => Some(Constant::Float(value)),
+            ExprKind::Unary(UnaryOp::Neg, ref operand) => match Constant::of(operand)? {
+                Constant::Int(value) => Some(Constant::Int(value.wrapping_neg())),
+                Constant::Float(value) => Some(Constant::Float(-value)),
+            },
+            _ => None,
+        }
+    }
+
+    fn ty(&self) -> Ty {
+        match self {
+            Constant::Int(_) => Ty::Int,
+            Constant::Float(_) => Ty::Float,
+        }
+    }
+
+    /// Encodes the constant as an immediate operand of an instruction of type `ty`
+    fn immediate(&self, ty: Type) -> Immediate {
+        match (*self, ty) {
+            (Constant::Int(value), Type::Float) if i16::try_from(value).is_ok() => Immediate::from_i32(value),
+            (Constant::Int(value), Type::Float) => Immediate::Imm32((value as f32).to_bits()),
+            (Constant::Float(value), _) if (value as i16 as f32).to_bits() == value.to_bits() => {
+                Immediate::from_i32(value as i16 as i32)
+            }
+            (Constant::Float(value), _) => Immediate::Imm32(value.to_bits()),
+            (Constant::Int(value), _) => Immediate::from_i32(value),
+        }
+    }
+}
+
+pub struct CodeGenerator {
+    items: Vec<Item>,
+    label_count: usize,
+    functions: HashMap<String, Signature>,
+    scopes: Vec<HashMap<String, Variable>>,
+    loops: Vec<Loop>,
+    /// The size of the local variables of the function being compiled
+    frame_size: u32,
+    return_ty: Ty,
+    return_label: Label,
+    /// The source line of the statement being compiled
+    line: usize,
+}
+
+impl CodeGenerator {
+    pub fn generate(functions: &[Function]) -> Result<Program, CompileError> {
+        let mut generator = CodeGenerator {
+            items: vec![],
+            label_count: 0,
+            functions: HashMap::new(),
+            scopes: vec![],
+            loops: vec![],
+            frame_size: 0,
+            return_ty: Ty::Void,
+            return_label: 0,
+            line: 1,
+        };
+
+        for function in functions {
+            if matches!(function.name.as_str(), "input" | "output") {
+                return Err(CompileError {
+                    span: function.span.clone(),
+                    message: format!("`{}` is a builtin function", function.name),
+                });
+            }
+            if generator.functions.contains_key(&function.name) {
+                return Err(CompileError {
+                    span: function.span.clone(),
+                    message: format!("function `{}` is already defined", function.name),
+                });
+            }
+
+            let label = generator.new_label();
+            generator.functions.insert(function.name.clone(), Signature {
+                return_ty: function.return_ty.clone(),
+                params: function.params.iter().map(|param| param.ty.clone()).collect(),
+                label,
+            });
+        }
+
+        let Some(main) = functions.iter().find(|function| function.name == "main") else {
+            return Err(CompileError {
+                span: Span { line: 1, columns: 0..0 },
+                message: "the program has no `main` function".to_owned(),
+            });
+        };
+        if !main.params.is_empty() {
+            return Err(CompileError {
+                span: main.span.clone(),
+                message: "`main` cannot take parameters".to_owned(),
+            });
+        }
+
+        // The entry point calls main and halts once it returns
+        generator.line = main.span.line;
+        let main_label = generator.functions["main"].label;
+        generator.emit_to(Instruction::CallTo(JumpTarget::Relative(0)), main_label);
+        generator.emit(Instruction::Halt);
+
+        for function in functions {
+            generator.function(function)?;
+        }
+
+        Ok(generator.finish(functions))
+    }
+
+    fn new_label(&mut self) -> Label {
+        self.label_count += 1;
+        self.label_count - 1
+    }
+
+    fn place_label(&mut self, label: Label) {
+        self.items.push(Item::Label(label));
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.items.push(Item::Instruction { instruction, target: None, line: self.line });
+    }
+
+    /// Emits a `JmpTo` or `CallTo` whose target is patched to point at `label`
+    fn emit_to(&mut self, instruction: Instruction, label: Label) {
+        self.items.push(Item::Instruction { instruction, target: Some(label), line: self.line });
+    }
+
+    fn jump(&mut self, label: Label) {
+        self.jump_if(Type::Int32, Condition::ALWAYS, label);
+    }
+
+    /// Jumps to `label` if `r1` compares to zero with `cnd`
+    fn jump_if(&mut self, ty: Type, cnd: Condition, label: Label) {
+        self.emit_to(Instruction::JmpTo { ty, src: R1, target: JumpTarget::Relative(0), cnd, imm: None }, label);
+    }
+
+    fn mov(&mut self, src_ty: Type, src: Location, dst_ty: Type, dst: Location) {
+        self.emit(Instruction::Mov { src_ty, dst_ty, src, dst });
+    }
+
+    fn binop_imm(&mut self, op: BinOp, ty: Type, src: Location, imm: i32, dst: Location) {
+        self.emit(Instruction::BinOpImm { op, ty, src, imm: Immediate::from_i32(imm), dst });
+    }
+
+    /// Lays out the code, patches jump targets, and builds the program
+    fn finish(self, functions: &[Function]) -> Program {
+        let mut addresses = vec![0u32; self.label_count];
+        let mut address = 0u32;
+        for item in &self.items {
+            match item {
+                Item::Label(label) => addresses[*label] = address,
+                Item::Instruction { instruction, .. } => {
+                    instruction.output_to(|_| address += 1);
+                }
+            }
+        }
+
+        let mut code = vec![];
+        let mut line_info: Vec<LineInfo> = vec![];
+        for item in &self.items {
+            let Item::Instruction { instruction, target, line } = item else { continue };
+
+            let mut instruction = *instruction;
+            if let Some(label) = *target {
+                let offset = addresses[label].wrapping_sub(code.len() as u32) as i32;
+                match &mut instruction {
+                    Instruction::JmpTo { target, .. } | Instruction::CallTo(target) => {
+                        *target = JumpTarget::Relative(offset);
+                    }
+                    _ => unreachable!("only direct jumps and calls have label targets"),
+                }
+            }
+
+            if line_info.last().is_none_or(|info| info.line != *line as u32) {
+                line_info.push(LineInfo { address: code.len() as u32, line: *line as u32 });
+            }
+            instruction.output_to(|byte| code.push(byte));
+        }
+
+        // Function names become assembler-style labels
+        let mut names = HashSet::new();
+        let mut symbols = vec![Symbol { name: "_START".to_owned(), address: 0 }];
+        names.insert("_START".to_owned());
+        for function in functions {
+            let base = function.name.to_uppercase();
+            let mut name = base.clone();
+            let mut suffix = 1;
+            while !names.insert(name.clone()) {
+                name = format!("{}_{}", base, suffix);
+                suffix += 1;
+            }
+            symbols.push(Symbol { name, address: addresses[self.functions[&function.name].label] });
+        }
+        symbols.sort_by(|a, b| a.address.cmp(&b.address).then_with(|| a.name.cmp(&b.name)));
+
+        Program {
+            entry_point: 0,
+            code,
+            symbols,
+            line_info,
+            ..Default::default()
+        }
+    }
+
+    fn function(&mut self, function: &Function) -> Result<(), CompileError> {
+        self.line = function.span.line;
+        let signature = self.functions[&function.name].clone();
+
+        let args_size: u32 = function.params.iter().map(|param| param.ty.size()).sum();
+        let mut offset = -FRAME_HEADER_SIZE - args_size as i32;
+        let mut params = HashMap::new();
+        for param in &function.params {
+            if param.ty == Ty::Void {
+                return Err(CompileError { span: param.span.clone(), message: "parameters cannot have type `void`".to_owned() });
+            }
+            let variable = Variable { ty: param.ty.clone(), offset };
+            if params.insert(param.name.clone(), variable).is_some() {
+                return Err(CompileError {
+                    span: param.span.clone(),
+                    message: format!("parameter `{}` is declared more than once", param.name),
+                });
+            }
+            offset += param.ty.size() as i32;
+        }
+
+        self.scopes = vec![params];
+        self.loops.clear();
+        self.frame_size = 0;
+        self.return_ty = function.return_ty.clone();
+        self.return_label = self.new_label();
+
+        // The body is generated first so that the prologue knows how much space the locals need
+        let header = std::mem::take(&mut self.items);
+        for statement in &function.body {
+            self.statement(statement)?;
+        }
+        let body = std::mem::replace(&mut self.items, header);
+
+        self.line = function.span.line;
+        self.place_label(signature.label);
+        self.emit(Instruction::Push(Type::Int32, FP));
+        self.mov(Type::Int32, SP, Type::Int32, FP);
+        if self.frame_size > 0 {
+            self.binop_imm(BinOp::ADD, Type::Int32, SP, self.frame_size as i32, SP);
+        }
+        self.items.extend(body);
+
+        self.place_label(self.return_label);
+        self.mov(Type::Int32, FP, Type::Int32, SP);
+        self.emit(Instruction::Pop(Type::Int32, FP));
+        self.emit(Instruction::Ret);
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str, span: &Span) -> Result<Variable, CompileError> {
+        self.scopes.iter().rev()
+            .find_map(|scope| scope.get(name))
+            .cloned()
+            .ok_or_else(|| CompileError { span: span.clone(), message: format!("unknown variable `{}`", name) })
+    }
+
+    /// Reserves a slot for a local variable, returning its offset from the frame pointer
+    fn allocate_local(&mut self, ty: &Ty) -> i32 {
+        let size = ty.size();
+        let offset = self.frame_size.next_multiple_of(size);
+        self.frame_size = offset + size;
+        offset as i32
+    }
+
+    fn statement(&mut self, statement: &Stmt) -> Result<(), CompileError> {
+        self.line = statement.span.line;
+
+        match &statement.kind {
+            StmtKind::Block(statements) => {
+                self.scopes.push(HashMap::new());
+                for statement in statements {
+                    self.statement(statement)?;
+                }
+                self.scopes.pop();
+            }
+            StmtKind::Declare { ty, name, init } => {
+                if *ty == Ty::Void {
+                    return Err(CompileError {
+                        span: statement.span.clone(),
+                        message: "variables cannot have type `void`".to_owned(),
+                    });
+                }
+                if self.scopes.last().unwrap().contains_key(name) {
+                    return Err(CompileError {
+                        span: statement.span.clone(),
+                        message: format!("`{}` is already declared in this scope", name),
+                    });
+                }
+
+                // Variables without an initializer start out as zero
+                match init {
+                    Some(init) => {
+                        let init_ty = self.expression(init)?;
+                        self.convert(&init_ty, ty, &init.span)?;
+                    }
+                    None => self.emit(Instruction::Lod32(R1, 0)),
+                }
+
+                let offset = self.allocate_local(ty);
+                self.binop_imm(BinOp::ADD, Type::Int32, FP, offset, R3);
+                self.mov(ty.vm_type(), R1, ty.vm_type(), AT_R3);
+                self.scopes.last_mut().unwrap().insert(name.clone(), Variable { ty: ty.clone(), offset });
+            }
+            StmtKind::If { condition, then, otherwise } => {
+                let else_label = self.new_label();
+                self.condition(condition)?;
+                self.jump_if(Type::Int8, Condition::EQ, else_label);
+                self.statement(then)?;
+
+                match otherwise {
+                    Some(otherwise) => {
+                        let end_label = self.new_label();
+                        self.jump(end_label);
+                        self.place_label(else_label);
+                        self.statement(otherwise)?;
+                        self.place_label(end_label);
+                    }
+                    None => self.place_label(else_label),
+                }
+            }
+            StmtKind::While { condition, body } => {
+                let start = self.new_label();
+                let end = self.new_label();
+
+                self.place_label(start);
+                self.condition(condition)?;
+                self.jump_if(Type::Int8, Condition::EQ, end);
+
+                self.loops.push(Loop { start, end });
+                self.statement(body)?;
+                self.loops.pop();
+
+                self.jump(start);
+                self.place_label(end);
+            }
+            StmtKind::Break | StmtKind::Continue => {
+                let is_break = matches!(statement.kind, StmtKind::Break);
+                let Some(innermost) = self.loops.last() else {
+                    return Err(CompileError {
+                        span: statement.span.clone(),
+                        message: format!("`{}` outside of a loop", if is_break { "break" } else { "continue" }),
+                    });
+                };
+                let target = if is_break { innermost.end } else { innermost.start };
+                self.jump(target);
+            }
+            StmtKind::Return(value) => {
+                match (value, self.return_ty.clone()) {
+                    (Some(value), Ty::Void) => return Err(CompileError {
+                        span: value.span.clone(),
+                        message: "a `void` function cannot return a value".to_owned(),
+                    }),
+                    (None, Ty::Void) => {}
+                    (None, return_ty) => return Err(CompileError {
+                        span: statement.span.clone(),
+                        message: format!("expected a return value of type `{}`", return_ty),
+                    }),
+                    (Some(value), return_ty) => {
+                        let ty = self.expression(value)?;
+                        self.convert(&ty, &return_ty, &value.span)?;
+                    }
+                }
+                self.jump(self.return_label);
+            }
+            StmtKind::Del(pointer) => {
+                let ty = self.expression(pointer)?;
+                if ty.pointee().is_none() {
+                    return Err(CompileError {
+                        span: pointer.span.clone(),
+                        message: format!("expected a pointer, found `{}`", ty),
+                    });
+                }
+                self.emit(Instruction::Del { src: R1 });
+            }
+            StmtKind::Expr(expr) => {
+                self.expression(expr)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Evaluates a condition into a `bool` in `r1`
+    fn condition(&mut self, condition: &Expr) -> Result<(), CompileError> {
+        let ty = self.expression(condition)?;
+        self.convert_to_bool(&ty, &condition.span)
+    }
+
+    fn convert_to_bool(&mut self, ty: &Ty, span: &Span) -> Result<(), CompileError> {
+        match ty {
+            Ty::Bool => Ok(()),
+            Ty::Void => Err(CompileError { span: span.clone(), message: "expected a condition, found `void`".to_owned() }),
+            _ => {
+                self.emit(Instruction::Cmp { ty: ty.vm_type(), src: R1, dst: R1, cnd: Condition::NE });
+                Ok(())
+            }
+        }
+    }
+
+    /// Implicitly converts the value in `r1` from one type to another
+    fn convert(&mut self, from: &Ty, to: &Ty, span: &Span) -> Result<(), CompileError> {
+        if from == to {
+            return Ok(());
+        }
+        if from.is_arithmetic() && to.is_arithmetic() {
+            self.convert_arithmetic(from.vm_type(), to);
+            return Ok(());
+        }
+        // `void*` converts to and from any other pointer
+        if let (Some(from_pointee), Some(to_pointee)) = (from.pointee(), to.pointee()) {
+            if *from_pointee == Ty::Void || *to_pointee == Ty::Void {
+                return Ok(());
+            }
+        }
+
+        Err(CompileError { span: span.clone(), message: format!("expected `{}`, found `{}`", to, from) })
+    }
+
+    fn convert_arithmetic(&mut self, from: Type, to: &Ty) {
+        if *to == Ty::Bool {
+            self.emit(Instruction::Cmp { ty: from, src: R1, dst: R1, cnd: Condition::NE });
+        } else if from != to.vm_type() {
+            self.mov(from, R1, to.vm_type(), R1);
+        }
+    }
+
+    /// Converts an integer in `r1` to an `int`, for use as an index or length
+    fn convert_to_index(&mut self, ty: &Ty, span: &Span, what: &str) -> Result<(), CompileError> {
+        if !ty.is_integer() {
+            return Err(CompileError { span: span.clone(), message: format!("expected an integer {}, found `{}`", what, ty) });
+        }
+        self.convert(ty, &Ty::Int, span)
+    }
+
+    /// The type that arithmetic between two values is done in
+    fn common_type(lhs: &Ty, rhs: &Ty) -> Ty {
+        if *lhs == Ty::Float || *rhs == Ty::Float { Ty::Float } else { Ty::Int }
+    }
+
+    /// Evaluates an expression into `r1`, returning its type
+    fn expression(&mut self, expr: &Expr) -> Result<Ty, CompileError> {
+        match &expr.kind {
+            ExprKind::Integer(value) => {
+                self.emit(Instruction::Lod32(R1, *value));
+                Ok(Ty::Int)
+            }
+            ExprKind::Float(value) => {
+                self.emit(Instruction::Lod32(R1, value.to_bits()));
+                Ok(Ty::Float)
+            }
+            ExprKind::Char(value) => {
+                self.emit(Instruction::Lod16(R1, *value));
+                Ok(Ty::Char)
+            }
+            ExprKind::Bool(value) => {
+                self.emit(Instruction::Lod8(R1, *value as u8));
+                Ok(Ty::Bool)
+            }
+            ExprKind::Null => {
+                self.emit(Instruction::Lod32(R1, 0));
+                Ok(Ty::Pointer(Box::new(Ty::Void)))
+            }
+            ExprKind::Variable(_) | ExprKind::Index(..) | ExprKind::Unary(UnaryOp::Deref, _) => {
+                let ty = self.address(expr)?;
+                self.mov(ty.vm_type(), AT_R1, ty.vm_type(), R1);
+                Ok(ty)
+            }
+            ExprKind::Unary(UnaryOp::AddressOf, operand) => {
+                if !Self::is_lvalue(operand) {
+                    return Err(CompileError {
+                        span: operand.span.clone(),
+                        message: "cannot take the address of this expression".to_owned(),
+                    });
+                }
+                let ty = self.address(operand)?;
+                Ok(Ty::Pointer(Box::new(ty)))
+            }
+            ExprKind::Unary(op, operand) => self.unary(*op, operand, expr),
+            ExprKind::Binary(op, lhs, rhs) => self.binary(*op, lhs, rhs, &expr.span),
+            ExprKind::Assign(lhs, rhs) => self.assign(lhs, rhs),
+            ExprKind::Call(name, args) => self.call(name, args, &expr.span),
+            ExprKind::Cast(ty, operand) => {
+                let from = self.expression(operand)?;
+                self.cast(&from, ty, &expr.span)?;
+                Ok(ty.clone())
+            }
+            ExprKind::New(ty, length) => {
+                if *ty == Ty::Void {
+                    return Err(CompileError { span: expr.span.clone(), message: "cannot allocate `void`".to_owned() });
+                }
+
+                let size = ty.size();
+                match length.as_deref().map(|length| (length, Constant::of(length))) {
+                    None => self.emit(Instruction::Lod32(R1, size)),
+                    Some((_, Some(Constant::Int(length)))) => {
+                        self.emit(Instruction::Lod32(R1, (length as u32).wrapping_mul(size)));
+                    }
+                    Some((length, _)) => {
+                        let length_ty = self.expression(length)?;
+                        self.convert_to_index(&length_ty, &length.span, "length")?;
+                        if size > 1 {
+                            self.binop_imm(BinOp::MUL, Type::Int32, R1, size as i32, R1);
+                        }
+                    }
+                }
+                self.emit(Instruction::New { size: R1, dst: R1 });
+                Ok(Ty::Pointer(Box::new(ty.clone())))
+            }
+        }
+    }
+
+    fn is_lvalue(expr: &Expr) -> bool {
+        matches!(expr.kind, ExprKind::Variable(_) | ExprKind::Index(..) | ExprKind::Unary(UnaryOp::Deref, _))
+    }
+
+    /// Evaluates the address of an lvalue into `r1`, returning the type of the value it points at
+    fn address(&mut self, expr: &Expr) -> Result<Ty, CompileError> {
+        match &expr.kind {
+            ExprKind::Variable(name) => {
+                let variable = self.lookup(name, &expr.span)?;
+                self.binop_imm(BinOp::ADD, Type::Int32, FP, variable.offset, R1);
+                Ok(variable.ty)
+            }
+            ExprKind::Unary(UnaryOp::Deref, pointer) => {
+                let ty = self.expression(pointer)?;
+                Self::dereferenced(&ty, &pointer.span)
+            }
+            ExprKind::Index(pointer, index) => {
+                let ty = self.expression(pointer)?;
+                let element = Self::dereferenced(&ty, &pointer.span)?;
+                self.offset_pointer(BinOp::ADD, element.size(), index)?;
+                Ok(element)
+            }
+            _ => unreachable!("only lvalues have an address"),
+        }
+    }
+
+    /// The type that a pointer of type `ty` points at, if it can be dereferenced
+    fn dereferenced(ty: &Ty, span: &Span) -> Result<Ty, CompileError> {
+        match ty.pointee() {
+            Some(Ty::Void) => Err(CompileError { span: span.clone(), message: "cannot dereference `void*`".to_owned() }),
+            Some(pointee) => Ok(pointee.clone()),
+            None => Err(CompileError { span: span.clone(), message: format!("expected a pointer, found `{}`", ty) }),
+        }
+    }
+
+    /// Adds or subtracts `index` elements of the given size to the pointer in `r1`
+    fn offset_pointer(&mut self, op: BinOp, element_size: u32, index: &Expr) -> Result<(), CompileError> {
+        if let Some(Constant::Int(index)) = Constant::of(index) {
+            self.binop_imm(op, Type::Int32, R1, index.wrapping_mul(element_size as i32), R1);
+            return Ok(());
+        }
+
+        self.emit(Instruction::Push(Type::Int32, R1));
+        let index_ty = self.expression(index)?;
+        self.convert_to_index(&index_ty, &index.span, "offset")?;
+        if element_size > 1 {
+            self.binop_imm(BinOp::MUL, Type::Int32, R1, element_size as i32, R1);
+        }
+        self.mov(Type::Int32, R1, Type::Int32, R2);
+        self.emit(Instruction::Pop(Type::Int32, R1));
+        self.emit(Instruction::BinOp { op, ty: Type::Int32, src1: R1, src2: R2, dst: R1 });
+        Ok(())
+    }
+
+    fn unary(&mut self, op: UnaryOp, operand: &Expr, expr: &Expr) -> Result<Ty, CompileError> {
+        if op == UnaryOp::Neg {
+            if let Some(constant) = Constant::of(expr) {
+                return Ok(match constant {
+                    Constant::Int(value) => {
+                        self.emit(Instruction::Lod32(R1, value as u32));
+                        Ty::Int
+                    }
+                    Constant::Float(value) => {
+                        self.emit(Instruction::Lod32(R1, value.to_bits()));
+                        Ty::Float
+                    }
+                });
+            }
+        }
+
+        let ty = self.expression(operand)?;
+        match op {
+            UnaryOp::Not => {
+                self.convert_to_bool(&ty, &operand.span)?;
+                self.emit(Instruction::Cmp { ty: Type::Int8, src: R1, dst: R1, cnd: Condition::EQ });
+                Ok(Ty::Bool)
+            }
+            UnaryOp::Neg | UnaryOp::BitNot => {
+                let valid = if op == UnaryOp::Neg { ty.is_arithmetic() } else { ty.is_integer() };
+                if !valid {
+                    return Err(CompileError {
+                        span: expr.span.clone(),
+                        message: format!("operator `{}` cannot be applied to `{}`", if op == UnaryOp::Neg { "-" } else { "~" }, ty),
+                    });
+                }
+
+                let result_ty = Self::common_type(&ty, &ty);
+                self.convert(&ty, &result_ty, &operand.span)?;
+                let op = if op == UnaryOp::Neg { UnOp::NEG } else { UnOp::NOT };
+                self.emit(Instruction::UnOp { op, ty: result_ty.vm_type(), src: R1, dst: R1 });
+                Ok(result_ty)
+            }
+            UnaryOp::Deref | UnaryOp::AddressOf => unreachable!("handled by expression"),
+        }
+    }
+
+    fn binary(&mut self, op: BinaryOp, lhs: &Expr, rhs: &Expr, span: &Span) -> Result<Ty, CompileError> {
+        let operator_error = |lhs_ty: &Ty, rhs_ty: &Ty| CompileError {
+            span: span.clone(),
+            message: format!("operator `{}` cannot be applied to `{}` and `{}`", op.symbol(), lhs_ty, rhs_ty),
+        };
+
+        let condition = match op {
+            BinaryOp::And | BinaryOp::Or => {
+                let end = self.new_label();
+                self.condition(lhs)?;
+                self.jump_if(Type::Int8, if op == BinaryOp::And { Condition::EQ } else { Condition::NE }, end);
+                self.condition(rhs)?;
+                self.place_label(end);
+                return Ok(Ty::Bool);
+            }
+            BinaryOp::Lt => Some(Condition::LT),
+            BinaryOp::Le => Some(Condition::LE),
+            BinaryOp::Gt => Some(Condition::GT),
+            BinaryOp::Ge => Some(Condition::GE),
+            BinaryOp::Eq => Some(Condition::EQ),
+            BinaryOp::Ne => Some(Condition::NE),
+            _ => None,
+        };
+        let bin_op = match op {
+            BinaryOp::Add => BinOp::ADD,
+            BinaryOp::Sub => BinOp::SUB,
+            BinaryOp::Mul => BinOp::MUL,
+            BinaryOp::Div => BinOp::DIV,
+            // The VM's `MOD` takes the sign of the divisor, so `%` is computed as `a - a / b * b`
+            // to take the sign of the dividend like in C
+            BinaryOp::Mod => BinOp::MOD,
+            BinaryOp::BitAnd => BinOp::AND,
+            BinaryOp::BitOr => BinOp::OR,
+            BinaryOp::BitXor => BinOp::XOR,
+            BinaryOp::Shl => BinOp::SHL,
+            BinaryOp::Shr => BinOp::SHR,
+            // Comparisons subtract their operands and compare the difference against zero, in a
+            // type wide enough that the difference cannot overflow
+            _ => BinOp::SUB,
+        };
+        // As in C, `%` only applies to integers
+        let integer_only = matches!(bin_op, BinOp::AND | BinOp::OR | BinOp::XOR | BinOp::SHL | BinOp::SHR | BinOp::MOD);
+
+        let lhs_ty = self.expression(lhs)?;
+
+        // Pointer arithmetic moves in steps of the pointee's size
+        if lhs_ty.pointee().is_some() && condition.is_none() {
+            let element = Self::dereferenced(&lhs_ty, &lhs.span)?;
+            if !matches!(bin_op, BinOp::ADD | BinOp::SUB) {
+                return Err(operator_error(&lhs_ty, &Ty::Int));
+            }
+            if Constant::of(rhs).is_some() {
+                self.offset_pointer(bin_op, element.size(), rhs)?;
+                return Ok(lhs_ty);
+            }
+
+            self.emit(Instruction::Push(Type::Int32, R1));
+            let rhs_ty = self.expression(rhs)?;
+            if rhs_ty.pointee().is_some() {
+                if bin_op != BinOp::SUB || rhs_ty != lhs_ty {
+                    return Err(operator_error(&lhs_ty, &rhs_ty));
+                }
+                self.mov(Type::Int32, R1, Type::Int32, R2);
+                self.emit(Instruction::Pop(Type::Int32, R1));
+                self.emit(Instruction::BinOp { op: BinOp::SUB, ty: Type::Int32, src1: R1, src2: R2, dst: R1 });
+                if element.size() > 1 {
+                    self.binop_imm(BinOp::DIV, Type::Int32, R1, element.size() as i32, R1);
+                }
+                return Ok(Ty::Int);
+            }
+
+            self.convert_to_index(&rhs_ty, &rhs.span, "offset")?;
+            if element.size() > 1 {
+                self.binop_imm(BinOp::MUL, Type::Int32, R1, element.size() as i32, R1);
+            }
+            self.mov(Type::Int32, R1, Type::Int32, R2);
+            self.emit(Instruction::Pop(Type::Int32, R1));
+            self.emit(Instruction::BinOp { op: bin_op, ty: Type::Int32, src1: R1, src2: R2, dst: R1 });
+            return Ok(lhs_ty);
+        }
+
+        // A constant right operand is encoded as an immediate
+        if let Some(constant) = Constant::of(rhs).filter(|_| lhs_ty.is_arithmetic()) {
+            let common = Self::common_type(&lhs_ty, &constant.ty());
+            if integer_only && common == Ty::Float {
+                return Err(operator_error(&lhs_ty, &constant.ty()));
+            }
+            self.convert(&lhs_ty, &common, &lhs.span)?;
+
+            let ty = common.vm_type();
+            let imm = constant.immediate(ty);
+            return Ok(match condition {
+                Some(cnd) => {
+                    self.emit(Instruction::CmpImm { ty, src: R1, imm, dst: R1, cnd });
+                    Ty::Bool
+                }
+                None if bin_op == BinOp::MOD => {
+                    self.mov(ty, R1, ty, R2);
+                    self.emit(Instruction::BinOpImm { op: BinOp::DIV, ty, src: R1, imm, dst: R1 });
+                    self.emit(Instruction::BinOpImm { op: BinOp::MUL, ty, src: R1, imm, dst: R1 });
+                    self.emit(Instruction::BinOp { op: BinOp::SUB, ty, src1: R2, src2: R1, dst: R1 });
+                    common
+                }
+                None => {
+                    self.emit(Instruction::BinOpImm { op: bin_op, ty, src: R1, imm, dst: R1 });
+                    common
+                }
+            });
+        }
+
+        // Otherwise the left operand waits on the stack while the right one is evaluated
+        if lhs_ty == Ty::Void {
+            return Err(CompileError { span: lhs.span.clone(), message: "expected a value, found `void`".to_owned() });
+        }
+        self.emit(Instruction::Push(lhs_ty.vm_type(), R1));
+        let rhs_ty = self.expression(rhs)?;
+
+        let common = match (lhs_ty.pointee(), rhs_ty.pointee()) {
+            _ if lhs_ty.is_arithmetic() && rhs_ty.is_arithmetic() => Self::common_type(&lhs_ty, &rhs_ty),
+            (Some(lhs_pointee), Some(rhs_pointee)) if condition.is_some()
+                && (lhs_ty == rhs_ty || *lhs_pointee == Ty::Void || *rhs_pointee == Ty::Void) => lhs_ty.clone(),
+            _ => return Err(operator_error(&lhs_ty, &rhs_ty)),
+        };
+        if integer_only && common == Ty::Float {
+            return Err(operator_error(&lhs_ty, &rhs_ty));
+        }
+
+        let ty = common.vm_type();
+        self.convert(&rhs_ty, &common, &rhs.span)?;
+        self.mov(ty, R1, ty, R2);
+        self.emit(Instruction::Pop(lhs_ty.vm_type(), R1));
+        self.convert(&lhs_ty, &common, &lhs.span)?;
+
+        Ok(match condition {
+            Some(cnd) => {
+                let wide = if ty == Type::Float { Type::Double } else { Type::Int64 };
+                self.mov(ty, R1, wide, R1);
+                self.mov(ty, R2, wide, R2);
+                self.emit(Instruction::BinOp { op: bin_op, ty: wide, src1: R1, src2: R2, dst: R1 });
+                self.emit(Instruction::Cmp { ty: wide, src: R1, dst: R1, cnd });
+                Ty::Bool
+            }
+            None if bin_op == BinOp::MOD => {
+                self.emit(Instruction::BinOp { op: BinOp::DIV, ty, src1: R1, src2: R2, dst: R3 });
+                self.emit(Instruction::BinOp { op: BinOp::MUL, ty, src1: R3, src2: R2, dst: R3 });
+                self.emit(Instruction::BinOp { op: BinOp::SUB, ty, src1: R1, src2: R3, dst: R1 });
+                common
+            }
+            None => {
+                self.emit(Instruction::BinOp { op: bin_op, ty, src1: R1, src2: R2, dst: R1 });
+                common
+            }
+        })
+    }
+
+    fn assign(&mut self, lhs: &Expr, rhs: &Expr) -> Result<Ty, CompileError> {
+        if !Self::is_lvalue(lhs) {
+            return Err(CompileError { span: lhs.span.clone(), message: "cannot assign to this expression".to_owned() });
+        }
+
+        // A variable's address is cheap to recompute, so it doesn't need to be saved
+        let ty = if let ExprKind::Variable(name) = &lhs.kind {
+            let variable = self.lookup(name, &lhs.span)?;
+            let rhs_ty = self.expression(rhs)?;
+            self.convert(&rhs_ty, &variable.ty, &rhs.span)?;
+            self.binop_imm(BinOp::ADD, Type::Int32, FP, variable.offset, R3);
+            variable.ty
+        } else {
+            let ty = self.address(lhs)?;
+            self.emit(Instruction::Push(Type::Int32, R1));
+            let rhs_ty = self.expression(rhs)?;
+            self.convert(&rhs_ty, &ty, &rhs.span)?;
+            self.emit(Instruction::Pop(Type::Int32, R3));
+            ty
+        };
+
+        self.mov(ty.vm_type(), R1, ty.vm_type(), AT_R3);
+        Ok(ty)
+    }
+
+    fn call(&mut self, name: &str, args: &[Expr], span: &Span) -> Result<Ty, CompileError> {
+        let signature = match name {
+            "input" => Signature { return_ty: Ty::Int, params: vec![], label: 0 },
+            "output" => Signature { return_ty: Ty::Void, params: vec![Ty::Int], label: 0 },
+            _ => self.functions.get(name).cloned().ok_or_else(|| CompileError {
+                span: span.clone(),
+                message: format!("unknown function `{}`", name),
+            })?,
+        };
+        if args.len() != signature.params.len() {
+            return Err(CompileError {
+                span: span.clone(),
+                message: format!("`{}` takes {} argument(s) but {} were given", name, signature.params.len(), args.len()),
+            });
+        }
+
+        match name {
+            "input" => self.emit(Instruction::Input(R1)),
+            "output" => {
+                let ty = self.expression(&args[0])?;
+                self.convert(&ty, &Ty::Int, &args[0].span)?;
+                self.emit(Instruction::Output(R1));
+            }
+            _ => {
+                for (arg, param) in args.iter().zip(&signature.params) {
+                    let ty = self.expression(arg)?;
+                    self.convert(&ty, param, &arg.span)?;
+                    self.emit(Instruction::Push(param.vm_type(), R1));
+                }
+
+                self.emit_to(Instruction::CallTo(JumpTarget::Relative(0)), signature.label);
+
+                let args_size: u32 = signature.params.iter().map(Ty::size).sum();
+                if args_size > 0 {
+                    self.binop_imm(BinOp::SUB, Type::Int32, SP, args_size as i32, SP);
+                }
+            }
+        }
+
+        Ok(signature.return_ty)
+    }
+
+    /// Explicitly converts the value in `r1`, which may also convert between pointers and
+    /// integers
+    fn cast(&mut self, from: &Ty, to: &Ty, span: &Span) -> Result<(), CompileError> {
+        match (from.pointee().is_some(), to.pointee().is_some()) {
+            (true, true) => Ok(()),
+            (true, false) if to.is_integer() => {
+                self.convert_arithmetic(from.vm_type(), to);
+                Ok(())
+            }
+            (false, true) if from.is_integer() => {
+                self.convert_arithmetic(from.vm_type(), &Ty::Int);
+                Ok(())
+            }
+            (false, false) if from.is_arithmetic() && to.is_arithmetic() => {
+                self.convert_arithmetic(from.vm_type(), to);
+                Ok(())
+            }
+            _ => Err(CompileError { span: span.clone(), message: format!("cannot cast `{}` to `{}`", from, to) }),
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::ops::Range;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenKind {
+    Identifier(String),
+    Keyword(&'static str),
+    Integer(u32),
+    Float(f32),
+    Char(u16),
+    /// An operator or punctuation
+    Symbol(&'static str),
+    EndOfFile,
+}
+
+#[derive(Clone, Debug)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// The 1-based line number
+    pub line: usize,
+    /// The byte range of the token within its line
+    pub columns: Range<usize>,
+}
+
+/// An error found while tokenizing, pointing at the offending text
+pub struct LexError {
+    pub line: usize,
+    pub columns: Range<usize>,
+    pub message: String,
+}
+
+const KEYWORDS: [&str; 17] = [
+    "void", "int", "short", "char", "bool", "float",
+    "if", "else", "while", "break", "continue", "return",
+    "new", "del", "true", "false", "null",
+];
+
+/// Longer symbols come first so that they take precedence over their prefixes
+const SYMBOLS: [&str; 29] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+    "+", "-", "*", "/", "%", "&", "|", "^", "~", "!", "<", ">", "=",
+    "(", ")", "{", "}", "[", "]", ";", ",",
+];
+
+pub fn tokenize(source: &str) -> Result<Vec<Token>, LexError> {
+    let mut tokens = vec![];
+    let mut line_number = 0;
+
+    for (line_index, line) in source.split('\n').enumerate() {
+        line_number = line_index + 1;
+        let bytes = line.as_bytes();
+        let mut i = 0;
+
+        while i < bytes.len() {
+            let c = bytes[i] as char;
+            let start = i;
+
+            if c.is_ascii_whitespace() {
+                i += 1;
+                continue;
+            }
+            if line[i..].starts_with("//") {
+                break;
+            }
+
+            let kind = if c.is_ascii_alphabetic() || c == '_' {
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+                let word = &line[start..i];
+                match KEYWORDS.iter().find(|keyword| **keyword == word) {
+                    Some(keyword) => TokenKind::Keyword(keyword),
+                    None => TokenKind::Identifier(word.to_owned()),
+                }
+            } else if c.is_ascii_digit() {
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'.') {
+                    i += 1;
+                }
+                let text = &line[start..i];
+                let error = || LexError {
+                    line: line_number,
+                    columns: start..i,
+                    message: format!("invalid number `{}`", text),
+                };
+
+                if let Some(hex) = text.strip_prefix("0x") {
+                    TokenKind::Integer(u32::from_str_radix(hex, 16).map_err(|_| error())?)
+                } else if text.contains('.') {
+                    TokenKind::Float(text.parse().map_err(|_| error())?)
+                } else {
+                    TokenKind::Integer(text.parse().map_err(|_| error())?)
+                }
+            } else if c == '\'' {
+                let (value, length) = parse_char_literal(&line[i..]).ok_or_else(|| LexError {
+                    line: line_number,
+                    columns: start..line.len(),
+                    message: "invalid character literal".to_owned(),
+                })?;
+                i += length;
+                TokenKind::Char(value)
+            } else {
+                let c = line[i..].chars().next().unwrap();
+                let symbol = SYMBOLS.iter().find(|symbol| line[i..].starts_with(**symbol)).ok_or_else(|| LexError {
+                    line: line_number,
+                    columns: start..start + c.len_utf8(),
+                    message: format!("unexpected character `{}`", c),
+                })?;
+                i += symbol.len();
+                TokenKind::Symbol(symbol)
+            };
+
+            tokens.push(Token { kind, line: line_number, columns: start..i });
+        }
+    }
+
+    tokens.push(Token { kind: TokenKind::EndOfFile, line: line_number.max(1), columns: 0..0 });
+    Ok(tokens)
+}
+
+/// Parses a character literal like `'a'` or `'\n'` at the start of `text`, returning its value and
+/// length in bytes
+fn parse_char_literal(text: &str) -> Option<(u16, usize)> {
+    let mut chars = text.char_indices().skip(1);
+    let (_, c) = chars.next()?;
+    let value = if c == '\\' {
+        match chars.next()?.1 {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            '\\' => '\\',
+            '\'' => '\'',
+            _ => return None,
+        }
+    } else {
+        c
+    };
+
+    let (end, quote) = chars.next()?;
+    if quote != '\'' {
+        return None;
+    }
+    Some((u16::try_from(value as u32).ok()?, end + 1))
+}
@@ -0,0 +1,68 @@
+//! A compiler from Cerium, a small C-like language, to VM instructions.
+//!
+//! A program is a list of functions, one of which must be `int main()` or `void main()`. The
+//! types `bool`, `char`, `short`, `int` and `float` are operated on as the VM types `b`, `s`, `s`,
+//! `i` and `f`, and any type can be made into a pointer with `*`. Arithmetic types convert into
+//! each other implicitly, `null` converts to any pointer, and pointers and integers convert into
+//! each other with a cast like `(int) p`. Memory is allocated with `new T` or `new T[n]` and freed
+//! with `del p;`. The builtins `input()` and `output(x)` read and write integers. As in C, `/`
+//! rounds toward zero, and `%` only applies to integers and takes the sign of its left operand,
+//! so `-7 % 3` is `-1`.
+//!
+//! # Calling convention
+//!
+//! The caller pushes the arguments in order, each with the size of its parameter type, and then
+//! calls the function. On return the result is in `r1`, and the caller pops the arguments. A
+//! stack frame looks like this:
+//!
+//! ```text
+//! arguments             pushed by the caller
+//! return address        4 bytes, pushed by `call`
+//! caller's r7           4 bytes, pushed by the callee
+//! local variables       <- r7 points here
+//! temporaries           <- sp points past these
+//! ```
+//!
+//! `r7` is the frame pointer and is preserved across calls; every other register may be
+//! clobbered. Expressions are evaluated into `r1`, with `r2` and `r3` as scratch registers and
+//! intermediate values saved on the stack.
+
+mod ast;
+mod codegen;
+mod lexer;
+mod parser;
+
+use crate::cerium::assembler::Diagnostic;
+use crate::cerium::program::Program;
+use codegen::CodeGenerator;
+use parser::Parser;
+use std::ops::Range;
+
+/// An error pointing at the offending part of a source line
+struct CompileError {
+    span: ast::Span,
+    message: String,
+}
+
+pub struct CeriumCompiler;
+
+impl CeriumCompiler {
+    /// Compiles a source file into a program that starts by calling `main` and halts once it
+    /// returns. Compilation stops at the first error.
+    pub fn compile(file_name: &str, source: &str) -> Result<Program, Vec<Diagnostic>> {
+        let diagnostic = |line: usize, columns: Range<usize>, message: String| Diagnostic {
+            file: file_name.to_owned(),
+            line,
+            columns,
+            message,
+            source_line: source.split('\n').nth(line - 1).unwrap_or_default().trim_end().to_owned(),
+            notes: vec![],
+        };
+        let from_error = |error: CompileError| vec![diagnostic(error.span.line, error.span.columns, error.message)];
+
+        let tokens = lexer::tokenize(source)
+            .map_err(|error| vec![diagnostic(error.line, error.columns, error.message)])?;
+        let functions = Parser::new(tokens).parse_program().map_err(from_error)?;
+        CodeGenerator::generate(&functions).map_err(from_error)
+    }
+}
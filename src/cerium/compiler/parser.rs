@@ -0,0 +1,339 @@
+use super::ast::*;
+use super::lexer::{Token, TokenKind};
+use super::CompileError;
+
+pub struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    pub fn new(tokens: Vec<Token>) -> Parser {
+        Parser { tokens, position: 0 }
+    }
+
+    pub fn parse_program(&mut self) -> Result<Vec<Function>, CompileError> {
+        let mut functions = vec![];
+        while self.peek().kind != TokenKind::EndOfFile {
+            functions.push(self.parse_function()?);
+        }
+        Ok(functions)
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position]
+    }
+
+    fn peek_at(&self, offset: usize) -> &Token {
+        &self.tokens[(self.position + offset).min(self.tokens.len() - 1)]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.position].clone();
+        if token.kind != TokenKind::EndOfFile {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn span_of(token: &Token) -> Span {
+        Span { line: token.line, columns: token.columns.clone() }
+    }
+
+    fn error(token: &Token, message: String) -> CompileError {
+        CompileError { span: Self::span_of(token), message }
+    }
+
+    fn describe(token: &Token) -> String {
+        match &token.kind {
+            TokenKind::Identifier(name) => format!("`{}`", name),
+            TokenKind::Keyword(keyword) => format!("`{}`", keyword),
+            TokenKind::Integer(value) => format!("`{}`", value),
+            TokenKind::Float(value) => format!("`{}`", value),
+            TokenKind::Char(_) => "a character literal".to_owned(),
+            TokenKind::Symbol(symbol) => format!("`{}`", symbol),
+            TokenKind::EndOfFile => "end of file".to_owned(),
+        }
+    }
+
+    fn is_symbol(&self, symbol: &str) -> bool {
+        self.peek().kind == TokenKind::Symbol(symbol_str(symbol))
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek().kind, TokenKind::Keyword(k) if k == keyword)
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        let found = self.is_symbol(symbol);
+        if found {
+            self.next();
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<Token, CompileError> {
+        if !self.is_symbol(symbol) {
+            let token = self.peek();
+            return Err(Self::error(token, format!("expected `{}`, found {}", symbol, Self::describe(token))));
+        }
+        Ok(self.next())
+    }
+
+    fn expect_identifier(&mut self, what: &str) -> Result<(String, Span), CompileError> {
+        let token = self.next();
+        match token.kind {
+            TokenKind::Identifier(ref name) => Ok((name.clone(), Self::span_of(&token))),
+            _ => Err(Self::error(&token, format!("expected {}, found {}", what, Self::describe(&token)))),
+        }
+    }
+
+    fn is_type_start(&self) -> bool {
+        matches!(self.peek().kind, TokenKind::Keyword("void" | "int" | "short" | "char" | "bool" | "float"))
+    }
+
+    fn parse_type(&mut self) -> Result<Ty, CompileError> {
+        let token = self.next();
+        let mut ty = match token.kind {
+            TokenKind::Keyword("void") => Ty::Void,
+            TokenKind::Keyword("int") => Ty::Int,
+            TokenKind::Keyword("short") => Ty::Short,
+            TokenKind::Keyword("char") => Ty::Char,
+            TokenKind::Keyword("bool") => Ty::Bool,
+            TokenKind::Keyword("float") => Ty::Float,
+            _ => return Err(Self::error(&token, format!("expected a type, found {}", Self::describe(&token)))),
+        };
+        while self.eat_symbol("*") {
+            ty = Ty::Pointer(Box::new(ty));
+        }
+        Ok(ty)
+    }
+
+    fn parse_function(&mut self) -> Result<Function, CompileError> {
+        let return_ty = self.parse_type()?;
+        let (name, span) = self.expect_identifier("a function name")?;
+
+        self.expect_symbol("(")?;
+        let mut params = vec![];
+        if !self.is_symbol(")") {
+            loop {
+                let ty = self.parse_type()?;
+                let (name, span) = self.expect_identifier("a parameter name")?;
+                params.push(Param { ty, name, span });
+                if !self.eat_symbol(",") {
+                    break;
+                }
+            }
+        }
+        self.expect_symbol(")")?;
+
+        let body = self.parse_block()?;
+        Ok(Function { name, span, return_ty, params, body })
+    }
+
+    fn parse_block(&mut self) -> Result<Vec<Stmt>, CompileError> {
+        self.expect_symbol("{")?;
+        let mut statements = vec![];
+        while !self.eat_symbol("}") {
+            if self.peek().kind == TokenKind::EndOfFile {
+                return Err(Self::error(self.peek(), "expected `}`, found end of file".to_owned()));
+            }
+            statements.push(self.parse_statement()?);
+        }
+        Ok(statements)
+    }
+
+    fn parse_statement(&mut self) -> Result<Stmt, CompileError> {
+        let start = self.peek().clone();
+        let span = Self::span_of(&start);
+
+        let kind = if self.is_symbol("{") {
+            StmtKind::Block(self.parse_block()?)
+        } else if self.is_type_start() {
+            let ty = self.parse_type()?;
+            let (name, _) = self.expect_identifier("a variable name")?;
+            let init = if self.eat_symbol("=") { Some(self.parse_expression()?) } else { None };
+            self.expect_symbol(";")?;
+            StmtKind::Declare { ty, name, init }
+        } else if self.is_keyword("if") {
+            self.next();
+            self.expect_symbol("(")?;
+            let condition = self.parse_expression()?;
+            self.expect_symbol(")")?;
+            let then = Box::new(self.parse_statement()?);
+            let otherwise = if self.is_keyword("else") {
+                self.next();
+                Some(Box::new(self.parse_statement()?))
+            } else {
+                None
+            };
+            StmtKind::If { condition, then, otherwise }
+        } else if self.is_keyword("while") {
+            self.next();
+            self.expect_symbol("(")?;
+            let condition = self.parse_expression()?;
+            self.expect_symbol(")")?;
+            StmtKind::While { condition, body: Box::new(self.parse_statement()?) }
+        } else if self.is_keyword("break") {
+            self.next();
+            self.expect_symbol(";")?;
+            StmtKind::Break
+        } else if self.is_keyword("continue") {
+            self.next();
+            self.expect_symbol(";")?;
+            StmtKind::Continue
+        } else if self.is_keyword("return") {
+            self.next();
+            let value = if self.is_symbol(";") { None } else { Some(self.parse_expression()?) };
+            self.expect_symbol(";")?;
+            StmtKind::Return(value)
+        } else if self.is_keyword("del") {
+            self.next();
+            let pointer = self.parse_expression()?;
+            self.expect_symbol(";")?;
+            StmtKind::Del(pointer)
+        } else {
+            let expr = self.parse_expression()?;
+            self.expect_symbol(";")?;
+            StmtKind::Expr(expr)
+        };
+
+        Ok(Stmt { kind, span })
+    }
+
+    fn parse_expression(&mut self) -> Result<Expr, CompileError> {
+        let lhs = self.parse_binary(0)?;
+        if self.is_symbol("=") {
+            let operator = self.next();
+            let rhs = self.parse_expression()?;
+            return Ok(Expr {
+                kind: ExprKind::Assign(Box::new(lhs), Box::new(rhs)),
+                span: Self::span_of(&operator),
+            });
+        }
+        Ok(lhs)
+    }
+
+    fn parse_binary(&mut self, level: usize) -> Result<Expr, CompileError> {
+        if level == BinaryOp::PRECEDENCE.len() {
+            return self.parse_unary();
+        }
+
+        let mut lhs = self.parse_binary(level + 1)?;
+        loop {
+            let operator = BinaryOp::PRECEDENCE[level].iter()
+                .find(|op| self.is_symbol(op.symbol()))
+                .copied();
+            let Some(operator) = operator else { break };
+
+            let token = self.next();
+            let rhs = self.parse_binary(level + 1)?;
+            lhs = Expr {
+                kind: ExprKind::Binary(operator, Box::new(lhs), Box::new(rhs)),
+                span: Self::span_of(&token),
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, CompileError> {
+        let token = self.peek().clone();
+        let operator = match token.kind {
+            TokenKind::Symbol("-") => Some(UnaryOp::Neg),
+            TokenKind::Symbol("!") => Some(UnaryOp::Not),
+            TokenKind::Symbol("~") => Some(UnaryOp::BitNot),
+            TokenKind::Symbol("*") => Some(UnaryOp::Deref),
+            TokenKind::Symbol("&") => Some(UnaryOp::AddressOf),
+            _ => None,
+        };
+
+        if let Some(operator) = operator {
+            self.next();
+            let operand = self.parse_unary()?;
+            return Ok(Expr { kind: ExprKind::Unary(operator, Box::new(operand)), span: Self::span_of(&token) });
+        }
+
+        // A parenthesized type is a cast
+        if self.is_symbol("(") && matches!(
+            self.peek_at(1).kind,
+            TokenKind::Keyword("void" | "int" | "short" | "char" | "bool" | "float")
+        ) {
+            self.next();
+            let ty = self.parse_type()?;
+            self.expect_symbol(")")?;
+            let operand = self.parse_unary()?;
+            return Ok(Expr { kind: ExprKind::Cast(ty, Box::new(operand)), span: Self::span_of(&token) });
+        }
+
+        self.parse_postfix()
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr, CompileError> {
+        let mut expr = self.parse_primary()?;
+        while self.is_symbol("[") {
+            let token = self.next();
+            let index = self.parse_expression()?;
+            self.expect_symbol("]")?;
+            expr = Expr { kind: ExprKind::Index(Box::new(expr), Box::new(index)), span: Self::span_of(&token) };
+        }
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, CompileError> {
+        let token = self.next();
+        let span = Self::span_of(&token);
+
+        let kind = match token.kind {
+            TokenKind::Integer(value) => ExprKind::Integer(value),
+            TokenKind::Float(value) => ExprKind::Float(value),
+            TokenKind::Char(value) => ExprKind::Char(value),
+            TokenKind::Keyword("true") => ExprKind::Bool(true),
+            TokenKind::Keyword("false") => ExprKind::Bool(false),
+            TokenKind::Keyword("null") => ExprKind::Null,
+            TokenKind::Keyword("new") => {
+                let ty = self.parse_type()?;
+                let length = if self.eat_symbol("[") {
+                    let length = self.parse_expression()?;
+                    self.expect_symbol("]")?;
+                    Some(Box::new(length))
+                } else {
+                    None
+                };
+                ExprKind::New(ty, length)
+            }
+            TokenKind::Identifier(name) if self.is_symbol("(") => {
+                self.next();
+                let mut args = vec![];
+                if !self.is_symbol(")") {
+                    loop {
+                        args.push(self.parse_expression()?);
+                        if !self.eat_symbol(",") {
+                            break;
+                        }
+                    }
+                }
+                self.expect_symbol(")")?;
+                ExprKind::Call(name, args)
+            }
+            TokenKind::Identifier(name) => ExprKind::Variable(name),
+            TokenKind::Symbol("(") => {
+                let expr = self.parse_expression()?;
+                self.expect_symbol(")")?;
+                return Ok(expr);
+            }
+            _ => return Err(Self::error(&token, format!("expected an expression, found {}", Self::describe(&token)))),
+        };
+
+        Ok(Expr { kind, span })
+    }
+}
+
+/// Finds the `'static` spelling of a symbol, so that it can be compared against tokens
+fn symbol_str(symbol: &str) -> &'static str {
+    const ALL: [&str; 29] = [
+        "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+        "+", "-", "*", "/", "%", "&", "|", "^", "~", "!", "<", ">", "=",
+        "(", ")", "{", "}", "[", "]", ";", ",",
+    ];
+    ALL.iter().find(|s| **s == symbol).copied().unwrap_or("")
+}
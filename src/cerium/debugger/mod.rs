@@ -0,0 +1,320 @@
+use crate::cerium::instruction::instruction_parts::{Condition, Register};
+use crate::cerium::instruction::Instruction;
+use crate::cerium::program::{Program, Symbol};
+use crate::cerium::symbol_map::{self, SymbolMap};
+use crate::cerium::vm::{
+    CeDouble, CeFloat, CeInt16, CeInt32, CeInt64, CeInt8, CeWord, CeriumVM, HostFunctions, StdIo, VmConfig, VmError,
+};
+use std::collections::BTreeSet;
+use std::io::{stdin, stdout, Write};
+
+const REGISTERS: [(&str, Register); 8] = [
+    ("sp", Register::SP),
+    ("r1", Register::R1),
+    ("r2", Register::R2),
+    ("r3", Register::R3),
+    ("r4", Register::R4),
+    ("r5", Register::R5),
+    ("r6", Register::R6),
+    ("r7", Register::R7),
+];
+
+/// Why execution stopped and control returned to the user
+enum Stop {
+    Stepped,
+    Breakpoint,
+    Halted,
+    Trapped(VmError),
+}
+
+/// An interactive step debugger for programs running on a [`CeriumVM`]
+pub struct Debugger {
+    vm: CeriumVM,
+    program: Program,
+    /// Replaces the program's own symbols in addresses that are shown or typed
+    symbols: Option<SymbolMap>,
+    breakpoints: BTreeSet<CeWord>,
+}
+
+impl Debugger {
+    pub fn new(program: Program) -> Debugger {
+        Self::with_config(program, VmConfig::default())
+    }
+
+    /// Creates a debugger whose VM's memory is limited according to `config`
+    pub fn with_config(program: Program, config: VmConfig) -> Debugger {
+        let mut vm = CeriumVM::with_config(StdIo, config);
+        vm.host_functions_mut().register_standard_services();
+        vm.load_program(&program);
+
+        Debugger { vm, program, symbols: None, breakpoints: Default::default() }
+    }
+
+    /// Names code addresses after `symbols`, like a symbol map written by `cerium assemble --map`,
+    /// instead of the symbols stored in the program
+    pub fn with_symbols(mut self, symbols: SymbolMap) -> Debugger {
+        self.symbols = Some(symbols);
+        self
+    }
+
+    /// The functions that the guest can call, which start out as the standard services
+    pub fn host_functions_mut(&mut self) -> &mut HostFunctions {
+        self.vm.host_functions_mut()
+    }
+
+    /// Reads and executes commands from stdin until the user quits or stdin is closed
+    pub fn run(&mut self) {
+        println!("CeriumVM debugger. Type `help` for a list of commands.");
+        self.print_current_instruction();
+
+        loop {
+            print!("(cerium) ");
+            stdout().flush().unwrap();
+
+            // Don't hold on to stdin, since the guest program reads from it too
+            let mut line = String::new();
+            if !matches!(stdin().read_line(&mut line), Ok(1..)) {
+                break;
+            }
+            let args: Vec<&str> = line.split_whitespace().collect();
+            if args.is_empty() {
+                continue;
+            }
+
+            match self.execute_command(&args) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(err) => println!("{}", err),
+            }
+        }
+    }
+
+    /// Executes a single debugger command, returning whether to keep going
+    fn execute_command(&mut self, args: &[&str]) -> Result<bool, String> {
+        match args[0] {
+            "b" | "break" => {
+                let address = self.parse_address(args.get(1).ok_or("Usage: break <address|label>")?)?;
+                self.breakpoints.insert(address);
+                println!("Breakpoint set at {}", self.describe_address(address));
+            }
+            "d" | "delete" => {
+                let address = self.parse_address(args.get(1).ok_or("Usage: delete <address|label>")?)?;
+                if !self.breakpoints.remove(&address) {
+                    return Err(format!("No breakpoint at {}", self.describe_address(address)));
+                }
+            }
+            "bl" | "breakpoints" => {
+                for address in &self.breakpoints {
+                    println!("  {}", self.describe_address(*address));
+                }
+            }
+            "s" | "step" => {
+                let count = match args.get(1) {
+                    Some(count) => count.parse::<usize>().map_err(|_| "Usage: step [count]")?,
+                    None => 1,
+                };
+                let mut stop = Stop::Stepped;
+                for _ in 0..count {
+                    stop = self.step()?;
+                    if !matches!(stop, Stop::Stepped) {
+                        break;
+                    }
+                }
+                self.report(stop);
+            }
+            "n" | "next" => {
+                let stop = self.step_over()?;
+                self.report(stop);
+            }
+            "c" | "continue" => {
+                let stop = self.continue_execution()?;
+                self.report(stop);
+            }
+            "r" | "regs" | "registers" => self.print_registers(),
+            "x" | "mem" | "memory" => {
+                let address = self.parse_value(args.get(1).ok_or("Usage: memory <address> [length]")?)?;
+                let length = match args.get(2) {
+                    Some(length) => self.parse_value(length)?,
+                    None => 16,
+                };
+                self.print_memory(address, length)?;
+            }
+            "w" | "where" => self.print_current_instruction(),
+            "bt" | "backtrace" => self.print_backtrace(),
+            "h" | "help" => Self::print_help(),
+            "q" | "quit" => return Ok(false),
+            command => return Err(format!("Unknown command `{}`. Type `help` for a list of commands.", command)),
+        }
+
+        Ok(true)
+    }
+
+    fn print_help() {
+        println!("Commands:");
+        println!("  break <address|label>    (b)  | Sets a breakpoint");
+        println!("  delete <address|label>   (d)  | Removes a breakpoint");
+        println!("  breakpoints              (bl) | Lists breakpoints");
+        println!("  step [count]             (s)  | Executes one or more instructions");
+        println!("  next                     (n)  | Executes one instruction, stepping over calls");
+        println!("  continue                 (c)  | Runs until a breakpoint or the end of the program");
+        println!("  registers                (r)  | Prints the registers in every type");
+        println!("  memory <address> [length](x)  | Dumps guest memory");
+        println!("  where                    (w)  | Prints the current instruction");
+        println!("  backtrace                (bt) | Prints the return addresses of active calls");
+        println!("  quit                     (q)  | Exits the debugger");
+        println!("Addresses may be numbers, labels, or registers.");
+    }
+
+    fn step(&mut self) -> Result<Stop, String> {
+        if self.vm.is_done() {
+            return Err("The program has halted".to_owned());
+        }
+
+        Ok(match self.vm.execute_next_instruction() {
+            Err(err) => Stop::Trapped(err),
+            Ok(()) if self.vm.is_done() => Stop::Halted,
+            Ok(()) => Stop::Stepped,
+        })
+    }
+
+    /// Runs until the program reaches `target` or stops for another reason
+    fn run_until(&mut self, target: Option<CeWord>) -> Result<Stop, String> {
+        loop {
+            match self.step()? {
+                Stop::Stepped => {}
+                stop => return Ok(stop),
+            }
+
+            let ip = self.vm.instruction_ptr();
+            if Some(ip) == target {
+                return Ok(Stop::Stepped);
+            }
+            if self.breakpoints.contains(&ip) {
+                return Ok(Stop::Breakpoint);
+            }
+        }
+    }
+
+    fn continue_execution(&mut self) -> Result<Stop, String> {
+        self.run_until(None)
+    }
+
+    /// Executes the current instruction. If it is a `call`, or an unconditional jump through a
+    /// register that hand-written calling code uses instead, execution continues until it returns
+    /// to the following instruction.
+    fn step_over(&mut self) -> Result<Stop, String> {
+        let (instruction, size) = self.vm.fetch().map_err(|trap| trap.to_string())?;
+        let next = self.vm.instruction_ptr() + size as CeWord;
+
+        match instruction {
+            Instruction::Jmp { tgt, cnd: Condition::ALWAYS, .. } if !tgt.indirect => {
+                self.run_until(Some(next))
+            }
+            Instruction::Call(_) | Instruction::CallTo(_) => self.run_until(Some(next)),
+            _ => self.step(),
+        }
+    }
+
+    fn report(&self, stop: Stop) {
+        match stop {
+            Stop::Stepped => {}
+            Stop::Breakpoint => println!("Breakpoint hit"),
+            Stop::Halted => {
+                println!("Program halted");
+                return;
+            }
+            Stop::Trapped(err) => println!("{}", err),
+        }
+        self.print_current_instruction();
+    }
+
+    fn print_current_instruction(&self) {
+        let ip = self.vm.instruction_ptr();
+        match self.vm.fetch() {
+            Ok((Instruction::CallHost(index), _)) if (index as usize) < self.program.imports.len() => {
+                println!("{}: syscall {}", self.describe_address(ip), self.program.imports[index as usize])
+            }
+            Ok((instruction, _)) => match instruction.jump_target() {
+                Some(target) => println!(
+                    "{}: {}",
+                    self.describe_address(ip),
+                    instruction.to_string_with_target(&self.describe_address(target.resolve(ip)))
+                ),
+                None => println!("{}: {}", self.describe_address(ip), instruction),
+            },
+            Err(trap) => println!("{}: {}", self.describe_address(ip), trap),
+        }
+    }
+
+    fn print_backtrace(&self) {
+        println!("#0  {}", self.describe_address(self.vm.instruction_ptr()));
+        for (i, address) in self.vm.call_stack().iter().rev().enumerate() {
+            println!("#{:<2} {}", i + 1, self.describe_address(*address));
+        }
+    }
+
+    fn print_registers(&self) {
+        for (name, register) in REGISTERS {
+            println!(
+                "{:<3} 0x{:08x}  b: {:<4}  s: {:<6}  i: {:<11}  l: {:<20}  f: {:<14?}  d: {:?}",
+                name,
+                self.vm.register::<CeWord>(register),
+                self.vm.register::<CeInt8>(register),
+                self.vm.register::<CeInt16>(register),
+                self.vm.register::<CeInt32>(register),
+                self.vm.register::<CeInt64>(register),
+                self.vm.register::<CeFloat>(register),
+                self.vm.register::<CeDouble>(register),
+            );
+        }
+    }
+
+    fn print_memory(&self, address: CeWord, length: CeWord) -> Result<(), String> {
+        let bytes = self.vm.read_memory(address, length).map_err(|trap| trap.to_string())?;
+
+        for (i, chunk) in bytes.chunks(16).enumerate() {
+            let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let ascii: String = chunk.iter()
+                .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+                .collect();
+            println!("0x{:08x}: {:<48} |{}|", address.wrapping_add(i as CeWord * 16), hex.join(" "), ascii);
+        }
+
+        Ok(())
+    }
+
+    fn symbols(&self) -> &[Symbol] {
+        self.symbols.as_ref().map_or(&self.program.symbols, SymbolMap::symbols)
+    }
+
+    fn describe_address(&self, address: CeWord) -> String {
+        match symbol_map::nearest_symbol(self.symbols(), address) {
+            Some((symbol, 0)) => format!("0x{:04x} <{}>", address, symbol.name),
+            Some((symbol, offset)) => format!("0x{:04x} <{}+0x{:x}>", address, symbol.name, offset),
+            None => format!("0x{:04x}", address),
+        }
+    }
+
+    /// Parses a code address, which may be a number or a label
+    fn parse_address(&self, text: &str) -> Result<CeWord, String> {
+        if let Some(symbol) = self.symbols().iter().find(|symbol| symbol.name == text) {
+            return Ok(symbol.address);
+        }
+        Self::parse_number(text).ok_or_else(|| format!("Unknown label or invalid address `{}`", text))
+    }
+
+    /// Parses a value, which may be a number, a label, or a register
+    fn parse_value(&self, text: &str) -> Result<CeWord, String> {
+        if let Some((_, register)) = REGISTERS.iter().find(|(name, _)| *name == text) {
+            return Ok(self.vm.register::<CeWord>(*register));
+        }
+        self.parse_address(text)
+    }
+
+    fn parse_number(text: &str) -> Option<CeWord> {
+        match text.strip_prefix("0x") {
+            Some(hex) => CeWord::from_str_radix(hex, 16).ok(),
+            None => text.parse().ok(),
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use crate::cerium::instruction::instruction_parts::JumpTarget;
+use crate::cerium::instruction::{DecodeError, Instruction};
+use crate::cerium::program::Program;
+use crate::cerium::vm::RAM;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+/// Turns the code of a [`Program`] back into CASM that the assembler accepts. Every instruction
+/// is annotated with its byte offset, and relative jumps and `lod` instructions whose value is later
+/// used as a jump target are printed with a label so that the output can be edited and re-assembled.
+/// The read-only data follows in a `.data` section, with `lod` instructions of the addresses of
+/// its labels printed with the label.
+pub struct CasmDisassembler<'a> {
+    program: &'a Program,
+    instructions: Vec<(usize, Result<Instruction, DecodeError>)>,
+    labels: BTreeMap<usize, Vec<String>>,
+    /// The labels in the data section, by offset into it
+    data_labels: BTreeMap<usize, Vec<String>>,
+    /// The labels to print in place of the value of a `lod` or the target of a relative jump, by
+    /// instruction address
+    operand_labels: HashMap<usize, String>,
+}
+
+impl<'a> CasmDisassembler<'a> {
+    pub fn disassemble(program: &'a Program) -> String {
+        let mut disassembler = CasmDisassembler {
+            program,
+            instructions: vec![],
+            labels: Default::default(),
+            data_labels: Default::default(),
+            operand_labels: Default::default(),
+        };
+
+        disassembler.decode_instructions();
+        disassembler.collect_labels();
+        disassembler.output()
+    }
+
+    fn decode_instructions(&mut self) {
+        let code = &self.program.code;
+        let mut address = 0;
+
+        while address < code.len() {
+            match Instruction::decode(&code[address..]) {
+                Ok((instruction, size)) => {
+                    self.instructions.push((address, Ok(instruction)));
+                    address += size;
+                }
+                Err(err) => {
+                    self.instructions.push((address, Err(err)));
+                    address += 1;
+                }
+            }
+        }
+    }
+
+    /// Whether a label can be placed at the given address
+    fn is_instruction_boundary(&self, address: usize) -> bool {
+        address == self.program.code.len() || self.instructions.binary_search_by_key(
+            &address, |(instruction_address, _)| *instruction_address
+        ).is_ok_and(|i| self.instructions[i].1.is_ok())
+    }
+
+    /// Returns the name of a label at the given address, creating one if there is none yet
+    fn label_at(&mut self, address: usize) -> String {
+        self.labels.entry(address)
+            .or_insert_with(|| vec![format!("L_{:04X}", address)])
+            .first().unwrap().clone()
+    }
+
+    fn collect_labels(&mut self) {
+        for symbol in &self.program.symbols {
+            let data_offset = (symbol.address & !RAM::STATIC_PTR_BIT) as usize;
+            if symbol.address & RAM::STATIC_PTR_BIT != 0 && data_offset <= self.program.rodata.len() {
+                self.data_labels.entry(data_offset).or_default().push(symbol.name.clone());
+            } else if self.is_instruction_boundary(symbol.address as usize) {
+                self.labels.entry(symbol.address as usize).or_default().push(symbol.name.clone());
+            }
+        }
+
+        // Track the last `lod` into each register to find jump targets
+        let mut register_values: [Option<(usize, u32)>; 8] = Default::default();
+        let mut jump_targets: Vec<(usize, usize)> = vec![];
+
+        for (address, instruction) in &self.instructions {
+            let Ok(instruction) = instruction else {
+                register_values = Default::default();
+                continue;
+            };
+
+            if let Some(target @ JumpTarget::Relative(_)) = instruction.jump_target() {
+                let target = target.resolve(*address as u32) as usize;
+                if self.is_instruction_boundary(target) {
+                    jump_targets.push((*address, target));
+                }
+            }
+
+            if let Instruction::Jmp { tgt, .. } | Instruction::Call(tgt) = instruction {
+                if let (false, Some((lod_address, value))) = (tgt.indirect, register_values[tgt.register as usize]) {
+                    if self.is_instruction_boundary(value as usize) {
+                        jump_targets.push((lod_address, value as usize));
+                    }
+                }
+            }
+
+            if let Instruction::Lod32(_, value) = instruction {
+                if let Some(labels) = self.data_labels.get(&((value & !RAM::STATIC_PTR_BIT) as usize)) {
+                    if value & RAM::STATIC_PTR_BIT != 0 {
+                        self.operand_labels.insert(*address, labels[0].clone());
+                    }
+                }
+            }
+
+            match instruction {
+                Instruction::Lod32(dst, value) if !dst.indirect => {
+                    register_values[dst.register as usize] = Some((*address, *value));
+                }
+                // Functions may return results in any register
+                Instruction::Call(_) | Instruction::CallTo(_) | Instruction::Syscall(_) | Instruction::CallHost(_) => {
+                    register_values = Default::default()
+                }
+                _ => if let Some((dst, _)) = instruction.destination().filter(|(dst, _)| !dst.indirect) {
+                    register_values[dst.register as usize] = None;
+                }
+            }
+        }
+
+        for (lod_address, target) in jump_targets {
+            let label = self.label_at(target);
+            self.operand_labels.insert(lod_address, label);
+        }
+
+        if self.program.entry_point != 0 {
+            self.label_at(self.program.entry_point as usize);
+        }
+    }
+
+    fn output(&self) -> String {
+        let mut output = String::new();
+
+        if self.program.entry_point != 0 {
+            let entry_label = &self.labels[&(self.program.entry_point as usize)][0];
+            writeln!(output, "    .entry {}", entry_label).unwrap();
+            writeln!(output).unwrap();
+        }
+
+        for (address, instruction) in &self.instructions {
+            self.output_labels(&mut output, *address);
+
+            let text = match instruction {
+                Ok(Instruction::Lod32(dst, _)) if self.operand_labels.contains_key(address) => {
+                    format!("lod {} <- {}", dst, self.operand_labels[address])
+                }
+                Ok(instruction) if instruction.jump_target().is_some() && self.operand_labels.contains_key(address) => {
+                    instruction.to_string_with_target(&self.operand_labels[address])
+                }
+                Ok(Instruction::CallHost(index)) if (*index as usize) < self.program.imports.len() => {
+                    format!("syscall {}", self.program.imports[*index as usize])
+                }
+                Ok(instruction) => instruction.to_string(),
+                Err(_) => format!("// invalid instruction byte 0x{:02x}", self.program.code[*address]),
+            };
+            writeln!(output, "    {:<32}// 0x{:04x}", text, address).unwrap();
+        }
+        self.output_labels(&mut output, self.program.code.len());
+        self.output_data(&mut output);
+
+        output
+    }
+
+    /// Prints the read-only data as `.string` directives where it looks like text, and as
+    /// `.byte` directives otherwise. Labels split it into pieces.
+    fn output_data(&self, output: &mut String) {
+        let data = &self.program.rodata;
+        if data.is_empty() && self.data_labels.is_empty() {
+            return;
+        }
+        writeln!(output).unwrap();
+        writeln!(output, "    .data").unwrap();
+
+        let mut boundaries: Vec<usize> = self.data_labels.keys().copied().chain([0, data.len()]).collect();
+        boundaries.sort_unstable();
+        boundaries.dedup();
+
+        for piece in boundaries.windows(2) {
+            let (start, end) = (piece[0], piece[1]);
+            for label in self.data_labels.get(&start).into_iter().flatten() {
+                writeln!(output, "{}:", label).unwrap();
+            }
+
+            let bytes = &data[start..end];
+            let address = |offset: usize| RAM::STATIC_PTR_BIT as usize | offset;
+            // Strings may be followed by padding from `.align`
+            let padding = bytes.iter().rev().take_while(|&&byte| byte == 0).count().saturating_sub(1);
+            if let Some(text) = Self::as_string_literal(&bytes[..bytes.len() - padding]) {
+                writeln!(output, "    {:<32}// 0x{:08x}", format!(".string {}", text), address(start)).unwrap();
+                if padding > 0 {
+                    let directive = format!(".zero {}", padding);
+                    writeln!(output, "    {:<32}// 0x{:08x}", directive, address(end - padding)).unwrap();
+                }
+                continue;
+            }
+            for (row, chunk) in bytes.chunks(Self::BYTES_PER_ROW).enumerate() {
+                let values: Vec<String> = chunk.iter().map(|byte| format!("0x{:02x}", byte)).collect();
+                let directive = format!(".byte {}", values.join(", "));
+                writeln!(output, "    {:<32}// 0x{:08x}", directive, address(start + row * Self::BYTES_PER_ROW)).unwrap();
+            }
+        }
+        for label in self.data_labels.get(&data.len()).into_iter().flatten() {
+            writeln!(output, "{}:", label).unwrap();
+        }
+    }
+
+    /// Quotes `bytes` as a string literal if they are printable text followed by a single NUL
+    fn as_string_literal(bytes: &[u8]) -> Option<String> {
+        let (0, text) = bytes.split_last()? else { return None };
+        let text = std::str::from_utf8(text).ok()?;
+        if text.is_empty() || text.chars().any(|c| c == '\0' || (c.is_control() && !"\n\r\t".contains(c))) {
+            return None;
+        }
+
+        let mut literal = String::from("\"");
+        for c in text.chars() {
+            match c {
+                '"' => literal.push_str("\\\""),
+                '\\' => literal.push_str("\\\\"),
+                '\n' => literal.push_str("\\n"),
+                '\r' => literal.push_str("\\r"),
+                '\t' => literal.push_str("\\t"),
+                _ => literal.push(c),
+            }
+        }
+        literal.push('"');
+        Some(literal)
+    }
+
+    /// How many bytes each `.byte` directive of the data section lists
+    const BYTES_PER_ROW: usize = 4;
+
+    fn output_labels(&self, output: &mut String, address: usize) {
+        for label in self.labels.get(&address).into_iter().flatten() {
+            writeln!(output, "{}:", label).unwrap();
+        }
+    }
+}
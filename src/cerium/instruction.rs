@@ -1,5 +1,7 @@
 pub mod instruction_parts {
-    #[derive(Copy, Clone)]
+    use std::fmt::{Display, Formatter};
+
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
     pub enum Condition {
         LT = 0b1000,
         EQ = 0b0100,
@@ -10,21 +12,125 @@ pub mod instruction_parts {
         ALWAYS = 0b1110,
     }
 
-    #[derive(Copy, Clone)]
+    impl Condition {
+        pub(crate) fn from_bits(bits: u8) -> Option<Condition> {
+            use Condition::*;
+            Some(match bits & 0b1110 {
+                0b1000 => LT,
+                0b0100 => EQ,
+                0b1100 => LE,
+                0b0010 => GT,
+                0b1010 => NE,
+                0b0110 => GE,
+                0b1110 => ALWAYS,
+                _ => return None
+            })
+        }
+    }
+
+    impl Display for Condition {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            use Condition::*;
+            f.write_str(match self {
+                LT => "<",
+                EQ => "==",
+                LE => "<=",
+                GT => ">",
+                NE => "!=",
+                GE => ">=",
+                ALWAYS => "always",
+            })
+        }
+    }
+
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
     pub enum Type {
         Int8 = 0,
         Int16 = 1,
         Int32 = 2,
         Float = 3,
+        UInt8 = 4,
+        UInt16 = 5,
+        UInt32 = 6,
+        Int64 = 7,
+        Double = 8,
     }
 
-    impl Into<u8> for Type {
-        fn into(self) -> u8 {
-            self as u8
+    impl Type {
+        /// Decodes the two-bit type field of the base instructions, which can only hold the
+        /// signed types and `Float`
+        pub(crate) fn from_bits(bits: u8) -> Type {
+            use Type::*;
+            match bits & 0b11 {
+                0b00 => Int8,
+                0b01 => Int16,
+                0b10 => Int32,
+                _ => Float,
+            }
+        }
+
+        /// Decodes the four-bit type field of the extended instructions
+        pub(crate) fn from_nibble(bits: u8) -> Option<Type> {
+            use Type::*;
+            Some(match bits & 0b1111 {
+                0b0000 => Int8,
+                0b0001 => Int16,
+                0b0010 => Int32,
+                0b0011 => Float,
+                0b0100 => UInt8,
+                0b0101 => UInt16,
+                0b0110 => UInt32,
+                0b0111 => Int64,
+                0b1000 => Double,
+                _ => return None
+            })
+        }
+
+        /// Whether the type fits in the two-bit type field of the base instructions
+        pub(crate) fn is_base(&self) -> bool {
+            (*self as u8) < 0b100
+        }
+
+        /// The size of a value of this type in bytes
+        pub fn size(&self) -> u32 {
+            use Type::*;
+            match self {
+                Int8 | UInt8 => 1,
+                Int16 | UInt16 => 2,
+                Int32 | UInt32 | Float => 4,
+                Int64 | Double => 8,
+            }
+        }
+
+        pub fn is_unsigned(&self) -> bool {
+            matches!(self, Type::UInt8 | Type::UInt16 | Type::UInt32)
+        }
+    }
+
+    impl Display for Type {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            use Type::*;
+            f.write_str(match self {
+                Int8 => "b",
+                Int16 => "s",
+                Int32 => "i",
+                Float => "f",
+                UInt8 => "ub",
+                UInt16 => "us",
+                UInt32 => "ui",
+                Int64 => "l",
+                Double => "d",
+            })
+        }
+    }
+
+    impl From<Type> for u8 {
+        fn from(value: Type) -> u8 {
+            value as u8
         }
     }
 
-    #[derive(Copy, Clone)]
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
     pub enum Register {
         SP = 0,
         R1 = 1,
@@ -36,7 +142,23 @@ pub mod instruction_parts {
         R7 = 7,
     }
 
-    #[derive(Copy, Clone)]
+    impl Register {
+        pub(crate) fn from_bits(bits: u8) -> Register {
+            use Register::*;
+            match bits & 0b111 {
+                0 => SP,
+                1 => R1,
+                2 => R2,
+                3 => R3,
+                4 => R4,
+                5 => R5,
+                6 => R6,
+                _ => R7,
+            }
+        }
+    }
+
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
     pub struct Location {
         pub(crate) register: Register,
         pub(crate) indirect: bool,
@@ -47,15 +169,34 @@ pub mod instruction_parts {
             let v = self.register as u8;
             if self.indirect { v | 0b1000 } else { v }
         }
+
+        pub(crate) fn from_bits(bits: u8) -> Location {
+            Location {
+                register: Register::from_bits(bits),
+                indirect: (bits & 0b1000) != 0,
+            }
+        }
     }
 
-    #[derive(Copy, Clone)]
+    impl Display for Location {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            if self.indirect {
+                f.write_str("@")?;
+            }
+            match self.register {
+                Register::SP => f.write_str("sp"),
+                register => write!(f, "r{}", register as u8),
+            }
+        }
+    }
+
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
     pub enum BinOp {
         XOR = 0b0001,
         OR = 0b0010,
         AND = 0b0011,
 
-        SHL = 0b0101,
+        SHL = 0b0110,
         SHR = 0b0111,
 
         MUL = 0b1001,
@@ -65,16 +206,210 @@ pub mod instruction_parts {
         MOD = 0b1101,
     }
 
-    #[derive(Copy, Clone)]
+    impl BinOp {
+        pub(crate) fn from_bits(bits: u8) -> Option<BinOp> {
+            use BinOp::*;
+            Some(match bits & 0b1111 {
+                0b0001 => XOR,
+                0b0010 => OR,
+                0b0011 => AND,
+                0b0110 => SHL,
+                0b0111 => SHR,
+                0b1001 => MUL,
+                0b1010 => ADD,
+                0b1011 => SUB,
+                0b1100 => DIV,
+                0b1101 => MOD,
+                _ => return None
+            })
+        }
+
+        pub fn mnemonic(&self) -> &'static str {
+            use BinOp::*;
+            match self {
+                XOR => "xor",
+                OR => "or",
+                AND => "and",
+                SHL => "shl",
+                SHR => "shr",
+                MUL => "mul",
+                ADD => "add",
+                SUB => "sub",
+                DIV => "div",
+                MOD => "mod",
+            }
+        }
+
+        pub fn symbol(&self) -> &'static str {
+            use BinOp::*;
+            match self {
+                XOR => "^",
+                OR => "|",
+                AND => "&",
+                SHL => "<<",
+                SHR => ">>",
+                MUL => "*",
+                ADD => "+",
+                SUB => "-",
+                DIV => "/",
+                MOD => "%",
+            }
+        }
+    }
+
+    /// A constant operand, stored in the fewest bytes that represent it. Integer operations
+    /// sign-extend it to their type. Float operations convert it from an integer, except for
+    /// 32-bit immediates, which hold the bits of the float.
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub enum Immediate {
+        Imm8(u8),
+        Imm16(u16),
+        Imm32(u32),
+    }
+
+    impl Immediate {
+        /// The smallest immediate that sign-extends to `value`
+        pub fn from_i32(value: i32) -> Immediate {
+            if let Ok(value) = i8::try_from(value) {
+                Immediate::Imm8(value as u8)
+            } else if let Ok(value) = i16::try_from(value) {
+                Immediate::Imm16(value as u16)
+            } else {
+                Immediate::Imm32(value as u32)
+            }
+        }
+
+        pub fn sign_extended(&self) -> i32 {
+            match *self {
+                Immediate::Imm8(value) => value as i8 as i32,
+                Immediate::Imm16(value) => value as i16 as i32,
+                Immediate::Imm32(value) => value as i32,
+            }
+        }
+
+        /// The two bits that encode the width of the immediate
+        pub(crate) fn width_bits(&self) -> u8 {
+            match self {
+                Immediate::Imm8(_) => 0b00,
+                Immediate::Imm16(_) => 0b01,
+                Immediate::Imm32(_) => 0b10,
+            }
+        }
+
+        /// The number of bytes that follow an instruction for an immediate of the given width, or
+        /// `None` if the width bits are invalid
+        pub(crate) fn size_for_width(bits: u8) -> Option<usize> {
+            match bits & 0b11 {
+                0b00 => Some(1),
+                0b01 => Some(2),
+                0b10 => Some(4),
+                _ => None,
+            }
+        }
+
+        pub(crate) fn from_bytes(bytes: &[u8]) -> Immediate {
+            match *bytes {
+                [b] => Immediate::Imm8(b),
+                [b1, b2] => Immediate::Imm16(u16::from_be_bytes([b1, b2])),
+                _ => Immediate::Imm32(u32::from_be_bytes(bytes.try_into().unwrap())),
+            }
+        }
+
+        pub(crate) fn output_to<F: FnMut(u8)>(&self, f: &mut F) {
+            match *self {
+                Immediate::Imm8(value) => f(value),
+                Immediate::Imm16(value) => value.to_be_bytes().into_iter().for_each(f),
+                Immediate::Imm32(value) => value.to_be_bytes().into_iter().for_each(f),
+            }
+        }
+
+        /// Formats the immediate as an operand of the given type
+        pub fn display(&self, ty: Type) -> String {
+            match (ty, *self) {
+                (Type::Float | Type::Double, Immediate::Imm32(bits)) => format!("{:?}", f32::from_bits(bits)),
+                (Type::UInt8, _) => (self.sign_extended() as u8).to_string(),
+                (Type::UInt16, _) => (self.sign_extended() as u16).to_string(),
+                (Type::UInt32, _) => (self.sign_extended() as u32).to_string(),
+                _ => self.sign_extended().to_string(),
+            }
+        }
+    }
+
+    /// The target of a direct jump or call
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub enum JumpTarget {
+        Absolute(u32),
+        /// An offset from the start of the jump instruction
+        Relative(i32),
+    }
+
+    impl JumpTarget {
+        /// The address this target refers to, for a jump instruction at `address`
+        pub fn resolve(&self, address: u32) -> u32 {
+            match *self {
+                JumpTarget::Absolute(target) => target,
+                JumpTarget::Relative(offset) => address.wrapping_add(offset as u32),
+            }
+        }
+
+        pub(crate) fn as_u32(&self) -> u32 {
+            match *self {
+                JumpTarget::Absolute(target) => target,
+                JumpTarget::Relative(offset) => offset as u32,
+            }
+        }
+
+        pub(crate) fn is_relative(&self) -> bool {
+            matches!(self, JumpTarget::Relative(_))
+        }
+    }
+
+    impl Display for JumpTarget {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            match *self {
+                JumpTarget::Absolute(target) => write!(f, "0x{:x}", target),
+                JumpTarget::Relative(offset) if offset < 0 => write!(f, "$-{}", offset.unsigned_abs()),
+                JumpTarget::Relative(offset) => write!(f, "$+{}", offset),
+            }
+        }
+    }
+
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
     pub enum UnOp {
         NEG = 0b1000,
         NOT = 0b1001,
     }
+
+    impl UnOp {
+        pub fn mnemonic(&self) -> &'static str {
+            match self {
+                UnOp::NEG => "neg",
+                UnOp::NOT => "not",
+            }
+        }
+
+        pub fn symbol(&self) -> &'static str {
+            match self {
+                UnOp::NEG => "-",
+                UnOp::NOT => "~",
+            }
+        }
+    }
 }
 
 use instruction_parts::*;
+use std::fmt::{Display, Formatter};
 
-#[derive(Copy, Clone)]
+/// An error produced when a byte sequence cannot be decoded into an [`Instruction`]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DecodeError {
+    /// The instruction continues past the end of the available bytes
+    UnexpectedEnd,
+    /// The first byte of the instruction is not a valid opcode
+    InvalidOpcode(u8),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Instruction {
     Mov {
         src_ty: Type,
@@ -85,6 +420,7 @@ pub enum Instruction {
     Lod8(Location, u8),
     Lod16(Location, u16),
     Lod32(Location, u32),
+    Lod64(Location, u64),
     Halt,
     Memcpy {
         src: Location,
@@ -123,15 +459,64 @@ pub enum Instruction {
         src: Location,
         dst: Location,
     },
+    /// A binary operation whose right operand is a constant
+    BinOpImm {
+        op: BinOp,
+        ty: Type,
+        src: Location,
+        imm: Immediate,
+        dst: Location,
+    },
+    /// A comparison against a constant instead of zero
+    CmpImm {
+        ty: Type,
+        src: Location,
+        imm: Immediate,
+        dst: Location,
+        cnd: Condition,
+    },
+    /// A jump to a constant target. The condition compares against `imm`, or zero if there is
+    /// none.
+    JmpTo {
+        ty: Type,
+        src: Location,
+        target: JumpTarget,
+        cnd: Condition,
+        imm: Option<Immediate>,
+    },
     Input(Location),
     Output(Location),
+    /// Pushes the address of the next instruction and jumps to the target
+    Call(Location),
+    /// A call to a constant target
+    CallTo(JumpTarget),
+    /// Pops a return address and jumps to it
+    Ret,
+    Push(Type, Location),
+    Pop(Type, Location),
+    /// Calls the host function registered under the given number
+    Syscall(u16),
+    /// Calls the host function named by the given entry of the program's import table
+    CallHost(u16),
+    /// Frees the heap blocks that the program can no longer reach
+    Gc,
 }
 
 impl Instruction {
+    /// The first byte of every instruction in the extended opcode space, whose second byte
+    /// selects the operation
+    const EXTENDED_PREFIX: u8 = 0b01001111;
+
     pub fn output_to<F: FnMut(u8)>(&self, mut f: F) {
         use Instruction::*;
 
         match *self {
+            Mov { src_ty, dst_ty, src, dst } if !src_ty.is_base() || !dst_ty.is_base() => {
+                f(Self::EXTENDED_PREFIX);
+                f(0b00000110);
+                f(((src_ty as u8) << 4) | (dst_ty as u8));
+                f((src.as_u8() << 4) | dst.as_u8());
+            }
             Mov { src_ty, dst_ty, src, dst } => {
                 f(((src_ty as u8) << 2) | (dst_ty as u8));
                 f((src.as_u8() << 4) | dst.as_u8());
@@ -152,6 +537,12 @@ impl Instruction {
                 f((val >> 8) as u8);
                 f(val as u8);
             }
+            Lod64(loc, val) => {
+                f(Self::EXTENDED_PREFIX);
+                f(0b00001000);
+                f(loc.as_u8());
+                val.to_be_bytes().into_iter().for_each(&mut f);
+            }
             Halt => {
                 f(0b01000000);
             }
@@ -173,26 +564,481 @@ impl Instruction {
                 src2,
                 dst
             } => {
-                f(0b11000000u8 | ((ty as u8) << 4) | (op as u8));
+                Self::output_ternary(&mut f, op as u8, ty);
                 f((src1.as_u8() << 4) | src2.as_u8());
                 f(dst.as_u8() << 4);
             }
+            UnOp { op, ty, src, dst } if !ty.is_base() => {
+                f(Self::EXTENDED_PREFIX);
+                f(0b00000111);
+                f(((ty as u8) << 4) | (op as u8));
+                f((src.as_u8() << 4) | dst.as_u8());
+            }
             UnOp { op, ty, src, dst } => {
                 f(((op as u8) << 4) | ((ty as u8) << 2));
                 f((src.as_u8() << 4) | dst.as_u8());
             }
             Cmp { ty, src, dst, cnd } => {
-                f(0b11_00_1110_u8 | ((ty as u8) << 4));
+                Self::output_ternary(&mut f, 0b1110, ty);
                 f((src.as_u8() << 4) | (cnd as u8));
                 f(dst.as_u8() << 4);
             }
             Jmp { ty, src, tgt, cnd } => {
-                f(0b11_00_1111_u8 | ((ty as u8) << 4));
+                Self::output_ternary(&mut f, 0b1111, ty);
                 f((src.as_u8() << 4) | (cnd as u8));
                 f(tgt.as_u8() << 4);
             }
+            BinOpImm { op, ty, src, imm, dst } => {
+                f(Self::EXTENDED_PREFIX);
+                f(0b00010000 | (op as u8));
+                f(((ty as u8) << 4) | (imm.width_bits() << 2));
+                f((src.as_u8() << 4) | dst.as_u8());
+                imm.output_to(&mut f);
+            }
+            CmpImm { ty, src, imm, dst, cnd } => {
+                f(Self::EXTENDED_PREFIX);
+                f(0b00100000);
+                f(((ty as u8) << 4) | (imm.width_bits() << 2));
+                f((src.as_u8() << 4) | (cnd as u8));
+                f(dst.as_u8() << 4);
+                imm.output_to(&mut f);
+            }
+            JmpTo { ty, src, target, cnd, imm } => {
+                let width = imm.map_or(0b11, |imm| imm.width_bits());
+                f(Self::EXTENDED_PREFIX);
+                f(0b00100001);
+                f(((ty as u8) << 4) | (width << 2) | ((target.is_relative() as u8) << 1));
+                f((src.as_u8() << 4) | (cnd as u8));
+                target.as_u32().to_be_bytes().into_iter().for_each(&mut f);
+                if let Some(imm) = imm {
+                    imm.output_to(&mut f);
+                }
+            }
             Input(dst) => f(0b10100000 | dst.as_u8()),
             Output(src) => f(0b10110000 | src.as_u8()),
+            Call(tgt) => {
+                f(Self::EXTENDED_PREFIX);
+                f(0b00000010);
+                f(tgt.as_u8() << 4);
+            }
+            CallTo(target) => {
+                f(Self::EXTENDED_PREFIX);
+                f(0b00100010);
+                f((target.is_relative() as u8) << 1);
+                target.as_u32().to_be_bytes().into_iter().for_each(&mut f);
+            }
+            Ret => {
+                f(Self::EXTENDED_PREFIX);
+                f(0b00000011);
+            }
+            Push(ty, src) => {
+                f(Self::EXTENDED_PREFIX);
+                f(0b00000100);
+                f(((ty as u8) << 4) | src.as_u8());
+            }
+            Pop(ty, dst) => {
+                f(Self::EXTENDED_PREFIX);
+                f(0b00000101);
+                f(((ty as u8) << 4) | dst.as_u8());
+            }
+            Syscall(number) => {
+                f(Self::EXTENDED_PREFIX);
+                f(0b00000000);
+                f((number >> 8) as u8);
+                f(number as u8);
+            }
+            CallHost(index) => {
+                f(Self::EXTENDED_PREFIX);
+                f(0b00000001);
+                f((index >> 8) as u8);
+                f(index as u8);
+            }
+            Gc => {
+                f(Self::EXTENDED_PREFIX);
+                f(0b00001001);
+            }
+        }
+    }
+
+    /// Writes the opcode of a ternary instruction (a BINOP, CMP or JMP), which needs the extended
+    /// encoding if its type doesn't fit in two bits
+    fn output_ternary<F: FnMut(u8)>(f: &mut F, op: u8, ty: Type) {
+        if ty.is_base() {
+            f(0b11000000 | ((ty as u8) << 4) | op);
+        } else {
+            f(Self::EXTENDED_PREFIX);
+            f(0b00110000 | op);
+            f((ty as u8) << 4);
         }
     }
-}
\ No newline at end of file
+
+    /// Decodes the operands of a ternary instruction with the given operation bits
+    fn decode_ternary(op: u8, ty: Type, b2: u8, b3: u8, invalid: DecodeError) -> Result<Instruction, DecodeError> {
+        use Instruction::*;
+
+        Ok(match op & 0b1111 {
+            0b1110 => Cmp {
+                ty,
+                src: Location::from_bits(b2 >> 4),
+                dst: Location::from_bits(b3 >> 4),
+                cnd: Condition::from_bits(b2).ok_or(invalid)?,
+            },
+            0b1111 => Jmp {
+                ty,
+                src: Location::from_bits(b2 >> 4),
+                tgt: Location::from_bits(b3 >> 4),
+                cnd: Condition::from_bits(b2).ok_or(invalid)?,
+            },
+            op => BinOp {
+                op: instruction_parts::BinOp::from_bits(op).ok_or(invalid)?,
+                ty,
+                src1: Location::from_bits(b2 >> 4),
+                src2: Location::from_bits(b2),
+                dst: Location::from_bits(b3 >> 4),
+            },
+        })
+    }
+
+    /// Decodes the instruction at the start of `bytes`, returning it along with its encoded
+    /// length in bytes. This is the inverse of [`Instruction::output_to`].
+    pub fn decode(bytes: &[u8]) -> Result<(Instruction, usize), DecodeError> {
+        use Instruction::*;
+
+        let byte = |i: usize| bytes.get(i).copied().ok_or(DecodeError::UnexpectedEnd);
+        let b1 = byte(0)?;
+        let invalid = DecodeError::InvalidOpcode(b1);
+
+        if (b1 >> 6) == 0b11 {
+            // Ternary instructions
+            let ty = Type::from_bits(b1 >> 4);
+            let instruction = Self::decode_ternary(b1, ty, byte(1)?, byte(2)?, invalid)?;
+            return Ok((instruction, 3));
+        }
+
+        Ok(match b1 >> 4 {
+            0b0000 => {
+                let b2 = byte(1)?;
+                (Mov {
+                    src_ty: Type::from_bits(b1 >> 2),
+                    dst_ty: Type::from_bits(b1),
+                    src: Location::from_bits(b2 >> 4),
+                    dst: Location::from_bits(b2),
+                }, 2)
+            }
+            0b0001 => (Lod8(Location::from_bits(b1), byte(1)?), 2),
+            0b0010 => (
+                Lod16(Location::from_bits(b1), u16::from_be_bytes([byte(1)?, byte(2)?])),
+                3
+            ),
+            0b0011 => (
+                Lod32(
+                    Location::from_bits(b1),
+                    u32::from_be_bytes([byte(1)?, byte(2)?, byte(3)?, byte(4)?])
+                ),
+                5
+            ),
+            0b0100 if b1 == 0b01000000 => (Halt, 1),
+            0b0100 if b1 == Self::EXTENDED_PREFIX => {
+                let type_nibble = |b: u8| Type::from_nibble(b >> 4).ok_or(invalid);
+                let typed_location = |b: u8| Ok((type_nibble(b)?, Location::from_bits(b)));
+                let immediate = |start: usize, width: u8| {
+                    let size = Immediate::size_for_width(width).ok_or(invalid)?;
+                    let end = start + size;
+                    let bytes = bytes.get(start..end).ok_or(DecodeError::UnexpectedEnd)?;
+                    Ok((Immediate::from_bytes(bytes), end))
+                };
+                let jump_target = |b: u8, start: usize| {
+                    let value = u32::from_be_bytes([byte(start)?, byte(start + 1)?, byte(start + 2)?, byte(start + 3)?]);
+                    Ok(if (b & 0b10) != 0 { JumpTarget::Relative(value as i32) } else { JumpTarget::Absolute(value) })
+                };
+
+                let b2 = byte(1)?;
+                match b2 {
+                    _ if (b2 >> 4) == 0b0011 => {
+                        let ty = type_nibble(byte(2)?)?;
+                        (Self::decode_ternary(b2, ty, byte(3)?, byte(4)?, invalid)?, 5)
+                    }
+                    _ if (b2 >> 4) == 0b0001 => {
+                        let b3 = byte(2)?;
+                        let b4 = byte(3)?;
+                        let (imm, size) = immediate(4, b3 >> 2)?;
+                        (BinOpImm {
+                            op: instruction_parts::BinOp::from_bits(b2).ok_or(invalid)?,
+                            ty: type_nibble(b3)?,
+                            src: Location::from_bits(b4 >> 4),
+                            imm,
+                            dst: Location::from_bits(b4),
+                        }, size)
+                    }
+                    0b00100000 => {
+                        let b3 = byte(2)?;
+                        let b4 = byte(3)?;
+                        let (imm, size) = immediate(5, b3 >> 2)?;
+                        (CmpImm {
+                            ty: type_nibble(b3)?,
+                            src: Location::from_bits(b4 >> 4),
+                            imm,
+                            dst: Location::from_bits(byte(4)? >> 4),
+                            cnd: Condition::from_bits(b4).ok_or(invalid)?,
+                        }, size)
+                    }
+                    0b00100001 => {
+                        let b3 = byte(2)?;
+                        let b4 = byte(3)?;
+                        let target = jump_target(b3, 4)?;
+                        let (imm, size) = match (b3 >> 2) & 0b11 {
+                            0b11 => (None, 8),
+                            width => {
+                                let (imm, size) = immediate(8, width)?;
+                                (Some(imm), size)
+                            }
+                        };
+                        (JmpTo {
+                            ty: type_nibble(b3)?,
+                            src: Location::from_bits(b4 >> 4),
+                            target,
+                            cnd: Condition::from_bits(b4).ok_or(invalid)?,
+                            imm,
+                        }, size)
+                    }
+                    0b00100010 => (CallTo(jump_target(byte(2)?, 3)?), 7),
+                    0b00000000 => (Syscall(u16::from_be_bytes([byte(2)?, byte(3)?])), 4),
+                    0b00000001 => (CallHost(u16::from_be_bytes([byte(2)?, byte(3)?])), 4),
+                    0b00000010 => (Call(Location::from_bits(byte(2)? >> 4)), 3),
+                    0b00000011 => (Ret, 2),
+                    0b00001001 => (Gc, 2),
+                    0b00000100 => {
+                        let (ty, src) = typed_location(byte(2)?)?;
+                        (Push(ty, src), 3)
+                    }
+                    0b00000101 => {
+                        let (ty, dst) = typed_location(byte(2)?)?;
+                        (Pop(ty, dst), 3)
+                    }
+                    0b00001000 => {
+                        let value = bytes.get(3..11).ok_or(DecodeError::UnexpectedEnd)?;
+                        (Lod64(Location::from_bits(byte(2)?), u64::from_be_bytes(value.try_into().unwrap())), 11)
+                    }
+                    0b00000110 => {
+                        let b3 = byte(2)?;
+                        let b4 = byte(3)?;
+                        (Mov {
+                            src_ty: type_nibble(b3)?,
+                            dst_ty: type_nibble(b3 << 4)?,
+                            src: Location::from_bits(b4 >> 4),
+                            dst: Location::from_bits(b4),
+                        }, 4)
+                    }
+                    0b00000111 => {
+                        let b3 = byte(2)?;
+                        let b4 = byte(3)?;
+                        let op = match b3 & 0b1111 {
+                            0b1000 => instruction_parts::UnOp::NEG,
+                            0b1001 => instruction_parts::UnOp::NOT,
+                            _ => return Err(invalid),
+                        };
+                        (UnOp {
+                            op,
+                            ty: type_nibble(b3)?,
+                            src: Location::from_bits(b4 >> 4),
+                            dst: Location::from_bits(b4),
+                        }, 4)
+                    }
+                    _ => return Err(invalid)
+                }
+            }
+            0b0101 => {
+                let b2 = byte(1)?;
+                (Memcpy {
+                    src: Location::from_bits(b2 >> 4),
+                    dst: Location::from_bits(b2),
+                    size: Location::from_bits(b1),
+                }, 2)
+            }
+            0b0110 if b1 == 0b01100000 => {
+                let b2 = byte(1)?;
+                (New {
+                    size: Location::from_bits(b2 >> 4),
+                    dst: Location::from_bits(b2),
+                }, 2)
+            }
+            0b0111 => (Del { src: Location::from_bits(b1) }, 1),
+            0b1000 | 0b1001 => {
+                let b2 = byte(1)?;
+                (UnOp {
+                    op: if (b1 >> 4) == 0b1000 { instruction_parts::UnOp::NEG } else { instruction_parts::UnOp::NOT },
+                    ty: Type::from_bits(b1 >> 2),
+                    src: Location::from_bits(b2 >> 4),
+                    dst: Location::from_bits(b2),
+                }, 2)
+            }
+            0b1010 => (Input(Location::from_bits(b1)), 1),
+            0b1011 => (Output(Location::from_bits(b1)), 1),
+            _ => return Err(invalid)
+        })
+    }
+}
+
+/// Formats an instruction in the syntax accepted by the assembler
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.fmt_with_target(f, None)
+    }
+}
+
+impl Instruction {
+    /// Formats the instruction like its [`Display`] implementation, but with `target` in place of
+    /// the target of a direct jump or call
+    pub fn to_string_with_target(&self, target: &str) -> String {
+        struct WithTarget<'a>(&'a Instruction, &'a str);
+
+        impl Display for WithTarget<'_> {
+            fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                self.0.fmt_with_target(f, Some(self.1))
+            }
+        }
+
+        WithTarget(self, target).to_string()
+    }
+
+    /// The target of a direct jump or call
+    pub fn jump_target(&self) -> Option<JumpTarget> {
+        match *self {
+            Instruction::JmpTo { target, .. } | Instruction::CallTo(target) => Some(target),
+            _ => None,
+        }
+    }
+
+    /// The name of the operation, as written in assembly
+    pub fn mnemonic(&self) -> &'static str {
+        use Instruction::*;
+
+        match *self {
+            Mov { .. } => "mov",
+            Lod8(..) | Lod16(..) | Lod32(..) | Lod64(..) => "lod",
+            Halt => "halt",
+            Memcpy { .. } => "memcpy",
+            New { .. } => "new",
+            Del { .. } => "del",
+            Cmp { .. } | CmpImm { .. } => "cmp",
+            Jmp { .. } | JmpTo { .. } => "jmp",
+            BinOp { op, .. } | BinOpImm { op, .. } => op.mnemonic(),
+            UnOp { op, .. } => op.mnemonic(),
+            Input(_) => "input",
+            Output(_) => "output",
+            Call(_) | CallTo(_) => "call",
+            Ret => "ret",
+            Push(..) => "push",
+            Pop(..) => "pop",
+            Syscall(_) => "syscall",
+            CallHost(_) => "callhost",
+            Gc => "gc",
+        }
+    }
+
+    /// The type the instruction operates on, which for `mov` is the type it converts to
+    pub fn ty(&self) -> Option<Type> {
+        use Instruction::*;
+
+        match *self {
+            Mov { dst_ty, .. } => Some(dst_ty),
+            Lod8(..) => Some(Type::Int8),
+            Lod16(..) => Some(Type::Int16),
+            Lod32(..) => Some(Type::Int32),
+            Lod64(..) => Some(Type::Int64),
+            Cmp { ty, .. } | CmpImm { ty, .. } | Jmp { ty, .. } | JmpTo { ty, .. } => Some(ty),
+            BinOp { ty, .. } | BinOpImm { ty, .. } | UnOp { ty, .. } | Push(ty, _) | Pop(ty, _) => Some(ty),
+            Halt | Memcpy { .. } | New { .. } | Del { .. } | Input(_) | Output(_) | Call(_) | CallTo(_) | Ret
+            | Syscall(_) | CallHost(_) | Gc => None,
+        }
+    }
+
+    /// The operands the instruction reads, along with the type each is read as. Immediates and
+    /// the stack accesses of `CALL`, `RET` and `POP` are not included.
+    pub fn sources(&self) -> Vec<(Location, Type)> {
+        use Instruction::*;
+
+        match *self {
+            Mov { src_ty, src, .. } => vec![(src, src_ty)],
+            Memcpy { src, dst, size } => vec![(src, Type::Int32), (dst, Type::Int32), (size, Type::Int32)],
+            New { size, .. } => vec![(size, Type::Int32)],
+            Del { src } | Output(src) | Call(src) => vec![(src, Type::Int32)],
+            Cmp { cnd: Condition::ALWAYS, .. } => vec![],
+            Cmp { ty, src, .. } | CmpImm { ty, src, .. } => vec![(src, ty)],
+            Jmp { tgt, cnd: Condition::ALWAYS, .. } => vec![(tgt, Type::Int32)],
+            Jmp { ty, src, tgt, .. } => vec![(src, ty), (tgt, Type::Int32)],
+            JmpTo { cnd: Condition::ALWAYS, .. } => vec![],
+            JmpTo { ty, src, .. } => vec![(src, ty)],
+            BinOp { ty, src1, src2, .. } => vec![(src1, ty), (src2, ty)],
+            UnOp { ty, src, .. } | BinOpImm { ty, src, .. } | Push(ty, src) => vec![(src, ty)],
+            Lod8(..) | Lod16(..) | Lod32(..) | Lod64(..) | Halt | Input(_) | CallTo(_) | Ret | Pop(..)
+            | Syscall(_) | CallHost(_) | Gc => vec![],
+        }
+    }
+
+    /// The operand the instruction writes, along with the type it is written as
+    pub fn destination(&self) -> Option<(Location, Type)> {
+        use Instruction::*;
+
+        match *self {
+            Mov { dst_ty, dst, .. } => Some((dst, dst_ty)),
+            Lod8(dst, _) => Some((dst, Type::Int8)),
+            Lod16(dst, _) => Some((dst, Type::Int16)),
+            Lod32(dst, _) => Some((dst, Type::Int32)),
+            Lod64(dst, _) => Some((dst, Type::Int64)),
+            New { dst, .. } | Input(dst) => Some((dst, Type::Int32)),
+            Cmp { dst, .. } | CmpImm { dst, .. } => Some((dst, Type::Int8)),
+            BinOp { ty, dst, .. } | UnOp { ty, dst, .. } | BinOpImm { ty, dst, .. } | Pop(ty, dst) => Some((dst, ty)),
+            Halt | Memcpy { .. } | Del { .. } | Jmp { .. } | JmpTo { .. } | Output(_) | Call(_) | CallTo(_)
+            | Ret | Push(..) | Syscall(_) | CallHost(_) | Gc => None,
+        }
+    }
+
+    fn fmt_with_target(&self, f: &mut Formatter<'_>, target_text: Option<&str>) -> std::fmt::Result {
+        use Instruction::*;
+
+        let target_text = |target: JumpTarget| target_text.map_or_else(|| target.to_string(), str::to_owned);
+
+        match *self {
+            Mov { src_ty, dst_ty, src, dst } => write!(f, "mov {} {} <- {} {}", dst_ty, dst, src_ty, src),
+            Lod8(loc, val) => write!(f, "lod {} <- b {}", loc, val as i8),
+            Lod16(loc, val) => write!(f, "lod {} <- s {}", loc, val as i16),
+            Lod32(loc, val) => write!(f, "lod {} <- i {}", loc, val as i32),
+            Lod64(loc, val) => write!(f, "lod {} <- l {}", loc, val as i64),
+            Halt => write!(f, "halt"),
+            Memcpy { src, dst, size } => write!(f, "memcpy {} <- {} ; {}", dst, src, size),
+            New { size, dst } => write!(f, "new {} <- {}", dst, size),
+            Del { src } => write!(f, "del {}", src),
+            Cmp { ty, src, dst, cnd } => write!(f, "cmp {} <- {} {} {} 0", dst, ty, src, cnd),
+            Jmp { tgt, cnd: Condition::ALWAYS, .. } => write!(f, "jmp {} always", tgt),
+            Jmp { ty, src, tgt, cnd } => write!(f, "jmp {} if {} {} {} 0", tgt, ty, src, cnd),
+            BinOp { op, ty, src1, src2, dst } => write!(
+                f, "{} {} {} <- {} {} {}", op.mnemonic(), ty, dst, src1, op.symbol(), src2
+            ),
+            BinOpImm { op, ty, src, imm, dst } => write!(
+                f, "{} {} {} <- {} {} {}", op.mnemonic(), ty, dst, src, op.symbol(), imm.display(ty)
+            ),
+            CmpImm { ty, src, imm, dst, cnd } => write!(
+                f, "cmp {} <- {} {} {} {}", dst, ty, src, cnd, imm.display(ty)
+            ),
+            JmpTo { target, cnd: Condition::ALWAYS, .. } => write!(f, "jmp {} always", target_text(target)),
+            JmpTo { ty, src, target, cnd, imm } => write!(
+                f, "jmp {} if {} {} {} {}",
+                target_text(target), ty, src, cnd, imm.map_or_else(|| "0".to_owned(), |imm| imm.display(ty))
+            ),
+            UnOp { op, ty, src, dst } => write!(
+                f, "{} {} {} <- {} {}", op.mnemonic(), ty, dst, op.symbol(), src
+            ),
+            Input(dst) => write!(f, "input -> {}", dst),
+            Output(src) => write!(f, "output <- {}", src),
+            Call(tgt) => write!(f, "call {}", tgt),
+            CallTo(target) => write!(f, "call {}", target_text(target)),
+            Ret => write!(f, "ret"),
+            Push(ty, src) => write!(f, "push {} {}", ty, src),
+            Pop(ty, dst) => write!(f, "pop {} {}", ty, dst),
+            Syscall(number) => write!(f, "syscall {}", number),
+            CallHost(index) => write!(f, "callhost {}", index),
+            Gc => write!(f, "gc"),
+        }
+    }
+}
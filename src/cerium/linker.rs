@@ -0,0 +1,217 @@
+use crate::cerium::object::{ObjectFile, ObjectSection, RelocationTarget};
+use crate::cerium::program::{Program, Symbol};
+use crate::cerium::vm::{CeWord, RAM};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LinkError {
+    /// Two objects export symbols with the same name
+    DuplicateExport { name: String, first: String, second: String },
+    /// An object refers to a symbol that no object exports
+    UndefinedSymbol { object: String, name: String },
+    /// More than one object sets the entry point
+    MultipleEntryPoints { first: String, second: String },
+    /// A relocated value does not fit in the bytes set aside for it
+    ValueOutOfRange { object: String, section: ObjectSection, location: CeWord, value: i64, size: u8 },
+    /// A PC-relative value, like the target of a jump, refers to data instead of code
+    RelativeToData { object: String, location: CeWord },
+    /// The data of all of the objects does not fit in the static region
+    DataTooLarge,
+    /// An object contradicts itself, like a relocation outside of its section
+    InvalidObject { object: String, reason: &'static str },
+}
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkError::DuplicateExport { name, first, second } => write!(
+                f, "`{}` is exported by both {} and {}", name, first, second
+            ),
+            LinkError::UndefinedSymbol { object, name } => write!(
+                f, "{}: undefined symbol `{}`, which no object exports", object, name
+            ),
+            LinkError::MultipleEntryPoints { first, second } => write!(
+                f, "both {} and {} set the entry point", first, second
+            ),
+            LinkError::ValueOutOfRange { object, section, location, value, size } => write!(
+                f, "{}: the value {} at offset 0x{:08x} of the {} does not fit in {} byte{}",
+                object, value, location,
+                if *section == ObjectSection::Code { "code" } else { "data" },
+                size, if *size == 1 { "" } else { "s" }
+            ),
+            LinkError::RelativeToData { object, location } => write!(
+                f, "{}: the jump at offset 0x{:08x} of the code targets data, not code", object, location
+            ),
+            LinkError::DataTooLarge => write!(
+                f, "the data of the objects is larger than the {} bytes that a program can have", RAM::STATIC_PTR_BIT
+            ),
+            LinkError::InvalidObject { object, reason } => write!(f, "{}: invalid object file: {}", object, reason),
+        }
+    }
+}
+
+impl Error for LinkError {}
+
+/// Where an object's sections start in the linked program
+#[derive(Copy, Clone)]
+struct Placement {
+    code: CeWord,
+    data: CeWord,
+}
+
+impl Placement {
+    /// Moves an address that the object used as if it were the whole program to where the object
+    /// is placed
+    fn relocate(&self, address: CeWord) -> CeWord {
+        if address & RAM::STATIC_PTR_BIT != 0 {
+            address + self.data
+        } else {
+            address + self.code
+        }
+    }
+}
+
+/// Combines object files into a program
+pub struct Linker;
+
+impl Linker {
+    /// Links named object files into a program. Their code and data are placed in the order that
+    /// they are given in, and symbols that one object exports fill in the relocations of the
+    /// others.
+    pub fn link(objects: &[(String, ObjectFile)]) -> Result<Program, Vec<LinkError>> {
+        let mut errors = vec![];
+        let mut program = Program::default();
+
+        let mut placements = vec![];
+        for (_, object) in objects {
+            let data_start = program.rodata.len().next_multiple_of(object.data_alignment as usize);
+            program.rodata.resize(data_start, 0);
+            placements.push(Placement { code: program.code.len() as CeWord, data: data_start as CeWord });
+            program.code.extend(&object.code);
+            program.rodata.extend(&object.rodata);
+        }
+        if program.rodata.len() > RAM::STATIC_PTR_BIT as usize {
+            return Err(vec![LinkError::DataTooLarge]);
+        }
+
+        let mut exports: HashMap<&str, (&str, CeWord)> = HashMap::new();
+        let mut entry_object: Option<&str> = None;
+        for ((name, object), placement) in objects.iter().zip(&placements) {
+            for (symbol, address) in &object.symbols {
+                program.symbols.push(Symbol { name: symbol.clone(), address: placement.relocate(*address) });
+            }
+
+            for export in &object.exports {
+                let Some((_, address)) = object.symbols.iter().find(|(symbol, _)| symbol == export) else {
+                    errors.push(LinkError::InvalidObject { object: name.clone(), reason: "an export is not a symbol" });
+                    continue;
+                };
+                if let Some((first, _)) = exports.get(export.as_str()) {
+                    errors.push(LinkError::DuplicateExport {
+                        name: export.clone(),
+                        first: first.to_string(),
+                        second: name.clone(),
+                    });
+                    continue;
+                }
+                exports.insert(export, (name, placement.relocate(*address)));
+            }
+
+            if let Some(entry_point) = object.entry_point {
+                if let Some(first) = entry_object {
+                    errors.push(LinkError::MultipleEntryPoints { first: first.to_owned(), second: name.clone() });
+                }
+                entry_object = Some(name);
+                program.entry_point = placement.code + entry_point;
+            }
+
+            for import in &object.imports {
+                if !program.imports.contains(import) {
+                    program.imports.push(import.clone());
+                }
+            }
+        }
+
+        for (name, object) in objects {
+            for symbol in &object.externs {
+                if !exports.contains_key(symbol.as_str()) {
+                    errors.push(LinkError::UndefinedSymbol { object: name.clone(), name: symbol.clone() });
+                }
+            }
+        }
+
+        for ((name, object), placement) in objects.iter().zip(&placements) {
+            for relocation in &object.relocations {
+                let target = match &relocation.target {
+                    RelocationTarget::Section(ObjectSection::Code) => placement.code as i64,
+                    RelocationTarget::Section(ObjectSection::Data) => placement.data as i64,
+                    RelocationTarget::Symbol(symbol) => match exports.get(symbol.as_str()) {
+                        Some((_, address)) => *address as i64,
+                        // Undeclared externs are reported here, and declared ones above
+                        None if object.externs.contains(symbol) => continue,
+                        None => {
+                            errors.push(LinkError::UndefinedSymbol { object: name.clone(), name: symbol.clone() });
+                            continue;
+                        }
+                    },
+                    RelocationTarget::HostFunction(function) => {
+                        program.imports.iter().position(|import| import == function).unwrap_or(0) as i64
+                    }
+                    RelocationTarget::Absolute => 0,
+                };
+
+                let mut value = target.wrapping_add(relocation.addend);
+                if let Some(base) = relocation.relative_to {
+                    if value & RAM::STATIC_PTR_BIT as i64 != 0 {
+                        errors.push(LinkError::RelativeToData { object: name.clone(), location: relocation.location });
+                        continue;
+                    }
+                    value -= (placement.code + base) as i64;
+                }
+
+                let range = match relocation.size {
+                    1 => -0x80..=0xff,
+                    2 => -0x8000..=0xffff,
+                    _ => -0x8000_0000..=0xffff_ffff,
+                };
+                if !range.contains(&value) {
+                    errors.push(LinkError::ValueOutOfRange {
+                        object: name.clone(),
+                        section: relocation.section,
+                        location: relocation.location,
+                        value,
+                        size: relocation.size,
+                    });
+                    continue;
+                }
+
+                let (buffer, start) = match relocation.section {
+                    ObjectSection::Code => (&mut program.code, placement.code),
+                    ObjectSection::Data => (&mut program.rodata, placement.data),
+                };
+                let size = relocation.size as usize;
+                let start = (start + relocation.location) as usize;
+                let section_length = match relocation.section {
+                    ObjectSection::Code => object.code.len(),
+                    ObjectSection::Data => object.rodata.len(),
+                };
+                if relocation.location as usize + size > section_length || !matches!(size, 1 | 2 | 4) {
+                    errors.push(LinkError::InvalidObject {
+                        object: name.clone(),
+                        reason: "a relocation lies outside of its section",
+                    });
+                    continue;
+                }
+                buffer[start..start + size].copy_from_slice(&(value as u32).to_be_bytes()[4 - size..]);
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        program.symbols.sort_by_key(|symbol| symbol.address);
+        Ok(program)
+    }
+}
@@ -12,13 +12,14 @@ impl<T: EndianConversion> MemoryBufferPtr<T> {
     pub fn ptr(&self) -> *mut T {
         self.ptr
     }
+    /// Values in guest memory can start at any byte, so accesses don't assume alignment
     #[inline(always)]
     pub unsafe fn write(&mut self, val: T) {
-        self.ptr.write(val.to_big_endian())
+        self.ptr.write_unaligned(val.to_big_endian())
     }
     #[inline(always)]
     pub fn get(&mut self) -> T {
-        unsafe { T::from_big_endian(&self.ptr.cast::<T>().read()) }
+        unsafe { T::from_big_endian(&self.ptr.cast::<T>().read_unaligned()) }
     }
 }
 
@@ -79,15 +80,15 @@ impl MemoryBuffer {
     }
 }
 
-impl Into<Box<[u8]>> for MemoryBuffer {
-    fn into(self) -> Box<[u8]> {
-        self.memory.into_boxed_slice()
+impl From<MemoryBuffer> for Box<[u8]> {
+    fn from(value: MemoryBuffer) -> Box<[u8]> {
+        value.memory.into_boxed_slice()
     }
 }
 
-impl<'a> Into<&'a [u8]> for &'a MemoryBuffer {
-    fn into(self) -> &'a [u8] {
-        self.memory.as_slice()
+impl<'a> From<&'a MemoryBuffer> for &'a [u8] {
+    fn from(value: &'a MemoryBuffer) -> &'a [u8] {
+        value.memory.as_slice()
     }
 }
 
@@ -114,6 +115,15 @@ impl EndianConversion for i16 {
     }
 }
 
+impl EndianConversion for u16 {
+    fn from_big_endian(value: &Self) -> Self {
+        Self::from_be(*value)
+    }
+    fn to_big_endian(&self) -> Self {
+        self.to_be()
+    }
+}
+
 impl EndianConversion for i32 {
     fn from_big_endian(value: &Self) -> Self {
         Self::from_be(*value)
@@ -132,4 +142,29 @@ impl EndianConversion for u32 {
     }
 }
 
-impl EndianConversion for f32 {}
\ No newline at end of file
+impl EndianConversion for i64 {
+    fn from_big_endian(value: &Self) -> Self {
+        Self::from_be(*value)
+    }
+    fn to_big_endian(&self) -> Self {
+        self.to_be()
+    }
+}
+
+impl EndianConversion for f32 {
+    fn from_big_endian(value: &Self) -> Self {
+        Self::from_bits(u32::from_be(value.to_bits()))
+    }
+    fn to_big_endian(&self) -> Self {
+        Self::from_bits(self.to_bits().to_be())
+    }
+}
+
+impl EndianConversion for f64 {
+    fn from_big_endian(value: &Self) -> Self {
+        Self::from_bits(u64::from_be(value.to_bits()))
+    }
+    fn to_big_endian(&self) -> Self {
+        Self::from_bits(self.to_bits().to_be())
+    }
+}
\ No newline at end of file
@@ -1,5 +1,12 @@
 pub mod vm;
 pub mod assembler;
-mod instruction;
+pub mod debugger;
+pub mod disassembler;
+pub mod profiler;
+pub mod instruction;
+pub mod program;
+pub mod object;
+pub mod linker;
+pub mod symbol_map;
 mod memory_buffer;
-mod compiler;
\ No newline at end of file
+pub mod compiler;
\ No newline at end of file
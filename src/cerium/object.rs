@@ -0,0 +1,315 @@
+//! The `.o` relocatable object format, which holds a piece of a program that the linker combines
+//! with others into a `.ce` program.
+//!
+//! All integers are big-endian. An object file starts with a fixed header:
+//!
+//! ```text
+//! magic           4 bytes   "CEOB"
+//! version         u16       must equal ObjectFile::FORMAT_VERSION
+//! flags           u16       bit 0 is set if the object has an entry point
+//! ```
+//!
+//! followed by the contents of the object, where `bytes` is a `u32` length followed by that many
+//! bytes, a `name` is a `u16` length followed by that many bytes of UTF-8, and a list is a `u32`
+//! count followed by that many entries:
+//!
+//! ```text
+//! entry point     u32       offset into the code, or 0 if there is none
+//! code            bytes
+//! read-only data  bytes
+//! data alignment  u32       the alignment that the data needs, a power of two
+//! symbols         list of `address: u32, name`
+//! exports         list of `name`
+//! externs         list of `name`
+//! imports         list of `name`
+//! relocations     list of relocations
+//! ```
+//!
+//! Addresses are laid out as if the object were the whole program: code starts at 0, and data at
+//! [`RAM::STATIC_PTR_BIT`](crate::cerium::vm::RAM::STATIC_PTR_BIT). Each relocation is
+//! `section: u8, location: u32, size: u8, target kind: u8, target name, addend: u64, relative: u8,
+//! relative to: u32`. The section is 1 for code and 2 for data, and the target kind is 1 for the
+//! start of the object's code, 2 for the start of its data, 3 for an exported symbol, 4 for a host
+//! function and 5 for no target. Names are empty for targets other than symbols and host
+//! functions, and `relative to` is 0 unless `relative` is 1.
+
+use crate::cerium::vm::CeWord;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// A section of an object that relocations can be in or refer to
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ObjectSection {
+    Code = 1,
+    Data = 2,
+}
+
+/// What the value of a relocation is computed from
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RelocationTarget {
+    /// The address that the object's code or data starts at once it is linked
+    Section(ObjectSection),
+    /// A symbol that another object exports
+    Symbol(String),
+    /// The index of a host function in the linked program's import table
+    HostFunction(String),
+    /// Nothing, so that the value is the addend
+    Absolute,
+}
+
+/// A value in an object that can only be filled in when the object is linked
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Relocation {
+    /// Which section the value is in
+    pub section: ObjectSection,
+    /// Where the value starts in its section
+    pub location: CeWord,
+    /// The size of the value: 1, 2 or 4 bytes
+    pub size: u8,
+    pub target: RelocationTarget,
+    /// What is added to the address of the target
+    pub addend: i64,
+    /// For PC-relative values, the address in the object's code that the value is relative to
+    pub relative_to: Option<CeWord>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ObjectFile {
+    /// The offset into the code that execution starts at, if the object sets the entry point
+    pub entry_point: Option<CeWord>,
+    pub code: Vec<u8>,
+    pub rodata: Vec<u8>,
+    /// The alignment that the start of the data must have, a power of two
+    pub data_alignment: CeWord,
+    /// The labels of the object, by name, including the ones that are not exported
+    pub symbols: Vec<(String, CeWord)>,
+    /// The names of the symbols that other objects can refer to
+    pub exports: Vec<String>,
+    /// The names of the symbols that the object expects other objects to export
+    pub externs: Vec<String>,
+    /// The names of the host functions that the object calls
+    pub imports: Vec<String>,
+    pub relocations: Vec<Relocation>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ObjectFormatError {
+    /// The file does not start with [`ObjectFile::MAGIC`]
+    BadMagic,
+    /// The file was written for a different version of the format
+    UnsupportedVersion(u16),
+    /// The file ended in the middle of its contents
+    Truncated,
+    /// A symbol or import name is not valid UTF-8
+    InvalidSymbolName,
+    /// The file describes something that an object could never contain
+    Invalid(&'static str),
+    /// The file continues after the end of the object
+    TrailingBytes,
+}
+
+impl Display for ObjectFormatError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObjectFormatError::BadMagic => write!(f, "not an object file (bad magic bytes)"),
+            ObjectFormatError::UnsupportedVersion(version) => write!(
+                f, "unsupported object file version {} (expected {})", version, ObjectFile::FORMAT_VERSION
+            ),
+            ObjectFormatError::Truncated => write!(f, "file is truncated"),
+            ObjectFormatError::InvalidSymbolName => write!(f, "symbol or import name is not valid UTF-8"),
+            ObjectFormatError::Invalid(reason) => write!(f, "invalid object file: {}", reason),
+            ObjectFormatError::TrailingBytes => write!(f, "trailing bytes after the object"),
+        }
+    }
+}
+
+impl Error for ObjectFormatError {}
+
+/// Reads big-endian values from an object file
+struct ObjectReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ObjectReader<'a> {
+    fn raw(&mut self, length: usize) -> Result<&'a [u8], ObjectFormatError> {
+        let end = self.position.checked_add(length).ok_or(ObjectFormatError::Truncated)?;
+        let result = self.bytes.get(self.position..end).ok_or(ObjectFormatError::Truncated)?;
+        self.position = end;
+        Ok(result)
+    }
+
+    fn u8(&mut self) -> Result<u8, ObjectFormatError> {
+        Ok(self.raw(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ObjectFormatError> {
+        Ok(u16::from_be_bytes(self.raw(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, ObjectFormatError> {
+        Ok(u32::from_be_bytes(self.raw(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, ObjectFormatError> {
+        Ok(u64::from_be_bytes(self.raw(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], ObjectFormatError> {
+        let length = self.u32()? as usize;
+        self.raw(length)
+    }
+
+    fn name(&mut self) -> Result<String, ObjectFormatError> {
+        let length = self.u16()? as usize;
+        let name = std::str::from_utf8(self.raw(length)?).map_err(|_| ObjectFormatError::InvalidSymbolName)?;
+        Ok(name.to_owned())
+    }
+
+    fn names(&mut self) -> Result<Vec<String>, ObjectFormatError> {
+        (0..self.u32()?).map(|_| self.name()).collect()
+    }
+
+    fn section(&mut self) -> Result<ObjectSection, ObjectFormatError> {
+        match self.u8()? {
+            1 => Ok(ObjectSection::Code),
+            2 => Ok(ObjectSection::Data),
+            _ => Err(ObjectFormatError::Invalid("unknown relocation section")),
+        }
+    }
+
+    fn relocation(&mut self) -> Result<Relocation, ObjectFormatError> {
+        let section = self.section()?;
+        let location = self.u32()?;
+        let size = self.u8()?;
+        if !matches!(size, 1 | 2 | 4) {
+            return Err(ObjectFormatError::Invalid("relocation size is not 1, 2 or 4"));
+        }
+        let kind = self.u8()?;
+        let name = self.name()?;
+        let target = match kind {
+            1 => RelocationTarget::Section(ObjectSection::Code),
+            2 => RelocationTarget::Section(ObjectSection::Data),
+            3 => RelocationTarget::Symbol(name),
+            4 => RelocationTarget::HostFunction(name),
+            5 => RelocationTarget::Absolute,
+            _ => return Err(ObjectFormatError::Invalid("unknown relocation target")),
+        };
+        let addend = self.u64()? as i64;
+        let relative = self.u8()?;
+        let relative_to = self.u32()?;
+        let relative_to = match relative {
+            0 => None,
+            1 => Some(relative_to),
+            _ => return Err(ObjectFormatError::Invalid("relative flag is neither 0 nor 1")),
+        };
+
+        Ok(Relocation { section, location, size, target, addend, relative_to })
+    }
+}
+
+impl ObjectFile {
+    pub const MAGIC: [u8; 4] = *b"CEOB";
+    pub const FORMAT_VERSION: u16 = 1;
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<ObjectFile, ObjectFormatError> {
+        let mut reader = ObjectReader { bytes, position: 0 };
+        if reader.raw(4).map_err(|_| ObjectFormatError::BadMagic)? != Self::MAGIC {
+            return Err(ObjectFormatError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version != Self::FORMAT_VERSION {
+            return Err(ObjectFormatError::UnsupportedVersion(version));
+        }
+        let flags = reader.u16()?;
+
+        let entry_point = reader.u32()?;
+        let code = reader.bytes()?.to_vec();
+        let rodata = reader.bytes()?.to_vec();
+        let data_alignment = reader.u32()?;
+        let symbols = (0..reader.u32()?)
+            .map(|_| {
+                let address = reader.u32()?;
+                Ok((reader.name()?, address))
+            })
+            .collect::<Result<_, _>>()?;
+        let exports = reader.names()?;
+        let externs = reader.names()?;
+        let imports = reader.names()?;
+        let relocations = (0..reader.u32()?).map(|_| reader.relocation()).collect::<Result<_, _>>()?;
+
+        if reader.position != bytes.len() {
+            return Err(ObjectFormatError::TrailingBytes);
+        }
+        if !data_alignment.is_power_of_two() {
+            return Err(ObjectFormatError::Invalid("data alignment is not a power of two"));
+        }
+        let entry_point = (flags & 1 != 0).then_some(entry_point);
+        if entry_point.is_some_and(|entry_point| entry_point as usize >= code.len()) {
+            return Err(ObjectFormatError::Invalid("entry point lies outside of the code"));
+        }
+
+        Ok(ObjectFile {
+            entry_point,
+            code,
+            rodata,
+            data_alignment,
+            symbols,
+            exports,
+            externs,
+            imports,
+            relocations,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Self::MAGIC.to_vec();
+        bytes.extend(Self::FORMAT_VERSION.to_be_bytes());
+        bytes.extend((self.entry_point.is_some() as u16).to_be_bytes());
+        bytes.extend(self.entry_point.unwrap_or(0).to_be_bytes());
+
+        let write_bytes = |bytes: &mut Vec<u8>, data: &[u8]| {
+            bytes.extend((data.len() as u32).to_be_bytes());
+            bytes.extend(data);
+        };
+        write_bytes(&mut bytes, &self.code);
+        write_bytes(&mut bytes, &self.rodata);
+        bytes.extend(self.data_alignment.to_be_bytes());
+
+        let write_name = |bytes: &mut Vec<u8>, name: &str| {
+            bytes.extend((name.len() as u16).to_be_bytes());
+            bytes.extend(name.as_bytes());
+        };
+        bytes.extend((self.symbols.len() as u32).to_be_bytes());
+        for (name, address) in &self.symbols {
+            bytes.extend(address.to_be_bytes());
+            write_name(&mut bytes, name);
+        }
+        for names in [&self.exports, &self.externs, &self.imports] {
+            bytes.extend((names.len() as u32).to_be_bytes());
+            for name in names {
+                write_name(&mut bytes, name);
+            }
+        }
+
+        bytes.extend((self.relocations.len() as u32).to_be_bytes());
+        for relocation in &self.relocations {
+            bytes.push(relocation.section as u8);
+            bytes.extend(relocation.location.to_be_bytes());
+            bytes.push(relocation.size);
+            let (kind, name) = match &relocation.target {
+                RelocationTarget::Section(section) => (*section as u8, ""),
+                RelocationTarget::Symbol(name) => (3, name.as_str()),
+                RelocationTarget::HostFunction(name) => (4, name.as_str()),
+                RelocationTarget::Absolute => (5, ""),
+            };
+            bytes.push(kind);
+            write_name(&mut bytes, name);
+            bytes.extend((relocation.addend as u64).to_be_bytes());
+            bytes.push(relocation.relative_to.is_some() as u8);
+            bytes.extend(relocation.relative_to.unwrap_or(0).to_be_bytes());
+        }
+
+        bytes
+    }
+}
@@ -0,0 +1,277 @@
+use crate::cerium::instruction::instruction_parts::{Condition, Type};
+use crate::cerium::instruction::Instruction;
+use crate::cerium::program::Program;
+use crate::cerium::vm::{CeWord, ExecutionObserver, InstructionEvent, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+/// A call in progress, as far as the profiler can tell
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+struct Frame {
+    /// The address the call jumped to
+    function: CeWord,
+    /// The stack pointer right after the call
+    sp: CeWord,
+}
+
+/// An [`ExecutionObserver`] that counts what a program executes, for finding hot spots.
+///
+/// Calls are tracked to produce folded stacks. `CALL` instructions are calls and `RET`
+/// instructions return from them. Code that predates those instructions calls with `jmp rN
+/// always` after growing the stack, and returns with `jmp @rN always`, so register jumps that
+/// grow the stack past the current frame count as calls too, and a `jmp @rN always` returns from
+/// every frame that the stack pointer has shrunk back to.
+#[derive(Default)]
+pub struct Profiler {
+    frames: Vec<Frame>,
+    /// Every distinct list of frames seen, by the ID that the counts refer to them with
+    stacks: Vec<Vec<Frame>>,
+    stack_ids: HashMap<Vec<Frame>, usize>,
+    current_stack: usize,
+    /// How often each instruction was executed, by stack ID and address
+    counts: HashMap<(usize, CeWord), u64>,
+    /// The instruction last executed at each address
+    instructions: HashMap<CeWord, Instruction>,
+    mnemonics: BTreeMap<&'static str, u64>,
+    /// How many instructions operated on each type, indexed by the type's encoding
+    types: [u64; 9],
+    memory_reads: u64,
+    bytes_read: u64,
+    memory_writes: u64,
+    bytes_written: u64,
+    allocations: u64,
+    bytes_allocated: u64,
+    frees: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The total number of instructions executed
+    pub fn instruction_count(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    fn enter_stack(&mut self) {
+        self.current_stack = match self.stack_ids.get(&self.frames) {
+            Some(&id) => id,
+            None => {
+                let id = self.stacks.len();
+                self.stacks.push(self.frames.clone());
+                self.stack_ids.insert(self.frames.clone(), id);
+                id
+            }
+        };
+    }
+
+    /// Pops the frames that the stack pointer has shrunk back past, or back to if `inclusive`,
+    /// but never the outermost one
+    fn return_to(&mut self, sp: CeWord, inclusive: bool) {
+        while self.frames.len() > 1 {
+            let top = self.frames[self.frames.len() - 1];
+            if top.sp > sp || (inclusive && top.sp == sp) {
+                self.frames.pop();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn track_calls(&mut self, event: &InstructionEvent) {
+        let frames_before = self.frames.len();
+        let top_sp = self.frames.last().map_or(0, |frame| frame.sp);
+
+        match event.instruction {
+            Instruction::Call(_) | Instruction::CallTo(_) => {
+                self.frames.push(Frame { function: event.next_ip, sp: event.sp });
+            }
+            Instruction::Ret => self.return_to(event.sp, false),
+            Instruction::Jmp { tgt, cnd: Condition::ALWAYS, .. } => {
+                if tgt.indirect {
+                    self.return_to(event.sp, true);
+                } else if event.sp > top_sp {
+                    self.frames.push(Frame { function: event.next_ip, sp: event.sp });
+                }
+            }
+            _ => return,
+        }
+
+        if self.frames.len() != frames_before {
+            self.enter_stack();
+        }
+    }
+
+    fn count_memory_accesses(&mut self, event: &InstructionEvent) {
+        let mut read = |bytes: u32| {
+            self.memory_reads += 1;
+            self.bytes_read += u64::from(bytes);
+        };
+        for source in event.sources.iter().filter(|source| source.location.indirect) {
+            read(source.ty.size());
+        }
+        match event.instruction {
+            Instruction::Pop(ty, _) => read(ty.size()),
+            Instruction::Ret => read(4),
+            _ => {}
+        }
+
+        let mut write = |bytes: u32| {
+            self.memory_writes += 1;
+            self.bytes_written += u64::from(bytes);
+        };
+        if let Some(destination) = event.destination.filter(|destination| destination.location.indirect) {
+            write(destination.ty.size());
+        }
+        match event.instruction {
+            Instruction::Push(ty, _) => write(ty.size()),
+            Instruction::Call(_) | Instruction::CallTo(_) => write(4),
+            _ => {}
+        }
+
+        if let Instruction::Memcpy { .. } = event.instruction {
+            if let Some(Value::Int(size)) = event.sources[2].value {
+                self.memory_reads += 1;
+                self.bytes_read += size as u32 as u64;
+                self.memory_writes += 1;
+                self.bytes_written += size as u32 as u64;
+            }
+        }
+    }
+
+    /// A human-readable summary of where the program spent its time. Addresses are attributed to
+    /// the nearest label before them in `program`.
+    pub fn report(&self, program: &Program) -> String {
+        let total = self.instruction_count();
+        let percent = |count: u64| if total == 0 { 0.0 } else { count as f64 * 100.0 / total as f64 };
+        let mut report = String::new();
+
+        let mut by_address: BTreeMap<CeWord, u64> = BTreeMap::new();
+        for (&(_, address), &count) in &self.counts {
+            *by_address.entry(address).or_default() += count;
+        }
+        let mut by_label: HashMap<String, u64> = HashMap::new();
+        for (&address, &count) in &by_address {
+            *by_label.entry(label_of(program, address)).or_default() += count;
+        }
+
+        writeln!(report, "Executed {} instructions", total).unwrap();
+
+        writeln!(report, "\nHot spots by label:").unwrap();
+        let mut labels: Vec<_> = by_label.into_iter().collect();
+        labels.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        for (label, count) in labels {
+            writeln!(report, "  {:>12}  {:>6.2}%  {}", count, percent(count), label).unwrap();
+        }
+
+        writeln!(report, "\nHot spots by address:").unwrap();
+        let mut addresses: Vec<_> = by_address.into_iter().collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        for (address, count) in addresses.into_iter().take(Self::HOT_ADDRESSES) {
+            writeln!(
+                report, "  {:>12}  {:>6.2}%  0x{:08x} {:<24} {}",
+                count, percent(count), address, describe_address(program, address),
+                display_instruction(program, address, &self.instructions[&address])
+            ).unwrap();
+        }
+
+        writeln!(report, "\nInstruction mix by opcode:").unwrap();
+        let mut mnemonics: Vec<_> = self.mnemonics.iter().collect();
+        mnemonics.sort_by(|a, b| b.1.cmp(a.1));
+        for (mnemonic, &count) in mnemonics {
+            writeln!(report, "  {:>12}  {:>6.2}%  {}", count, percent(count), mnemonic).unwrap();
+        }
+
+        writeln!(report, "\nInstruction mix by type:").unwrap();
+        for (index, &count) in self.types.iter().enumerate().filter(|(_, &count)| count > 0) {
+            let ty = Type::from_nibble(index as u8).unwrap();
+            writeln!(report, "  {:>12}  {:>6.2}%  {:?} ({})", count, percent(count), ty, ty).unwrap();
+        }
+
+        writeln!(report, "\nMemory:").unwrap();
+        writeln!(report, "  {:>12}  reads ({} bytes)", self.memory_reads, self.bytes_read).unwrap();
+        writeln!(report, "  {:>12}  writes ({} bytes)", self.memory_writes, self.bytes_written).unwrap();
+        writeln!(report, "  {:>12}  allocations ({} bytes)", self.allocations, self.bytes_allocated).unwrap();
+        writeln!(report, "  {:>12}  frees", self.frees).unwrap();
+
+        report
+    }
+
+    /// The instruction counts as folded stacks, one `outer;inner;label count` line per stack,
+    /// which flamegraph tools accept as input
+    pub fn folded_stacks(&self, program: &Program) -> String {
+        let mut folded: BTreeMap<String, u64> = BTreeMap::new();
+        for (&(stack, address), &count) in &self.counts {
+            let mut names: Vec<String> = self.stacks[stack].iter()
+                .map(|frame| label_of(program, frame.function))
+                .collect();
+            let label = label_of(program, address);
+            if names.last() != Some(&label) {
+                names.push(label);
+            }
+            *folded.entry(names.join(";")).or_default() += count;
+        }
+
+        folded.into_iter().map(|(stack, count)| format!("{} {}\n", stack, count)).collect()
+    }
+
+    /// How many of the most executed addresses [`Self::report`] lists
+    const HOT_ADDRESSES: usize = 20;
+}
+
+impl ExecutionObserver for Profiler {
+    fn on_instruction(&mut self, event: &InstructionEvent) {
+        if self.frames.is_empty() {
+            self.frames.push(Frame { function: event.ip, sp: 0 });
+            self.enter_stack();
+        }
+
+        *self.counts.entry((self.current_stack, event.ip)).or_default() += 1;
+        self.instructions.insert(event.ip, event.instruction);
+        *self.mnemonics.entry(event.instruction.mnemonic()).or_default() += 1;
+        if let Some(ty) = event.instruction.ty() {
+            self.types[ty as usize] += 1;
+        }
+        self.count_memory_accesses(event);
+
+        match event.instruction {
+            Instruction::New { .. } => {
+                self.allocations += 1;
+                if let Some(Value::Int(size)) = event.sources[0].value {
+                    self.bytes_allocated += size as u32 as u64;
+                }
+            }
+            Instruction::Del { .. } => self.frees += 1,
+            _ => {}
+        }
+
+        self.track_calls(event);
+    }
+}
+
+fn label_of(program: &Program, address: CeWord) -> String {
+    match program.nearest_symbol(address) {
+        Some((symbol, _)) => symbol.name.clone(),
+        None => format!("0x{:08x}", address),
+    }
+}
+
+/// Shows direct jump and call targets by their label
+fn display_instruction(program: &Program, address: CeWord, instruction: &Instruction) -> String {
+    match instruction.jump_target().map(|target| target.resolve(address)) {
+        Some(target) => match describe_address(program, target) {
+            description if description.is_empty() => instruction.to_string_with_target(&format!("0x{:08x}", target)),
+            description => instruction.to_string_with_target(&description),
+        },
+        None => instruction.to_string(),
+    }
+}
+
+fn describe_address(program: &Program, address: CeWord) -> String {
+    match program.nearest_symbol(address) {
+        Some((symbol, 0)) => symbol.name.clone(),
+        Some((symbol, offset)) => format!("{}+0x{:x}", symbol.name, offset),
+        None => String::new(),
+    }
+}
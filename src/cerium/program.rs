@@ -0,0 +1,326 @@
+//! The `.ce` container format.
+//!
+//! All integers are big-endian. A file starts with a fixed header:
+//!
+//! ```text
+//! magic           4 bytes   "CEVM"
+//! version         u16       must equal Program::FORMAT_VERSION
+//! reserved        u16       written as 0
+//! entry point     u32       offset into the code section
+//! section count   u32
+//! ```
+//!
+//! followed by `section count` section headers of the form `kind: u32, offset: u32, length: u32`,
+//! where `offset` is relative to the start of the file. Each section kind may appear at most
+//! once, and the code section is required.
+//!
+//! The read-only data section holds raw bytes that the VM maps to guest addresses starting at
+//! [`RAM::STATIC_PTR_BIT`](crate::cerium::vm::RAM::STATIC_PTR_BIT) when it loads the program.
+//!
+//! The symbol section is a `u32` count followed by `address: u32, name length: u16, name` entries,
+//! and the debug section is a `u32` count followed by `address: u32, line: u32` entries that map
+//! the start of each instruction to the (1-based) source line it was assembled from.
+//!
+//! The import section is a `u32` count followed by `name length: u16, name` entries naming the
+//! host functions that the program calls with `CALLHOST`, which refers to them by index.
+
+use crate::cerium::symbol_map;
+use crate::cerium::vm::CeWord;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SectionKind {
+    Code = 1,
+    ReadOnlyData = 2,
+    Symbols = 3,
+    Debug = 4,
+    Imports = 5,
+}
+
+impl SectionKind {
+    fn from_u32(value: u32) -> Option<SectionKind> {
+        use SectionKind::*;
+        Some(match value {
+            1 => Code,
+            2 => ReadOnlyData,
+            3 => Symbols,
+            4 => Debug,
+            5 => Imports,
+            _ => return None
+        })
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub address: CeWord,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LineInfo {
+    pub address: CeWord,
+    pub line: u32,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Program {
+    pub entry_point: CeWord,
+    pub code: Vec<u8>,
+    pub rodata: Vec<u8>,
+    /// Sorted by address. Labels at the same address are in the order they are defined in.
+    pub symbols: Vec<Symbol>,
+    pub line_info: Vec<LineInfo>,
+    /// The names of the host functions called by `CALLHOST`
+    pub imports: Vec<String>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FormatError {
+    /// The file does not start with [`Program::MAGIC`]
+    BadMagic,
+    /// The file was written for a different version of the format
+    UnsupportedVersion(u16),
+    /// The file ended in the middle of a header or section
+    Truncated,
+    UnknownSection(u32),
+    DuplicateSection(SectionKind),
+    MissingCodeSection,
+    /// A section header points outside of the file
+    SectionOutOfBounds(SectionKind),
+    EntryPointOutOfBounds(CeWord),
+    /// A symbol or import name is not valid UTF-8
+    InvalidSymbolName,
+    /// A section contains bytes after its last entry
+    TrailingBytes(SectionKind),
+}
+
+impl Display for FormatError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormatError::BadMagic => write!(f, "not a .ce file (bad magic bytes)"),
+            FormatError::UnsupportedVersion(version) => write!(
+                f, "unsupported .ce format version {} (expected {})", version, Program::FORMAT_VERSION
+            ),
+            FormatError::Truncated => write!(f, "file is truncated"),
+            FormatError::UnknownSection(kind) => write!(f, "unknown section kind {}", kind),
+            FormatError::DuplicateSection(kind) => write!(f, "duplicate {:?} section", kind),
+            FormatError::MissingCodeSection => write!(f, "missing code section"),
+            FormatError::SectionOutOfBounds(kind) => write!(f, "{:?} section lies outside of the file", kind),
+            FormatError::EntryPointOutOfBounds(entry) => write!(
+                f, "entry point 0x{:08x} lies outside of the code section", entry
+            ),
+            FormatError::InvalidSymbolName => write!(f, "symbol or import name is not valid UTF-8"),
+            FormatError::TrailingBytes(kind) => write!(f, "trailing bytes in {:?} section", kind),
+        }
+    }
+}
+
+impl Error for FormatError {}
+
+/// Reads big-endian values from a byte slice
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, position: 0 }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], FormatError> {
+        let end = self.position.checked_add(length).ok_or(FormatError::Truncated)?;
+        let result = self.bytes.get(self.position..end).ok_or(FormatError::Truncated)?;
+        self.position = end;
+        Ok(result)
+    }
+
+    fn u16(&mut self) -> Result<u16, FormatError> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, FormatError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+}
+
+impl Program {
+    pub const MAGIC: [u8; 4] = *b"CEVM";
+    pub const FORMAT_VERSION: u16 = 1;
+
+    const HEADER_SIZE: usize = 16;
+    const SECTION_HEADER_SIZE: usize = 12;
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Program, FormatError> {
+        let mut reader = Reader::new(bytes);
+
+        if reader.take(4).map_err(|_| FormatError::BadMagic)? != Self::MAGIC {
+            return Err(FormatError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version != Self::FORMAT_VERSION {
+            return Err(FormatError::UnsupportedVersion(version));
+        }
+        reader.u16()?;
+
+        let entry_point = reader.u32()?;
+        let section_count = reader.u32()?;
+
+        let mut program = Program { entry_point, ..Default::default() };
+        let mut seen_sections: Vec<SectionKind> = vec![];
+
+        for _ in 0..section_count {
+            let kind = reader.u32()?;
+            let kind = SectionKind::from_u32(kind).ok_or(FormatError::UnknownSection(kind))?;
+            let offset = reader.u32()? as usize;
+            let length = reader.u32()? as usize;
+
+            if seen_sections.contains(&kind) {
+                return Err(FormatError::DuplicateSection(kind));
+            }
+            seen_sections.push(kind);
+
+            let data = offset.checked_add(length)
+                .and_then(|end| bytes.get(offset..end))
+                .ok_or(FormatError::SectionOutOfBounds(kind))?;
+
+            match kind {
+                SectionKind::Code => program.code = data.to_vec(),
+                SectionKind::ReadOnlyData => program.rodata = data.to_vec(),
+                SectionKind::Symbols => program.symbols = Self::read_symbols(data)?,
+                SectionKind::Debug => program.line_info = Self::read_line_info(data)?,
+                SectionKind::Imports => program.imports = Self::read_imports(data)?,
+            }
+        }
+
+        if !seen_sections.contains(&SectionKind::Code) {
+            return Err(FormatError::MissingCodeSection);
+        }
+        if entry_point as usize >= program.code.len() {
+            return Err(FormatError::EntryPointOutOfBounds(entry_point));
+        }
+
+        Ok(program)
+    }
+
+    fn read_symbols(data: &[u8]) -> Result<Vec<Symbol>, FormatError> {
+        let mut reader = Reader::new(data);
+        let mut symbols = vec![];
+
+        for _ in 0..reader.u32()? {
+            let address = reader.u32()?;
+            let name_length = reader.u16()? as usize;
+            let name = std::str::from_utf8(reader.take(name_length)?)
+                .map_err(|_| FormatError::InvalidSymbolName)?;
+
+            symbols.push(Symbol { name: name.to_owned(), address });
+        }
+
+        if !reader.is_at_end() {
+            return Err(FormatError::TrailingBytes(SectionKind::Symbols));
+        }
+        symbols.sort_by_key(|symbol| symbol.address);
+        Ok(symbols)
+    }
+
+    fn read_line_info(data: &[u8]) -> Result<Vec<LineInfo>, FormatError> {
+        let mut reader = Reader::new(data);
+        let mut line_info = vec![];
+
+        for _ in 0..reader.u32()? {
+            line_info.push(LineInfo { address: reader.u32()?, line: reader.u32()? });
+        }
+
+        if !reader.is_at_end() {
+            return Err(FormatError::TrailingBytes(SectionKind::Debug));
+        }
+        Ok(line_info)
+    }
+
+    fn read_imports(data: &[u8]) -> Result<Vec<String>, FormatError> {
+        let mut reader = Reader::new(data);
+        let mut imports = vec![];
+
+        for _ in 0..reader.u32()? {
+            let name_length = reader.u16()? as usize;
+            let name = std::str::from_utf8(reader.take(name_length)?)
+                .map_err(|_| FormatError::InvalidSymbolName)?;
+
+            imports.push(name.to_owned());
+        }
+
+        if !reader.is_at_end() {
+            return Err(FormatError::TrailingBytes(SectionKind::Imports));
+        }
+        Ok(imports)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut sections: Vec<(SectionKind, Vec<u8>)> = vec![(SectionKind::Code, self.code.clone())];
+
+        if !self.rodata.is_empty() {
+            sections.push((SectionKind::ReadOnlyData, self.rodata.clone()));
+        }
+        if !self.symbols.is_empty() {
+            let mut data = (self.symbols.len() as u32).to_be_bytes().to_vec();
+            for symbol in &self.symbols {
+                data.extend(symbol.address.to_be_bytes());
+                data.extend((symbol.name.len() as u16).to_be_bytes());
+                data.extend(symbol.name.as_bytes());
+            }
+            sections.push((SectionKind::Symbols, data));
+        }
+        if !self.line_info.is_empty() {
+            let mut data = (self.line_info.len() as u32).to_be_bytes().to_vec();
+            for info in &self.line_info {
+                data.extend(info.address.to_be_bytes());
+                data.extend(info.line.to_be_bytes());
+            }
+            sections.push((SectionKind::Debug, data));
+        }
+        if !self.imports.is_empty() {
+            let mut data = (self.imports.len() as u32).to_be_bytes().to_vec();
+            for name in &self.imports {
+                data.extend((name.len() as u16).to_be_bytes());
+                data.extend(name.as_bytes());
+            }
+            sections.push((SectionKind::Imports, data));
+        }
+
+        let mut bytes = Self::MAGIC.to_vec();
+        bytes.extend(Self::FORMAT_VERSION.to_be_bytes());
+        bytes.extend(0u16.to_be_bytes());
+        bytes.extend(self.entry_point.to_be_bytes());
+        bytes.extend((sections.len() as u32).to_be_bytes());
+
+        let mut offset = Self::HEADER_SIZE + sections.len() * Self::SECTION_HEADER_SIZE;
+        for (kind, data) in &sections {
+            bytes.extend((*kind as u32).to_be_bytes());
+            bytes.extend((offset as u32).to_be_bytes());
+            bytes.extend((data.len() as u32).to_be_bytes());
+            offset += data.len();
+        }
+        for (_, data) in sections {
+            bytes.extend(data);
+        }
+
+        bytes
+    }
+
+    /// Finds the symbol with the given name
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+
+    /// Finds the last symbol at or before `address`, returning it along with the offset of
+    /// `address` from it
+    pub fn nearest_symbol(&self, address: CeWord) -> Option<(&Symbol, CeWord)> {
+        symbol_map::nearest_symbol(&self.symbols, address)
+    }
+}
@@ -0,0 +1,109 @@
+//! The `.map` symbol map format, a text file that names the addresses of a program so that
+//! runtime errors and traces can show code addresses like `FIB_RECURSE+0x1c`.
+//!
+//! Each line is an address in hexadecimal followed by the name of the symbol at that address:
+//!
+//! ```text
+//! // Symbol map of fibonacci.casm
+//! 0x00000000 POW
+//! 0x0000001a POW_LOOP
+//! 0x0000002c FIB_RECURSE
+//! ```
+//!
+//! Blank lines and everything after `//` are ignored. Symbols may be listed in any order, except
+//! that of several symbols at the same address, the one listed last is used to describe it.
+
+use crate::cerium::program::Symbol;
+use crate::cerium::vm::CeWord;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SymbolMap {
+    /// Sorted by address, and otherwise in the order they were given in
+    symbols: Vec<Symbol>,
+}
+
+/// A line of a symbol map that could not be parsed
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SymbolMapError {
+    /// The 1-based line number
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for SymbolMapError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for SymbolMapError {}
+
+impl SymbolMap {
+    pub fn new(mut symbols: Vec<Symbol>) -> SymbolMap {
+        symbols.sort_by_key(|symbol| symbol.address);
+        SymbolMap { symbols }
+    }
+
+    pub fn parse(text: &str) -> Result<SymbolMap, SymbolMapError> {
+        let mut symbols = vec![];
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| SymbolMapError { line: index + 1, message };
+            let line = line.find("//").map_or(line, |comment| &line[..comment]);
+            let mut fields = line.split_whitespace();
+            let Some(address) = fields.next() else {
+                continue;
+            };
+
+            let digits = address.strip_prefix("0x").or_else(|| address.strip_prefix("0X"))
+                .ok_or_else(|| error(format!("expected an address like `0x0000001c`, found `{}`", address)))?;
+            let address = CeWord::from_str_radix(digits, 16)
+                .map_err(|_| error(format!("invalid address `{}`", address)))?;
+            let name = fields.next().ok_or_else(|| error("expected a symbol name after the address".to_owned()))?;
+            if let Some(extra) = fields.next() {
+                return Err(error(format!("unexpected `{}` after the symbol name", extra)));
+            }
+            symbols.push(Symbol { name: name.to_owned(), address });
+        }
+        Ok(SymbolMap::new(symbols))
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// Finds the last symbol at or before `address`, returning it along with the offset of
+    /// `address` from it
+    pub fn nearest_symbol(&self, address: CeWord) -> Option<(&Symbol, CeWord)> {
+        nearest_symbol(&self.symbols, address)
+    }
+
+    /// Shows an address relative to the symbol before it, like `FIB_RECURSE+0x1c`, or as a
+    /// number if no symbol comes before it
+    pub fn describe(&self, address: CeWord) -> String {
+        match self.nearest_symbol(address) {
+            Some((symbol, 0)) => symbol.name.clone(),
+            Some((symbol, offset)) => format!("{}+0x{:x}", symbol.name, offset),
+            None => format!("0x{:08x}", address),
+        }
+    }
+}
+
+/// Finds the last of `symbols`, which must be sorted by address, that is at or before `address`,
+/// returning it along with the offset of `address` from it. Of several symbols at the same
+/// address, the last one is used.
+pub fn nearest_symbol(symbols: &[Symbol], address: CeWord) -> Option<(&Symbol, CeWord)> {
+    let end = symbols.partition_point(|symbol| symbol.address <= address);
+    let symbol = symbols[..end].last()?;
+    Some((symbol, address - symbol.address))
+}
+
+impl Display for SymbolMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for symbol in &self.symbols {
+            writeln!(f, "0x{:08x} {}", symbol.address, symbol.name)?;
+        }
+        Ok(())
+    }
+}
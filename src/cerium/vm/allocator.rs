@@ -1,8 +1,35 @@
+use super::error::Trap;
+use super::snapshot::{SnapshotError, SnapshotReader, SnapshotWriter};
 use super::types::{Pointer, Size};
 use super::CeWord;
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::{Debug, Formatter};
 
+/// A summary of the state of the heap, as returned by [`Allocator::stats`]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AllocatorStats {
+    pub live_blocks: usize,
+    pub live_bytes: CeWord,
+    /// The most bytes that were live at once
+    pub peak_bytes: CeWord,
+    pub free_blocks: usize,
+    pub free_bytes: CeWord,
+    /// The share of the free bytes that lie outside of the largest free block, from 0 when the
+    /// free memory is in one piece towards 1 as it is split up
+    pub fragmentation: f64,
+    /// The number of free blocks of each size, smallest size first
+    pub free_list_sizes: Vec<(CeWord, usize)>,
+}
+
+/// A heap block that has not been freed
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Allocation {
+    pub address: CeWord,
+    pub size: CeWord,
+    /// The address of the instruction that allocated the block
+    pub allocated_at: CeWord,
+}
+
 #[derive(Copy, Clone, Debug)]
 struct MemorySpan {
     start: Pointer,
@@ -35,6 +62,11 @@ pub struct Allocator {
     free_blocks_for_size: FreeBlocksMap,
 
     last_heap_ptr: Pointer,
+
+    /// The address of the instruction that allocated each used block
+    allocation_sites: HashMap<Pointer, CeWord>,
+    live_bytes: CeWord,
+    peak_bytes: CeWord,
 }
 
 impl Allocator {
@@ -95,10 +127,11 @@ impl Allocator {
                 );
             }
         }
-        // Otherwise, we can remove this block  entirely because it is a trailing free block
+        // Otherwise, we can remove this block  entirely because it is a trailing free block, and
+        // the heap ends where it started
         else {
             self.remove_block(curr_block);
-            return;
+            self.last_heap_ptr = curr_block.span.start;
         }
     }
 
@@ -171,42 +204,188 @@ impl Allocator {
         (left_block, right_block)
     }
 
-    pub fn allocate(&mut self, alloc_size: Size) -> Pointer {
+    /// Allocates a block of `alloc_size` bytes for the instruction at `site`. The heap only grows
+    /// as far as `limit`.
+    pub fn allocate(&mut self, alloc_size: Size, site: CeWord, limit: CeWord) -> Result<Pointer, Trap> {
         // Try to find a free block of the right size
-        if let Some(mut block) = self.free_blocks_for_size.get_first_ptr_with_min_size(alloc_size).and_then(|x| self.blocks.get(&x).cloned()) {
+        let start = if let Some(mut block) = self.free_blocks_for_size.get_first_ptr_with_min_size(alloc_size).and_then(|x| self.blocks.get(&x).cloned()) {
             // Split the block
             if block.span.size() > alloc_size {
                 block = self.split_free_block(block, alloc_size).0;
             }
 
             self.mark_block_used(block.span.start);
-            return block.span.start;
-        }
+            block.span.start
+        } else {
+            // Allocate a new space at the end of the heap
+            let start = self.last_heap_ptr;
+            let requested = CeWord::from(start).saturating_add(alloc_size.into());
+            if requested > limit {
+                return Err(Trap::OutOfMemory { requested, limit });
+            }
+            let end = start + alloc_size;
 
-        // Allocate a new space at the end of the heap
-        let start = self.last_heap_ptr;
-        let end = start + alloc_size;
+            self.add_block(MemoryBlockInfo {
+                span: MemorySpan { start, end },
+                status: MemoryBlockStatus::USED,
+                prev_block_start_ptr: self.blocks.last_key_value().map(|(x, _)| *x),
+            });
+            self.last_heap_ptr = end;
 
-        self.add_block(MemoryBlockInfo {
-            span: MemorySpan { start, end },
-            status: MemoryBlockStatus::USED,
-            prev_block_start_ptr: self.blocks.last_key_value().map(|(x, _)| *x),
-        });
-        self.last_heap_ptr = end;
+            start
+        };
 
-        start
+        self.allocation_sites.insert(start, site);
+        self.live_bytes += CeWord::from(alloc_size);
+        self.peak_bytes = self.peak_bytes.max(self.live_bytes);
+        Ok(start)
     }
 
-    pub fn deallocate(&mut self, ptr: Pointer) -> Result<(), String> {
+    pub fn deallocate(&mut self, ptr: Pointer) -> Result<(), Trap> {
         if let Some(block) = self.blocks.get(&ptr).cloned() {
             if block.status == MemoryBlockStatus::USED {
+                self.allocation_sites.remove(&ptr);
+                self.live_bytes -= CeWord::from(block.span.size());
                 let block = self.mark_block_free(ptr);
                 self.merge_free_block_with_adjacent(block);
                 return Ok(());
             }
         }
 
-        Err("CeriumVM Error: invalid pointer to deallocate".to_owned())
+        Err(Trap::InvalidFree { address: ptr.into() })
+    }
+
+    /// The address just past the last block that was ever allocated
+    pub fn heap_end(&self) -> Pointer {
+        self.last_heap_ptr
+    }
+
+    /// The total size of the blocks that have not been freed
+    pub fn live_bytes(&self) -> CeWord {
+        self.live_bytes
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        let mut stats = AllocatorStats {
+            live_bytes: self.live_bytes,
+            peak_bytes: self.peak_bytes,
+            ..Default::default()
+        };
+        let mut largest_free_block: CeWord = 0;
+
+        for block in self.blocks.values() {
+            let size = CeWord::from(block.span.size());
+            match block.status {
+                MemoryBlockStatus::USED => stats.live_blocks += 1,
+                MemoryBlockStatus::FREE => {
+                    stats.free_blocks += 1;
+                    stats.free_bytes += size;
+                    largest_free_block = largest_free_block.max(size);
+                }
+            }
+        }
+        if stats.free_bytes > 0 {
+            stats.fragmentation = 1.0 - largest_free_block as f64 / stats.free_bytes as f64;
+        }
+        stats.free_list_sizes = self.free_blocks_for_size.sizes()
+            .map(|(size, count)| (size.into(), count))
+            .collect();
+
+        stats
+    }
+
+    /// The start and end of the used block that `ptr` points into, if any
+    pub fn used_block_containing(&self, ptr: Pointer) -> Option<(Pointer, Pointer)> {
+        self.blocks.range(..=ptr).next_back()
+            .map(|(_, block)| block)
+            .filter(|block| block.status == MemoryBlockStatus::USED && ptr < block.span.end)
+            .map(|block| (block.span.start, block.span.end))
+    }
+
+    /// The blocks that have not been freed, by ascending address
+    pub fn live_allocations(&self) -> Vec<Allocation> {
+        self.blocks.values()
+            .filter(|block| block.status == MemoryBlockStatus::USED)
+            .map(|block| Allocation {
+                address: block.span.start.into(),
+                size: block.span.size().into(),
+                allocated_at: self.allocation_sites.get(&block.span.start).copied().unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    pub(super) fn save(&self, writer: &mut SnapshotWriter) {
+        writer.u32(self.last_heap_ptr.into());
+        writer.u32(self.blocks.len() as u32);
+        for block in self.blocks.values() {
+            writer.u32(block.span.start.into());
+            writer.u32(block.span.end.into());
+            writer.u8((block.status == MemoryBlockStatus::FREE) as u8);
+            writer.u8(block.prev_block_start_ptr.is_some() as u8);
+            writer.u32(block.prev_block_start_ptr.unwrap_or_default().into());
+            writer.u32(self.allocation_sites.get(&block.span.start).copied().unwrap_or_default());
+        }
+    }
+
+    pub(super) fn load(reader: &mut SnapshotReader) -> Result<Allocator, SnapshotError> {
+        let mut allocator = Allocator { last_heap_ptr: reader.u32()?.into(), ..Default::default() };
+        // Freeing relies on the blocks tiling the heap from its start, with their `prev` pointers
+        // linking each block to the one before it and no two free blocks next to each other
+        let mut previous: Option<MemoryBlockInfo> = None;
+
+        for _ in 0..reader.u32()? {
+            let start = Pointer::new(reader.u32()?);
+            let end = Pointer::new(reader.u32()?);
+            let status = if reader.bool()? { MemoryBlockStatus::FREE } else { MemoryBlockStatus::USED };
+            let has_prev = reader.bool()?;
+            let prev = Pointer::new(reader.u32()?);
+            let allocated_at = reader.u32()?;
+
+            let previous_end = previous.map_or(Pointer::new(0), |block| block.span.end);
+            if start != previous_end {
+                return Err(SnapshotError::Inconsistent("heap blocks overlap or leave gaps"));
+            }
+            if end <= start || end > allocator.last_heap_ptr {
+                return Err(SnapshotError::Inconsistent("heap block is empty or lies past the end of the heap"));
+            }
+            if has_prev != previous.is_some() || (has_prev && Some(prev) != previous.map(|block| block.span.start)) {
+                return Err(SnapshotError::Inconsistent("heap block does not link to the block before it"));
+            }
+            if status == MemoryBlockStatus::FREE
+                && previous.is_some_and(|block| block.status == MemoryBlockStatus::FREE) {
+                return Err(SnapshotError::Inconsistent("adjacent heap blocks are both free"));
+            }
+
+            if status == MemoryBlockStatus::USED {
+                allocator.allocation_sites.insert(start, allocated_at);
+                allocator.live_bytes += CeWord::from(end - start);
+            }
+
+            let block = MemoryBlockInfo {
+                span: MemorySpan { start, end },
+                status,
+                prev_block_start_ptr: has_prev.then_some(prev),
+            };
+            allocator.add_block(block);
+            previous = Some(block);
+        }
+
+        // Trailing free blocks are given back to the heap, so the heap ends with a used block
+        match previous {
+            Some(block) if block.status == MemoryBlockStatus::FREE => {
+                return Err(SnapshotError::Inconsistent("the last heap block is free"));
+            }
+            Some(block) if block.span.end != allocator.last_heap_ptr => {
+                return Err(SnapshotError::Inconsistent("heap blocks do not reach the end of the heap"));
+            }
+            None if allocator.last_heap_ptr != Pointer::new(0) => {
+                return Err(SnapshotError::Inconsistent("heap blocks do not reach the end of the heap"));
+            }
+            _ => {}
+        }
+
+        allocator.peak_bytes = allocator.live_bytes;
+        Ok(allocator)
     }
 }
 
@@ -214,9 +393,7 @@ impl Debug for Allocator {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         macro_rules! write_or_return {
             ($f:expr, $($arg:tt)*) => {
-                if let Err(e) = write!($f, $($arg)*) {
-                    return Err(e);
-                }
+                write!($f, $($arg)*)?
             };
         }
 
@@ -239,7 +416,7 @@ impl Debug for Allocator {
                         for _ in 0..size {
                             write_or_return!(f, "--");
                         }
-                        print!("->");
+                        write_or_return!(f, "->");
                     }
                     MemoryBlockStatus::FREE => {
                         if size == 1 {
@@ -269,7 +446,7 @@ pub struct FreeBlocksMap {
 impl FreeBlocksMap
 {
     pub fn get_ptrs_with_size(&mut self, size: Size) -> &mut BTreeSet<Pointer> {
-        self.backing_map.entry(size).or_insert_with(BTreeSet::new)
+        self.backing_map.entry(size).or_default()
     }
 
     pub fn insert(&mut self, key: Size, value: Pointer) {
@@ -286,6 +463,11 @@ impl FreeBlocksMap
         }
     }
 
+    /// The sizes that have free blocks, smallest first, with the number of blocks of each size
+    pub fn sizes(&self) -> impl Iterator<Item = (Size, usize)> + '_ {
+        self.backing_map.iter().map(|(&size, ptrs)| (size, ptrs.len()))
+    }
+
     pub fn get_first_ptr_with_min_size(&self, minimum_size: Size) -> Option<Pointer> {
         self.backing_map.range(minimum_size..).next()
             .and_then(|(key, _)| self.backing_map.get(key))
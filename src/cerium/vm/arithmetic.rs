@@ -0,0 +1,160 @@
+use super::error::Trap;
+use super::{CeDouble, CeFloat, CeInt16, CeInt32, CeInt64, CeInt8, CeUInt16, CeUInt32, CeUInt8};
+use crate::cerium::instruction::instruction_parts::{BinOp, Immediate, Type, UnOp};
+use crate::cerium::memory_buffer::EndianConversion;
+
+/// A value type that the VM can do arithmetic on. Integer arithmetic wraps on overflow so that a
+/// guest program can never bring down the host.
+pub trait Arithmetic: EndianConversion + PartialOrd {
+    const TYPE: Type;
+    const ZERO: Self;
+
+    fn binop(op: BinOp, lhs: Self, rhs: Self) -> Result<Self, Trap>;
+    fn unop(op: UnOp, val: Self) -> Result<Self, Trap>;
+    /// Converts a constant operand to this type
+    fn from_immediate(imm: Immediate) -> Self;
+}
+
+/// Unsigned types divide, shift and compare their values as unsigned numbers
+macro_rules! impl_integer_arithmetic {
+    (signed $t: ty, $ty: expr) => {
+        // The remainder takes the sign of the divisor
+        impl_integer_arithmetic!($t, $ty, |lhs: $t, rhs: $t| {
+            let r = lhs.wrapping_rem(rhs);
+            if r != 0 && (r < 0) != (rhs < 0) { r.wrapping_add(rhs) } else { r }
+        });
+    };
+    (unsigned $t: ty, $ty: expr) => {
+        impl_integer_arithmetic!($t, $ty, |lhs: $t, rhs: $t| lhs % rhs);
+    };
+    ($t: ty, $ty: expr, $modulo: expr) => {
+        impl Arithmetic for $t {
+            const TYPE: Type = $ty;
+            const ZERO: Self = 0;
+
+            #[inline(always)]
+            fn binop(op: BinOp, lhs: Self, rhs: Self) -> Result<Self, Trap> {
+                use BinOp::*;
+                Ok(match op {
+                    XOR => lhs ^ rhs,
+                    OR => lhs | rhs,
+                    AND => lhs & rhs,
+                    SHL => lhs.wrapping_shl(rhs as u32),
+                    SHR => lhs.wrapping_shr(rhs as u32),
+                    MUL => lhs.wrapping_mul(rhs),
+                    ADD => lhs.wrapping_add(rhs),
+                    SUB => lhs.wrapping_sub(rhs),
+                    DIV if rhs == 0 => return Err(Trap::DivisionByZero),
+                    DIV => lhs.wrapping_div(rhs),
+                    MOD if rhs == 0 => return Err(Trap::DivisionByZero),
+                    MOD => ($modulo)(lhs, rhs),
+                })
+            }
+
+            #[inline(always)]
+            fn unop(op: UnOp, val: Self) -> Result<Self, Trap> {
+                Ok(match op {
+                    UnOp::NEG => val.wrapping_neg(),
+                    UnOp::NOT => !val,
+                })
+            }
+
+            #[inline(always)]
+            fn from_immediate(imm: Immediate) -> Self {
+                imm.sign_extended() as Self
+            }
+        }
+    };
+}
+
+impl_integer_arithmetic!(signed CeInt8, Type::Int8);
+impl_integer_arithmetic!(signed CeInt16, Type::Int16);
+impl_integer_arithmetic!(signed CeInt32, Type::Int32);
+impl_integer_arithmetic!(signed CeInt64, Type::Int64);
+impl_integer_arithmetic!(unsigned CeUInt8, Type::UInt8);
+impl_integer_arithmetic!(unsigned CeUInt16, Type::UInt16);
+impl_integer_arithmetic!(unsigned CeUInt32, Type::UInt32);
+
+macro_rules! impl_float_arithmetic {
+    ($t: ty, $ty: expr) => {
+        impl Arithmetic for $t {
+            const TYPE: Type = $ty;
+            const ZERO: Self = 0.0;
+
+            #[inline(always)]
+            fn binop(op: BinOp, lhs: Self, rhs: Self) -> Result<Self, Trap> {
+                use BinOp::*;
+                Ok(match op {
+                    MUL => lhs * rhs,
+                    ADD => lhs + rhs,
+                    SUB => lhs - rhs,
+                    DIV => lhs / rhs,
+                    MOD => (lhs % rhs + rhs) % rhs,
+                    XOR | OR | AND | SHL | SHR => return Err(Trap::UnsupportedOperation {
+                        operation: op.mnemonic(),
+                        ty: Self::TYPE,
+                    }),
+                })
+            }
+
+            #[inline(always)]
+            fn unop(op: UnOp, val: Self) -> Result<Self, Trap> {
+                match op {
+                    UnOp::NEG => Ok(-val),
+                    UnOp::NOT => Err(Trap::UnsupportedOperation {
+                        operation: op.mnemonic(),
+                        ty: Self::TYPE,
+                    }),
+                }
+            }
+
+            #[inline(always)]
+            fn from_immediate(imm: Immediate) -> Self {
+                match imm {
+                    Immediate::Imm32(bits) => CeFloat::from_bits(bits) as Self,
+                    imm => imm.sign_extended() as Self,
+                }
+            }
+        }
+    };
+}
+
+impl_float_arithmetic!(CeFloat, Type::Float);
+impl_float_arithmetic!(CeDouble, Type::Double);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn modulo<T: Arithmetic>(lhs: T, rhs: T) -> T {
+        T::binop(BinOp::MOD, lhs, rhs).unwrap()
+    }
+
+    #[test]
+    fn signed_modulo_takes_the_sign_of_the_divisor() {
+        assert_eq!(modulo::<CeInt8>(100, 120), 100);
+        assert_eq!(modulo::<CeInt8>(-7, 3), 2);
+        assert_eq!(modulo::<CeInt8>(7, -3), -2);
+        assert_eq!(modulo::<CeInt8>(-7, -3), -1);
+        assert_eq!(modulo::<CeInt8>(6, -3), 0);
+        assert_eq!(modulo::<CeInt8>(CeInt8::MIN, -1), 0);
+        assert_eq!(modulo::<CeInt8>(CeInt8::MIN, 3), 1);
+        assert_eq!(modulo::<CeInt8>(CeInt8::MIN, CeInt8::MAX), CeInt8::MAX - 1);
+        assert_eq!(modulo::<CeInt8>(CeInt8::MAX, CeInt8::MIN), -1);
+        assert_eq!(modulo::<CeInt8>(CeInt8::MAX, -2), -1);
+
+        assert_eq!(modulo::<CeInt32>(1, CeInt32::MAX), 1);
+        assert_eq!(modulo::<CeInt32>(-1, CeInt32::MAX), CeInt32::MAX - 1);
+        assert_eq!(modulo::<CeInt32>(CeInt32::MIN, -1), 0);
+        assert_eq!(modulo::<CeInt32>(CeInt32::MIN, CeInt32::MAX), CeInt32::MAX - 1);
+        assert_eq!(modulo::<CeInt32>(CeInt32::MAX, CeInt32::MIN), -1);
+        assert_eq!(modulo::<CeInt32>(CeInt32::MAX, CeInt32::MAX), 0);
+        assert_eq!(modulo::<CeInt32>(-2_000_000_000, 2_000_000_001), 1);
+    }
+
+    #[test]
+    fn modulo_by_zero_traps() {
+        assert!(matches!(CeInt32::binop(BinOp::MOD, 1, 0), Err(Trap::DivisionByZero)));
+        assert!(matches!(CeUInt8::binop(BinOp::MOD, 1, 0), Err(Trap::DivisionByZero)));
+    }
+}
@@ -0,0 +1,110 @@
+use super::{CeWord, RAM};
+
+/// Memory limits for a [`CeriumVM`](super::CeriumVM), built up from the defaults with chained
+/// calls like `VmConfig::new().max_heap(1 << 20).allow_growth(false)`. All sizes are in bytes.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct VmConfig {
+    pub(crate) initial_stack: CeWord,
+    pub(crate) max_stack: CeWord,
+    pub(crate) initial_heap: CeWord,
+    pub(crate) max_heap: CeWord,
+    pub(crate) allow_growth: bool,
+    pub(crate) sanitize: bool,
+    pub(crate) garbage_collect: bool,
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        VmConfig {
+            initial_stack: 1 << 8,
+            max_stack: 1 << 20,
+            initial_heap: 1 << 8,
+            max_heap: 1 << 24,
+            allow_growth: true,
+            sanitize: false,
+            garbage_collect: false,
+        }
+    }
+}
+
+impl VmConfig {
+    /// The largest stack that fits below [`RAM::STATIC_PTR_BIT`]
+    pub const STACK_ADDRESS_SPACE: CeWord = RAM::STATIC_PTR_BIT;
+    /// The largest heap that fits below [`RAM::HEAP_PTR_BIT`]
+    pub const HEAP_ADDRESS_SPACE: CeWord = RAM::HEAP_PTR_BIT;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The size the stack starts out with
+    pub fn initial_stack(mut self, size: CeWord) -> Self {
+        self.initial_stack = size;
+        self
+    }
+
+    /// The largest size the stack can grow to. Sizes beyond [`Self::STACK_ADDRESS_SPACE`] are
+    /// clamped to it.
+    pub fn max_stack(mut self, size: CeWord) -> Self {
+        self.max_stack = size.min(Self::STACK_ADDRESS_SPACE);
+        self
+    }
+
+    /// The size the heap starts out with
+    pub fn initial_heap(mut self, size: CeWord) -> Self {
+        self.initial_heap = size;
+        self
+    }
+
+    /// The largest size the heap can grow to. Sizes beyond [`Self::HEAP_ADDRESS_SPACE`] are
+    /// clamped to it.
+    pub fn max_heap(mut self, size: CeWord) -> Self {
+        self.max_heap = size.min(Self::HEAP_ADDRESS_SPACE);
+        self
+    }
+
+    /// Whether the stack and heap may grow past their initial sizes. Without growth, each region
+    /// is allocated at its initial size up front and that size is also its limit.
+    pub fn allow_growth(mut self, allow: bool) -> Self {
+        self.allow_growth = allow;
+        self
+    }
+
+    /// Whether to check every heap access and free against the blocks that are live, trapping with
+    /// [`Trap::Sanitizer`](super::Trap::Sanitizer) on use after free, double free, out-of-bounds
+    /// accesses, reads of uninitialized bytes and frees of pointers into the middle of a block.
+    /// This makes heap accesses considerably slower.
+    pub fn sanitize(mut self, sanitize: bool) -> Self {
+        self.sanitize = sanitize;
+        self
+    }
+
+    /// Whether to collect garbage automatically, freeing the heap blocks that the program can no
+    /// longer reach once enough has been allocated since the last collection, or when an
+    /// allocation would not fit otherwise. `DEL` still frees blocks explicitly, and the `GC`
+    /// instruction collects whether or not this is enabled.
+    pub fn garbage_collect(mut self, collect: bool) -> Self {
+        self.garbage_collect = collect;
+        self
+    }
+
+    /// The size the stack starts out with, never more than its limit
+    pub fn stack_size(&self) -> CeWord {
+        self.initial_stack.min(self.stack_limit())
+    }
+
+    /// The largest size the stack can actually reach
+    pub fn stack_limit(&self) -> CeWord {
+        if self.allow_growth { self.max_stack } else { self.initial_stack.min(self.max_stack) }
+    }
+
+    /// The size the heap starts out with, never more than its limit
+    pub fn heap_size(&self) -> CeWord {
+        self.initial_heap.min(self.heap_limit())
+    }
+
+    /// The largest size the heap can actually reach
+    pub fn heap_limit(&self) -> CeWord {
+        if self.allow_growth { self.max_heap } else { self.initial_heap.min(self.max_heap) }
+    }
+}
@@ -0,0 +1,116 @@
+use super::{CeWord, SanitizerReport};
+use crate::cerium::instruction::instruction_parts::Type;
+use crate::cerium::instruction::Instruction;
+use crate::cerium::symbol_map::SymbolMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io::ErrorKind;
+
+/// A condition that stops the guest program from executing further
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Trap {
+    /// A memory access of `size` bytes at `address` lies outside of guest memory
+    OutOfBounds { address: CeWord, size: CeWord },
+    /// A write to `address` targets the program's read-only data
+    WriteToReadOnly { address: CeWord },
+    /// `address` is not the start of a live heap allocation
+    InvalidFree { address: CeWord },
+    /// An integer division or modulo had a divisor of zero
+    DivisionByZero,
+    /// The byte at the instruction pointer is not a valid opcode
+    InvalidOpcode(u8),
+    /// The operation cannot be applied to operands of the given type
+    UnsupportedOperation { operation: &'static str, ty: Type },
+    /// The instruction pointer ran past the end of the program
+    IpOutOfBounds,
+    /// An allocation would grow the heap to `requested` bytes, beyond its `limit`
+    OutOfMemory { requested: CeWord, limit: CeWord },
+    /// A `NEW` instruction requested zero bytes
+    EmptyAllocation,
+    /// An `INP` instruction found no more input
+    InputEof,
+    /// An `INP` instruction read something that is not an integer
+    InvalidInput,
+    /// The host failed to perform I/O on behalf of the guest
+    IoError(ErrorKind),
+    /// A `PUSH` or `CALL` would grow the stack past `address`, beyond its `limit` in bytes
+    StackOverflow { address: CeWord, limit: CeWord },
+    /// A `POP` or `RET` found fewer bytes on the stack than it needs
+    StackUnderflow,
+    /// A `SYSCALL` instruction used a number that no host function is registered under
+    UnknownSyscall(u16),
+    /// A `CALLHOST` instruction named an import that is missing from the program or that no host
+    /// function is registered under
+    UnresolvedImport(u16),
+    /// The sanitizer found an invalid heap access or free
+    Sanitizer(SanitizerReport),
+}
+
+impl Display for Trap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Trap::OutOfBounds { address, size } => write!(
+                f, "out-of-bounds access of {} bytes at 0x{:08x}", size, address
+            ),
+            Trap::WriteToReadOnly { address } => write!(
+                f, "write to read-only memory at 0x{:08x}", address
+            ),
+            Trap::InvalidFree { address } => write!(
+                f, "invalid pointer to deallocate: 0x{:08x}", address
+            ),
+            Trap::DivisionByZero => write!(f, "division by zero"),
+            Trap::InvalidOpcode(byte) => write!(f, "invalid opcode 0b{:08b}", byte),
+            Trap::UnsupportedOperation { operation, ty } => write!(
+                f, "cannot apply {} to {:?}", operation, ty
+            ),
+            Trap::IpOutOfBounds => write!(f, "instruction pointer ran past the end of the program"),
+            Trap::OutOfMemory { requested, limit } => write!(
+                f, "out of memory: the heap would grow to {} bytes, past its limit of {} bytes", requested, limit
+            ),
+            Trap::EmptyAllocation => write!(f, "allocation must not be empty"),
+            Trap::InputEof => write!(f, "reached the end of input"),
+            Trap::InvalidInput => write!(f, "input is not a valid integer"),
+            Trap::IoError(kind) => write!(f, "I/O error: {}", kind),
+            Trap::StackOverflow { address, limit } => write!(
+                f, "stack overflow: 0x{:08x} is past the stack limit of {} bytes", address, limit
+            ),
+            Trap::StackUnderflow => write!(f, "stack underflow"),
+            Trap::UnknownSyscall(number) => write!(f, "no host function is registered as syscall {}", number),
+            Trap::UnresolvedImport(index) => write!(f, "import #{} is not provided by the host", index),
+            Trap::Sanitizer(report) => write!(f, "{}", report),
+        }
+    }
+}
+
+/// A [`Trap`] raised while executing a program, along with where it happened
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct VmError {
+    pub trap: Trap,
+    /// The address of the faulting instruction
+    pub ip: CeWord,
+    /// The faulting instruction, if it could be decoded
+    pub instruction: Option<Instruction>,
+}
+
+impl VmError {
+    /// Renders the error with the faulting address relative to a symbol, like `FIB_RECURSE+0x1c`
+    pub fn to_string_with_symbols(&self, symbols: &SymbolMap) -> String {
+        self.render(&symbols.describe(self.ip))
+    }
+
+    fn render(&self, location: &str) -> String {
+        let mut rendered = format!("CeriumVM error at {}", location);
+        if let Some(instruction) = &self.instruction {
+            rendered += &format!(" ({})", instruction);
+        }
+        rendered + &format!(": {}", self.trap)
+    }
+}
+
+impl Display for VmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.render(&format!("0x{:08x}", self.ip)))
+    }
+}
+
+impl Error for VmError {}
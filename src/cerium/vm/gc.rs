@@ -0,0 +1,46 @@
+use super::CeWord;
+
+/// What a garbage collection freed
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Collection {
+    pub freed_blocks: usize,
+    pub freed_bytes: CeWord,
+}
+
+/// Decides when to collect garbage automatically: once as many bytes have been allocated since
+/// the last collection as were live after it, so the heap can roughly double between collections
+pub(super) struct GcPacer {
+    allocated: CeWord,
+    threshold: CeWord,
+}
+
+impl GcPacer {
+    /// The fewest bytes allocated between two automatic collections
+    const MIN_THRESHOLD: CeWord = 1 << 16;
+
+    pub fn new() -> Self {
+        GcPacer { allocated: 0, threshold: Self::MIN_THRESHOLD }
+    }
+
+    /// Whether allocating another `size` bytes should be preceded by a collection
+    pub fn should_collect(&self, size: CeWord) -> bool {
+        self.allocated.saturating_add(size) > self.threshold
+    }
+
+    pub fn on_allocate(&mut self, size: CeWord) {
+        self.allocated = self.allocated.saturating_add(size);
+    }
+
+    pub fn on_collect(&mut self, live_bytes: CeWord) {
+        self.allocated = 0;
+        self.threshold = live_bytes.max(Self::MIN_THRESHOLD);
+    }
+}
+
+/// Calls `visit` with every big-endian word in `bytes`, at every offset, since a pointer may be
+/// stored unaligned
+pub(super) fn scan_words(bytes: &[u8], mut visit: impl FnMut(CeWord)) {
+    for window in bytes.windows(size_of::<CeWord>()) {
+        visit(CeWord::from_be_bytes(window.try_into().unwrap()));
+    }
+}
@@ -1,36 +1,34 @@
+use super::error::Trap;
+use super::snapshot::{SnapshotError, SnapshotReader, SnapshotWriter};
 use super::{CeWord, Pointer};
 use crate::cerium::memory_buffer::{EndianConversion, MemoryBuffer, MemoryBufferPtr};
 
+/// A block of memory that starts out at `initial_size` bytes and grows on demand, up to
+/// `max_size` bytes
 pub struct GrowableMemoryBlock {
     pub memory: MemoryBuffer,
-}
-
-impl Default for GrowableMemoryBlock {
-    fn default() -> Self {
-        Self::new()
-    }
+    max_size: CeWord,
 }
 
 impl GrowableMemoryBlock {
-    const INITIAL_MEMORY: CeWord = 1 << 8;
-    const MAX_MEMORY: CeWord = 1 << 12;
-
-    pub fn new() -> Self {
+    pub fn new(initial_size: CeWord, max_size: CeWord) -> Self {
         let mut memory = MemoryBuffer::new();
-        memory.resize(Self::INITIAL_MEMORY as usize);
-        GrowableMemoryBlock { memory }
+        memory.resize(initial_size.min(max_size) as usize);
+        GrowableMemoryBlock { memory, max_size }
+    }
+
+    pub fn max_size(&self) -> CeWord {
+        self.max_size
     }
 
     #[inline(always)]
-    pub fn resize_to_fit(&mut self, size: CeWord) -> Result<(), String> {
-        if size > Self::MAX_MEMORY {
-            Err(format!(
-                "CeriumVM error: memory size cannot exceed {} bytes",
-                Self::MAX_MEMORY
-            ).to_owned())
+    pub fn resize_to_fit(&mut self, size: CeWord) -> Result<(), Trap> {
+        if size > self.max_size {
+            Err(Trap::OutOfMemory { requested: size, limit: self.max_size })
         } else {
             if size > self.memory.size() {
-                self.memory.resize(usize::next_power_of_two(size as usize));
+                let grown = usize::next_power_of_two(size as usize).min(self.max_size as usize);
+                self.memory.resize(grown);
             }
 
             Ok(())
@@ -38,10 +36,39 @@ impl GrowableMemoryBlock {
     }
 
     #[inline(always)]
-    pub fn at<T: EndianConversion>(&mut self, ptr: Pointer) -> Result<MemoryBufferPtr<T>, String> {
-        match self.resize_to_fit(CeWord::from(ptr) + size_of::<T>() as CeWord) {
+    pub fn at<T: EndianConversion>(&mut self, ptr: Pointer) -> Result<MemoryBufferPtr<T>, Trap> {
+        let size = size_of::<T>() as CeWord;
+        let out_of_bounds = Trap::OutOfBounds { address: ptr.into(), size };
+
+        let end = CeWord::from(ptr).checked_add(size).ok_or(out_of_bounds)?;
+        match self.resize_to_fit(end) {
             Ok(_) => Ok(self.memory.get(CeWord::from(ptr) as usize)),
-            Err(err) => Err(err),
+            Err(_) => Err(out_of_bounds),
+        }
+    }
+
+    /// Reads a value without growing the block, or `None` if it lies past the block's current size
+    pub fn peek<T: EndianConversion>(&self, ptr: Pointer) -> Option<T> {
+        let end = CeWord::from(ptr).checked_add(size_of::<T>() as CeWord)?;
+        (end <= self.memory.size()).then(|| self.memory.get::<T>(CeWord::from(ptr) as usize).get())
+    }
+
+    pub(super) fn save(&self, writer: &mut SnapshotWriter) {
+        writer.u32(self.max_size);
+        writer.bytes((&self.memory).into());
+    }
+
+    /// Reads a block whose limit must fit in `address_space`, so that it cannot reach into the
+    /// region after it
+    pub(super) fn load(reader: &mut SnapshotReader, address_space: CeWord) -> Result<GrowableMemoryBlock, SnapshotError> {
+        let max_size = reader.u32()?;
+        let contents = reader.bytes()?;
+        if max_size > address_space {
+            return Err(SnapshotError::Inconsistent("memory limit is larger than the region's address space"));
         }
+        if contents.len() > max_size as usize {
+            return Err(SnapshotError::Inconsistent("memory is larger than its limit"));
+        }
+        Ok(GrowableMemoryBlock { memory: MemoryBuffer::from(contents), max_size })
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,204 @@
+use super::register::Register;
+use super::{CeInt32, CeWord, Trap, RAM};
+use crate::cerium::instruction::instruction_parts;
+use crate::cerium::memory_buffer::EndianConversion;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Returns the current time: `r1` is set to the number of whole seconds since the Unix epoch,
+/// and `r2` to the milliseconds within the current second
+pub const SYS_TIME: u16 = 1;
+/// Sets `r1` to the number of milliseconds since the standard services were registered
+pub const SYS_CLOCK: u16 = 2;
+/// Sets `r1` to a pseudo-random 32-bit integer
+pub const SYS_RANDOM: u16 = 3;
+/// Reads a whole file, if the host allows it with [`HostFunctions::register_file_services`]. `r1`
+/// points to the path, relative to the directory that the host allows, and `r2` holds its
+/// length in bytes. On success,
+/// `r1` is set to a new heap block holding the contents of the file and `r2` to its length;
+/// the guest frees the block with `DEL`. An empty file sets `r1` and `r2` to 0, and a failure
+/// sets `r1` to 0 and `r2` to -1.
+pub const SYS_READ_FILE: u16 = 4;
+
+/// The view of the VM that a host function gets. By convention, host functions take their
+/// arguments from `r1` to `r6` and return their results in `r1` and `r2`.
+pub struct HostContext<'a> {
+    pub(super) registers: &'a mut [Register; 8],
+    pub(super) memory: &'a mut RAM,
+}
+
+impl HostContext<'_> {
+    /// Reads the value of a register as the given type
+    pub fn register<T: EndianConversion>(&self, register: instruction_parts::Register) -> T {
+        self.registers[register as usize].read()
+    }
+
+    pub fn set_register<T: EndianConversion>(&mut self, register: instruction_parts::Register, value: T) {
+        unsafe {
+            self.registers[register as usize].get().write(value);
+        }
+    }
+
+    /// Reads `length` bytes of guest memory starting at `address`
+    pub fn read_memory(&self, address: CeWord, length: CeWord) -> Result<Vec<u8>, Trap> {
+        self.memory.read_bytes(address, length)
+    }
+
+    /// Writes `bytes` to guest memory starting at `address`
+    pub fn write_memory(&mut self, address: CeWord, bytes: &[u8]) -> Result<(), Trap> {
+        self.memory.write_bytes(address, bytes)
+    }
+
+    /// Allocates a heap block on behalf of the guest, as if by a `NEW` instruction
+    pub fn allocate(&mut self, size: CeWord) -> Result<CeWord, Trap> {
+        Ok(self.memory.allocate(size)?.into())
+    }
+}
+
+pub type HostFunction = Box<dyn FnMut(&mut HostContext) -> Result<(), Trap>>;
+
+/// The host functions that a guest can call with the `SYSCALL` and `CALLHOST` instructions.
+/// `SYSCALL` selects a function by number, while `CALLHOST` selects one by the name listed in the
+/// program's import table.
+#[derive(Default)]
+pub struct HostFunctions {
+    functions: Vec<HostFunction>,
+    numbers: HashMap<u16, usize>,
+    names: HashMap<String, usize>,
+}
+
+impl HostFunctions {
+    /// Registers a function that can only be called by name, replacing any previous function with
+    /// the same name
+    pub fn register(
+        &mut self,
+        name: &str,
+        function: impl FnMut(&mut HostContext) -> Result<(), Trap> + 'static,
+    ) {
+        self.functions.push(Box::new(function));
+        self.names.insert(name.to_owned(), self.functions.len() - 1);
+    }
+
+    /// Registers a function that can be called both by number and by name, replacing any previous
+    /// function with the same number or name
+    pub fn register_numbered(
+        &mut self,
+        number: u16,
+        name: &str,
+        function: impl FnMut(&mut HostContext) -> Result<(), Trap> + 'static,
+    ) {
+        self.register(name, function);
+        self.numbers.insert(number, self.functions.len() - 1);
+    }
+
+    pub(super) fn by_number(&mut self, number: u16) -> Option<&mut HostFunction> {
+        self.numbers.get(&number).map(|&index| &mut self.functions[index])
+    }
+
+    pub(super) fn by_name(&mut self, name: &str) -> Option<&mut HostFunction> {
+        self.names.get(name).map(|&index| &mut self.functions[index])
+    }
+
+    /// Registers the clock and random number services under the `SYS_*` numbers and the names
+    /// `time`, `clock` and `random`. These cannot reach anything outside of the VM, so they are
+    /// safe to give to any guest.
+    pub fn register_standard_services(&mut self) {
+        use instruction_parts::Register::{R1, R2};
+
+        self.register_numbered(SYS_TIME, "time", |context| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            context.set_register(R1, now.as_secs() as CeInt32);
+            context.set_register(R2, now.subsec_millis() as CeInt32);
+            Ok(())
+        });
+
+        let start = Instant::now();
+        self.register_numbered(SYS_CLOCK, "clock", move |context| {
+            context.set_register(R1, start.elapsed().as_millis() as CeInt32);
+            Ok(())
+        });
+
+        // xorshift64*, seeded from the system time
+        let mut state = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64) | 1;
+        self.register_numbered(SYS_RANDOM, "random", move |context| {
+            state ^= state >> 12;
+            state ^= state << 25;
+            state ^= state >> 27;
+            let value = state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 32;
+            context.set_register(R1, value as CeInt32);
+            Ok(())
+        });
+    }
+
+    /// Registers the file reading service under [`SYS_READ_FILE`] and the name `read_file`. The
+    /// guest can only read files inside of `root`, which paths are relative to; reading anything
+    /// else fails as if the file did not exist.
+    pub fn register_file_services(&mut self, root: &Path) {
+        use instruction_parts::Register::{R1, R2};
+
+        let root = std::fs::canonicalize(root).ok();
+        self.register_numbered(SYS_READ_FILE, "read_file", move |context| {
+            let path = context.read_memory(context.register(R1), context.register(R2))?;
+            let contents = String::from_utf8(path).ok()
+                .and_then(|path| Self::path_within(root.as_deref()?, &path))
+                .and_then(|path| std::fs::read(path).ok());
+
+            let (address, length) = match contents {
+                Some(contents) if contents.is_empty() => (0, 0),
+                Some(contents) => {
+                    let address = context.allocate(contents.len() as CeWord)?;
+                    context.write_memory(address, &contents)?;
+                    (address, contents.len() as CeInt32)
+                }
+                None => (0, -1),
+            };
+            context.set_register(R1, address as CeInt32);
+            context.set_register(R2, length);
+            Ok(())
+        });
+    }
+
+    /// Resolves a guest path against `root`, which must be canonical, unless it leads outside of
+    /// `root`, including through `..` or symbolic links
+    fn path_within(root: &Path, path: &str) -> Option<PathBuf> {
+        let resolved = std::fs::canonicalize(root.join(path)).ok()?;
+        resolved.starts_with(root).then_some(resolved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_outside_of_the_root_are_refused() {
+        let sandbox = std::env::temp_dir().join(format!("cerium-path-within-{}", std::process::id()));
+        let root = sandbox.join("root");
+        std::fs::create_dir_all(root.join("inner")).unwrap();
+        std::fs::write(root.join("inner/data.txt"), "inside").unwrap();
+        std::fs::write(sandbox.join("secret.txt"), "outside").unwrap();
+        let root = std::fs::canonicalize(&root).unwrap();
+
+        let within = |path: &str| HostFunctions::path_within(&root, path);
+        assert_eq!(within("inner/data.txt"), Some(root.join("inner/data.txt")));
+        assert_eq!(within("inner/../inner/./data.txt"), Some(root.join("inner/data.txt")));
+        assert_eq!(within("../secret.txt"), None);
+        assert_eq!(within("inner/../../secret.txt"), None);
+        assert_eq!(within(sandbox.join("secret.txt").to_str().unwrap()), None);
+        assert_eq!(within("missing.txt"), None);
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(sandbox.join("secret.txt"), root.join("escape.txt")).unwrap();
+            std::os::unix::fs::symlink(&sandbox, root.join("parent")).unwrap();
+            std::os::unix::fs::symlink(root.join("inner"), root.join("alias")).unwrap();
+            assert_eq!(within("escape.txt"), None);
+            assert_eq!(within("parent/secret.txt"), None);
+            assert_eq!(within("alias/data.txt"), Some(root.join("inner/data.txt")));
+        }
+
+        std::fs::remove_dir_all(&sandbox).unwrap();
+    }
+}
@@ -0,0 +1,101 @@
+use super::{CeInt32, Trap};
+use std::collections::VecDeque;
+use std::io::{stdin, stdout, Write};
+
+/// The host side of the `INP` and `DSP` instructions
+pub trait VmIo {
+    /// Provides the value for an `INP` instruction
+    fn input(&mut self) -> Result<CeInt32, Trap>;
+    /// Consumes the value of a `DSP` instruction
+    fn output(&mut self, value: CeInt32) -> Result<(), Trap>;
+}
+
+fn parse_input(text: &str) -> Result<CeInt32, Trap> {
+    text.trim().parse().map_err(|_| Trap::InvalidInput)
+}
+
+/// Prompts for input on stdin and prints output to stdout
+#[derive(Default)]
+pub struct StdIo;
+
+impl VmIo for StdIo {
+    fn input(&mut self) -> Result<CeInt32, Trap> {
+        print!("<CeriumVM> Enter a number: ");
+        stdout().flush().map_err(|err| Trap::IoError(err.kind()))?;
+
+        let mut line = String::new();
+        match stdin().read_line(&mut line) {
+            Ok(0) => Err(Trap::InputEof),
+            Ok(_) => parse_input(&line),
+            Err(err) => Err(Trap::IoError(err.kind())),
+        }
+    }
+
+    fn output(&mut self, value: CeInt32) -> Result<(), Trap> {
+        writeln!(stdout(), "{}", value).map_err(|err| Trap::IoError(err.kind()))
+    }
+}
+
+/// Reads whitespace-separated input from a string and collects output into a string, one value
+/// per line
+#[derive(Default)]
+pub struct BufferIo {
+    input: VecDeque<String>,
+    output: String,
+}
+
+impl BufferIo {
+    pub fn new(input: &str) -> BufferIo {
+        BufferIo {
+            input: input.split_whitespace().map(str::to_owned).collect(),
+            output: String::new(),
+        }
+    }
+
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+}
+
+impl VmIo for BufferIo {
+    fn input(&mut self) -> Result<CeInt32, Trap> {
+        parse_input(&self.input.pop_front().ok_or(Trap::InputEof)?)
+    }
+
+    fn output(&mut self, value: CeInt32) -> Result<(), Trap> {
+        self.output.push_str(&value.to_string());
+        self.output.push('\n');
+        Ok(())
+    }
+}
+
+/// Supplies a fixed sequence of input values and records every output value
+#[derive(Default)]
+pub struct ScriptedIo {
+    inputs: VecDeque<CeInt32>,
+    outputs: Vec<CeInt32>,
+}
+
+impl ScriptedIo {
+    pub fn new(inputs: impl IntoIterator<Item = CeInt32>) -> ScriptedIo {
+        ScriptedIo {
+            inputs: inputs.into_iter().collect(),
+            outputs: vec![],
+        }
+    }
+
+    pub fn outputs(&self) -> &[CeInt32] {
+        &self.outputs
+    }
+}
+
+impl VmIo for ScriptedIo {
+    fn input(&mut self) -> Result<CeInt32, Trap> {
+        self.inputs.pop_front().ok_or(Trap::InputEof)
+    }
+
+    fn output(&mut self, value: CeInt32) -> Result<(), Trap> {
+        self.outputs.push(value);
+        Ok(())
+    }
+}
@@ -2,9 +2,29 @@ mod vm;
 mod ram;
 mod growable_memory;
 mod allocator;
+mod arithmetic;
+mod error;
+mod io;
+mod host;
 mod types;
 mod register;
+mod config;
+mod snapshot;
+mod sanitizer;
+mod observer;
+mod trace;
+mod gc;
 
+pub use allocator::{Allocation, AllocatorStats};
+pub use config::*;
+pub use error::*;
+pub use gc::Collection;
+pub use host::*;
+pub use io::*;
+pub use observer::*;
 pub use ram::*;
+pub use sanitizer::{HeapBlock, MemoryErrorKind, SanitizerReport};
+pub use snapshot::{SnapshotError, SNAPSHOT_MAGIC, SNAPSHOT_VERSION};
+pub use trace::*;
 pub use types::*;
 pub use vm::*;
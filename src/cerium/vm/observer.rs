@@ -0,0 +1,69 @@
+use super::{CeWord, VmError};
+use crate::cerium::instruction::instruction_parts::{Location, Type};
+use crate::cerium::instruction::Instruction;
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+/// A value read from or written to an operand, widened from its VM type
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Value {
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::UInt(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// An operand of an executed instruction and its value
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OperandValue {
+    pub location: Location,
+    pub ty: Type,
+    /// `None` if the operand points outside of guest memory
+    pub value: Option<Value>,
+}
+
+/// An instruction that executed without trapping
+#[derive(Clone, Debug, PartialEq)]
+pub struct InstructionEvent {
+    pub ip: CeWord,
+    pub instruction: Instruction,
+    /// The operands the instruction read, with their values from before it executed
+    pub sources: Vec<OperandValue>,
+    /// The operand the instruction wrote, with its value from after it executed
+    pub destination: Option<OperandValue>,
+    /// The address execution continues at
+    pub next_ip: CeWord,
+    /// The stack pointer after the instruction executed
+    pub sp: CeWord,
+}
+
+/// Receives an event for every instruction a [`CeriumVM`](super::CeriumVM) executes, once
+/// installed with [`CeriumVM::set_observer`](super::CeriumVM::set_observer). Without an
+/// observer, the VM does none of the work of collecting events.
+pub trait ExecutionObserver {
+    fn on_instruction(&mut self, event: &InstructionEvent);
+
+    /// Called instead of [`Self::on_instruction`] when an instruction traps
+    fn on_trap(&mut self, _error: &VmError) {}
+}
+
+/// Lets the host keep a handle on an observer that the VM owns, to look at what it collected
+impl<T: ExecutionObserver> ExecutionObserver for Rc<RefCell<T>> {
+    fn on_instruction(&mut self, event: &InstructionEvent) {
+        self.borrow_mut().on_instruction(event);
+    }
+
+    fn on_trap(&mut self, error: &VmError) {
+        self.borrow_mut().on_trap(error);
+    }
+}
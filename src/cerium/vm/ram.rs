@@ -1,25 +1,65 @@
-use super::allocator::Allocator;
+use super::allocator::{Allocation, Allocator, AllocatorStats};
+use super::error::Trap;
+use super::gc::{self, Collection, GcPacer};
 use super::growable_memory::GrowableMemoryBlock;
+use super::sanitizer::Sanitizer;
+use super::snapshot::{SnapshotError, SnapshotReader, SnapshotWriter};
 use super::types::{Pointer, Size};
-use super::CeWord;
-use crate::cerium::memory_buffer::{EndianConversion, MemoryBufferPtr};
+use super::{CeWord, VmConfig};
+use std::collections::HashSet;
+use crate::cerium::memory_buffer::{EndianConversion, MemoryBuffer, MemoryBufferPtr};
 
-#[derive(Default)]
+/// Guest memory. The top bits of a pointer select the region it points into: heap pointers have
+/// [`RAM::HEAP_PTR_BIT`] set, pointers into the program's read-only data have only
+/// [`RAM::STATIC_PTR_BIT`] set, and everything else points into the stack.
 pub struct RAM {
     stack_memory: GrowableMemoryBlock,
     heap_memory: GrowableMemoryBlock,
+    static_memory: MemoryBuffer,
     allocator: Allocator,
+    sanitizer: Option<Sanitizer>,
+    /// Tracks allocations to decide when to collect garbage, if that happens automatically
+    gc_pacer: Option<GcPacer>,
+    /// The address of the instruction being executed, which allocations are attributed to
+    current_ip: CeWord,
+}
+
+impl Default for RAM {
+    fn default() -> Self {
+        Self::new(&VmConfig::default())
+    }
 }
 
 impl RAM {
-    const HEAP_PTR_BIT: CeWord = (1 << (size_of::<CeWord>() * 8 - 1)) as CeWord;
+    pub const HEAP_PTR_BIT: CeWord = 1 << (CeWord::BITS - 1);
+    pub const STATIC_PTR_BIT: CeWord = 1 << (CeWord::BITS - 2);
+
+    pub fn new(config: &VmConfig) -> Self {
+        RAM {
+            stack_memory: GrowableMemoryBlock::new(config.stack_size(), config.stack_limit()),
+            heap_memory: GrowableMemoryBlock::new(config.heap_size(), config.heap_limit()),
+            static_memory: Default::default(),
+            allocator: Default::default(),
+            sanitizer: config.sanitize.then(|| Sanitizer::new(Self::HEAP_PTR_BIT)),
+            gc_pacer: config.garbage_collect.then(GcPacer::new),
+            current_ip: 0,
+        }
+    }
 
     fn is_heap_ptr(ptr: Pointer) -> bool {
         (CeWord::from(ptr) & Self::HEAP_PTR_BIT) != 0
     }
 
+    fn is_static_ptr(ptr: Pointer) -> bool {
+        !Self::is_heap_ptr(ptr) && (CeWord::from(ptr) & Self::STATIC_PTR_BIT) != 0
+    }
+
     fn ptr_to_mem_ptr(ptr: Pointer) -> Pointer {
-        (CeWord::from(ptr) & !Self::HEAP_PTR_BIT).into()
+        if Self::is_heap_ptr(ptr) {
+            (CeWord::from(ptr) & !Self::HEAP_PTR_BIT).into()
+        } else {
+            (CeWord::from(ptr) & !Self::STATIC_PTR_BIT).into()
+        }
     }
 
     fn mem_ptr_to_ptr(ptr: Pointer, is_heap: bool) -> Pointer {
@@ -30,62 +70,294 @@ impl RAM {
         }
     }
 
-    fn resize_mem_to_fit(&mut self, ptr: Pointer) -> Result<(), String> {
-        let mem_ptr = Self::ptr_to_mem_ptr(ptr);
-        if Self::is_heap_ptr(mem_ptr) {
-            self.heap_memory.resize_to_fit(mem_ptr.into())
+    /// The largest size the stack can grow to, in bytes
+    pub fn stack_limit(&self) -> CeWord {
+        self.stack_memory.max_size()
+    }
+
+    /// Records the address of the instruction being executed, as the site of the allocations and
+    /// frees it makes
+    #[inline(always)]
+    pub fn set_current_ip(&mut self, ip: CeWord) {
+        self.current_ip = ip;
+    }
+
+    pub fn heap_stats(&self) -> AllocatorStats {
+        self.allocator.stats()
+    }
+
+    /// The heap blocks that have not been freed, by ascending address
+    pub fn live_allocations(&self) -> Vec<Allocation> {
+        self.allocator.live_allocations().into_iter()
+            .map(|allocation| Allocation {
+                address: Self::mem_ptr_to_ptr(allocation.address.into(), true).into(),
+                ..allocation
+            })
+            .collect()
+    }
+
+    /// Whether garbage is collected automatically when the heap fills up
+    pub fn collects_garbage(&self) -> bool {
+        self.gc_pacer.is_some()
+    }
+
+    pub fn set_collects_garbage(&mut self, collect: bool) {
+        if collect != self.collects_garbage() {
+            self.gc_pacer = collect.then(GcPacer::new);
+        }
+    }
+
+    /// Whether heap accesses and frees are checked by the sanitizer
+    pub fn sanitizes(&self) -> bool {
+        self.sanitizer.is_some()
+    }
+
+    /// Turns the sanitizer on or off. The blocks that are already live when it is turned on are
+    /// tracked from then on, with their contents counting as written.
+    pub fn set_sanitizes(&mut self, sanitize: bool) {
+        if sanitize == self.sanitizes() {
+            return;
+        }
+        self.sanitizer = sanitize.then(|| {
+            let mut sanitizer = Sanitizer::new(Self::HEAP_PTR_BIT);
+            for allocation in self.live_allocations() {
+                sanitizer.on_existing_block(allocation.address, allocation.size, allocation.allocated_at);
+            }
+            sanitizer
+        });
+    }
+
+    /// Whether garbage should be collected before allocating `size` bytes
+    pub fn should_collect(&self, size: CeWord) -> bool {
+        self.gc_pacer.as_ref().is_some_and(|pacer| pacer.should_collect(size))
+    }
+
+    /// Frees every heap block that cannot be reached from the registers, whose contents are
+    /// `registers`, or from the stack below `stack_top`. The collection is conservative: any word with [`Self::HEAP_PTR_BIT`] set
+    /// that points into a block keeps it alive, and reachable blocks are scanned the same way.
+    pub fn collect_garbage(&mut self, registers: &[[u8; 8]], stack_top: CeWord) -> Collection {
+        let mut reachable = HashSet::new();
+        let mut unscanned = vec![];
+        let mut mark = |word: CeWord, unscanned: &mut Vec<(Pointer, Pointer)>| {
+            if word & Self::HEAP_PTR_BIT == 0 {
+                return;
+            }
+            if let Some(block) = self.allocator.used_block_containing((word & !Self::HEAP_PTR_BIT).into()) {
+                if reachable.insert(block.0) {
+                    unscanned.push(block);
+                }
+            }
+        };
+
+        let stack: &[u8] = (&self.stack_memory.memory).into();
+        for register in registers {
+            gc::scan_words(register, |word| mark(word, &mut unscanned));
+        }
+        gc::scan_words(&stack[..stack.len().min(stack_top as usize)], |word| mark(word, &mut unscanned));
+        while let Some((start, end)) = unscanned.pop() {
+            let heap: &[u8] = (&self.heap_memory.memory).into();
+            let block = &heap[CeWord::from(start) as usize..CeWord::from(end) as usize];
+            gc::scan_words(block, |word| mark(word, &mut unscanned));
+        }
+
+        let mut collection = Collection::default();
+        for allocation in self.allocator.live_allocations() {
+            if reachable.contains(&allocation.address.into()) {
+                continue;
+            }
+            let ptr = Self::mem_ptr_to_ptr(allocation.address.into(), true);
+            self.deallocate(ptr).expect("unreachable blocks are live");
+            collection.freed_blocks += 1;
+            collection.freed_bytes += allocation.size;
+        }
+
+        let live_bytes = self.allocator.live_bytes();
+        if let Some(pacer) = &mut self.gc_pacer {
+            pacer.on_collect(live_bytes);
+        }
+        collection
+    }
+
+    /// Lets the sanitizer check an access of `length` bytes at `ptr`, if it is on the heap
+    #[inline(always)]
+    fn sanitize(&mut self, ptr: Pointer, length: CeWord, is_write: bool) -> Result<(), Trap> {
+        match &mut self.sanitizer {
+            Some(sanitizer) if Self::is_heap_ptr(ptr) => sanitizer.check(ptr.into(), length, is_write),
+            _ => Ok(()),
+        }
+    }
+
+    /// Replaces the contents of the read-only data region
+    pub fn load_static_data(&mut self, data: &[u8]) {
+        self.static_memory = MemoryBuffer::from(data);
+    }
+
+    pub(super) fn save(&self, writer: &mut SnapshotWriter) {
+        writer.bytes((&self.static_memory).into());
+        self.stack_memory.save(writer);
+        self.heap_memory.save(writer);
+        self.allocator.save(writer);
+    }
+
+    pub(super) fn load(reader: &mut SnapshotReader) -> Result<RAM, SnapshotError> {
+        let static_memory = reader.bytes()?;
+        if static_memory.len() > Self::STATIC_PTR_BIT as usize {
+            return Err(SnapshotError::Inconsistent("read-only data is larger than its address space"));
+        }
+        let static_memory = MemoryBuffer::from(static_memory);
+        let stack_memory = GrowableMemoryBlock::load(reader, VmConfig::STACK_ADDRESS_SPACE)?;
+        let heap_memory = GrowableMemoryBlock::load(reader, VmConfig::HEAP_ADDRESS_SPACE)?;
+        let allocator = Allocator::load(reader)?;
+        if CeWord::from(allocator.heap_end()) > heap_memory.memory.size() {
+            return Err(SnapshotError::Inconsistent("heap blocks lie past the end of heap memory"));
+        }
+        Ok(RAM { stack_memory, heap_memory, static_memory, allocator, sanitizer: None, gc_pacer: None, current_ip: 0 })
+    }
+
+    /// Makes sure that the `length` bytes starting at `ptr` are backed by memory
+    fn resize_mem_to_fit(&mut self, ptr: Pointer, length: Size) -> Result<(), Trap> {
+        let mem_ptr = CeWord::from(Self::ptr_to_mem_ptr(ptr));
+        let out_of_bounds = Trap::OutOfBounds { address: ptr.into(), size: length.into() };
+
+        let end = mem_ptr.checked_add(length.into()).ok_or(out_of_bounds)?;
+        let result = if Self::is_heap_ptr(ptr) {
+            self.heap_memory.resize_to_fit(end)
+        } else if Self::is_static_ptr(ptr) {
+            if end <= self.static_memory.size() { Ok(()) } else { Err(out_of_bounds) }
         } else {
-            self.stack_memory.resize_to_fit(mem_ptr.into())
+            self.stack_memory.resize_to_fit(end)
+        };
+        result.map_err(|_| out_of_bounds)
+    }
+
+    /// Returns a pointer to guest memory for reading
+    pub fn at<T: EndianConversion>(&mut self, ptr: Pointer) -> Result<MemoryBufferPtr<T>, Trap> {
+        self.sanitize(ptr, size_of::<T>() as CeWord, false)?;
+        self.locate(ptr)
+    }
+
+    /// Returns a pointer to guest memory for writing
+    pub fn at_mut<T: EndianConversion>(&mut self, ptr: Pointer) -> Result<MemoryBufferPtr<T>, Trap> {
+        if Self::is_static_ptr(ptr) {
+            return Err(Trap::WriteToReadOnly { address: ptr.into() });
         }
+        self.sanitize(ptr, size_of::<T>() as CeWord, true)?;
+        self.locate(ptr)
     }
 
-    pub fn at<T: EndianConversion>(&mut self, ptr: Pointer) -> Result<MemoryBufferPtr<T>, String> {
+    /// Reads guest memory without changing it in any way: memory is not grown and the sanitizer
+    /// is not consulted. Returns `None` if the value lies outside of the memory that exists so far.
+    pub fn peek<T: EndianConversion>(&self, ptr: Pointer) -> Option<T> {
         let mem_ptr = Self::ptr_to_mem_ptr(ptr);
         if Self::is_heap_ptr(ptr) {
-            self.heap_memory.at(mem_ptr.into())
+            self.heap_memory.peek(mem_ptr)
+        } else if Self::is_static_ptr(ptr) {
+            let end = CeWord::from(mem_ptr).checked_add(size_of::<T>() as CeWord)?;
+            (end <= self.static_memory.size())
+                .then(|| self.static_memory.get::<T>(CeWord::from(mem_ptr) as usize).get())
         } else {
-            self.stack_memory.at(mem_ptr.into())
+            self.stack_memory.peek(mem_ptr)
         }
     }
 
-    pub fn allocate(&mut self, size: CeWord) -> Result<Pointer, String> {
-        let size: Size = size.into();
+    /// Returns a pointer to guest memory, growing the memory to fit if needed
+    fn locate<T: EndianConversion>(&mut self, ptr: Pointer) -> Result<MemoryBufferPtr<T>, Trap> {
+        let mem_ptr = Self::ptr_to_mem_ptr(ptr);
+        let result = if Self::is_heap_ptr(ptr) {
+            self.heap_memory.at(mem_ptr)
+        } else if Self::is_static_ptr(ptr) {
+            self.resize_mem_to_fit(ptr, (size_of::<T>() as CeWord).into())
+                .map(|_| self.static_memory.get(CeWord::from(mem_ptr) as usize))
+        } else {
+            self.stack_memory.at(mem_ptr)
+        };
+        // Report the address the guest actually used
+        result.map_err(|_| Trap::OutOfBounds { address: ptr.into(), size: size_of::<T>() as CeWord })
+    }
+
+    /// Copies `length` bytes of guest memory starting at `address`, like [`Self::peek`] without
+    /// growing memory or consulting the sanitizer
+    pub fn read_bytes(&self, address: CeWord, length: CeWord) -> Result<Vec<u8>, Trap> {
+        let out_of_bounds = Trap::OutOfBounds { address, size: length };
+        (0..length).map(|offset| {
+            let ptr = address.checked_add(offset).ok_or(out_of_bounds)?;
+            self.peek::<u8>(ptr.into()).ok_or(out_of_bounds)
+        }).collect()
+    }
 
-        if CeWord::from(size) == 0 {
-            return Err("CeriumVM error: allocation must not be empty".to_owned());
+    /// Copies `bytes` into guest memory starting at `address`
+    pub fn write_bytes(&mut self, address: CeWord, bytes: &[u8]) -> Result<(), Trap> {
+        let out_of_bounds = Trap::OutOfBounds { address, size: bytes.len() as CeWord };
+        let length = CeWord::try_from(bytes.len()).map_err(|_| out_of_bounds)?;
+        if length == 0 {
+            return Ok(());
         }
+        if Self::is_static_ptr(address.into()) {
+            return Err(Trap::WriteToReadOnly { address });
+        }
+        self.sanitize(address.into(), length, true)?;
+        self.resize_mem_to_fit(address.into(), length.into())?;
 
-        let heap_ptr = self.allocator.allocate(size.into());
-        if let Err(err) = self.resize_mem_to_fit(heap_ptr + size) {
-            return Err(err);
+        let dst_ptr = self.locate::<u8>(address.into())?.ptr();
+        unsafe {
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), dst_ptr, bytes.len());
         }
 
-        Ok(Self::mem_ptr_to_ptr(heap_ptr, true))
+        Ok(())
     }
 
-    pub fn deallocate(&mut self, ptr: Pointer) -> Result<(), String> {
+    pub fn allocate(&mut self, size: CeWord) -> Result<Pointer, Trap> {
+        if size == 0 {
+            return Err(Trap::EmptyAllocation);
+        }
+        if size > self.heap_memory.max_size() {
+            return Err(Trap::OutOfMemory { requested: size, limit: self.heap_memory.max_size() });
+        }
+
+        let heap_ptr = self.allocator.allocate(size.into(), self.current_ip, self.heap_memory.max_size())?;
+        self.heap_memory.resize_to_fit(CeWord::from(heap_ptr) + size)?;
+
+        let ptr = Self::mem_ptr_to_ptr(heap_ptr, true);
+        if let Some(sanitizer) = &mut self.sanitizer {
+            sanitizer.on_allocate(ptr.into(), size, self.current_ip);
+        }
+        if let Some(pacer) = &mut self.gc_pacer {
+            pacer.on_allocate(size);
+        }
+        Ok(ptr)
+    }
+
+    pub fn deallocate(&mut self, ptr: Pointer) -> Result<(), Trap> {
         if !Self::is_heap_ptr(ptr) {
-            return Err("CeriumVM Error: Attempting to deallocate non-heap pointer".to_owned());
+            return Err(Trap::InvalidFree { address: ptr.into() });
+        }
+        if let Some(sanitizer) = &mut self.sanitizer {
+            sanitizer.on_free(ptr.into(), self.current_ip)?;
         }
         let heap_ptr = Self::ptr_to_mem_ptr(ptr);
-        self.allocator.deallocate(heap_ptr)
+        self.allocator.deallocate(heap_ptr).map_err(|_| Trap::InvalidFree { address: ptr.into() })
     }
-    
-    pub fn memcpy(&mut self, src: Pointer, dst: Pointer, length: Size) -> Result<(), String> {
-        if let Err(err) = self.resize_mem_to_fit(src + length) {
-            return Err(err);
+
+    pub fn memcpy(&mut self, src: Pointer, dst: Pointer, length: Size) -> Result<(), Trap> {
+        if CeWord::from(length) == 0 {
+            return Ok(());
+        }
+        if Self::is_static_ptr(dst) {
+            return Err(Trap::WriteToReadOnly { address: dst.into() });
         }
-        if let Err(err) = self.resize_mem_to_fit(dst + length) {
-            return Err(err);
+        if let Some(sanitizer) = &mut self.sanitizer {
+            sanitizer.check_copy(src.into(), dst.into(), length.into())?;
         }
+        self.resize_mem_to_fit(src, length)?;
+        self.resize_mem_to_fit(dst, length)?;
+
+        let dst_ptr = self.locate::<i8>(dst)?.ptr() as *mut u8;
+        let src_ptr = self.locate::<i8>(src)?.ptr() as *const u8;
 
-        let dst_ptr = self.at::<i8>(dst)?.ptr() as *mut u8;
-        let src_ptr = self.at::<i8>(src)?.ptr() as *const u8;
-        
         unsafe {
             std::ptr::copy(src_ptr, dst_ptr, CeWord::from(length) as usize);
         }
-        
+
         Ok(())
     }
 }
\ No newline at end of file
@@ -1,16 +1,32 @@
-use super::CeWord;
 use crate::cerium::memory_buffer::{EndianConversion, MemoryBufferPtr};
 
+/// A 64-bit register. Every type is viewed from the start of the register, so writing a value
+/// narrower than 64 bits leaves the rest of the register unchanged.
 #[derive(Default)]
 pub struct Register {
-    value: CeWord,
+    value: u64,
 }
 
 impl Register {
     #[inline(always)]
     pub fn get<T: EndianConversion>(&mut self) -> MemoryBufferPtr<T> {
         unsafe {
-            MemoryBufferPtr::new((&mut self.value) as *mut CeWord)
+            MemoryBufferPtr::new((&mut self.value) as *mut u64)
         }
     }
-}
\ No newline at end of file
+
+    /// Reads the register's value as the given type without the possibility of modifying it
+    pub fn read<T: EndianConversion>(&self) -> T {
+        let mut value = self.value;
+        unsafe { MemoryBufferPtr::<T>::new((&mut value) as *mut u64).get() }
+    }
+
+    /// The register's bytes in the order its views see them
+    pub fn to_bytes(&self) -> [u8; 8] {
+        self.value.to_ne_bytes()
+    }
+
+    pub fn from_bytes(bytes: [u8; 8]) -> Register {
+        Register { value: u64::from_ne_bytes(bytes) }
+    }
+}
@@ -0,0 +1,220 @@
+use super::{CeWord, Trap};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MemoryErrorKind {
+    /// An access to a heap block that has been freed
+    UseAfterFree,
+    /// A `DEL` of a heap block that has already been freed
+    DoubleFree,
+    /// An access to heap memory that does not belong to any block
+    OutOfBounds,
+    /// A read of heap bytes that were never written since they were allocated
+    UninitializedRead,
+    /// A `DEL` of a pointer that is not the start of a heap block
+    InvalidFree,
+}
+
+impl Display for MemoryErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            MemoryErrorKind::UseAfterFree => "use after free",
+            MemoryErrorKind::DoubleFree => "double free",
+            MemoryErrorKind::OutOfBounds => "heap access out of bounds",
+            MemoryErrorKind::UninitializedRead => "read of uninitialized memory",
+            MemoryErrorKind::InvalidFree => "free of a pointer that is not the start of a heap block",
+        })
+    }
+}
+
+/// A heap block as the sanitizer remembers it
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct HeapBlock {
+    pub start: CeWord,
+    pub size: CeWord,
+    /// The address of the instruction that allocated the block
+    pub allocated_at: CeWord,
+    /// The address of the instruction that freed the block, if it has been freed
+    pub freed_at: Option<CeWord>,
+}
+
+/// What the sanitizer found wrong with a heap access
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SanitizerReport {
+    pub kind: MemoryErrorKind,
+    pub address: CeWord,
+    pub size: CeWord,
+    /// The block that the access was in or nearest to
+    pub block: Option<HeapBlock>,
+}
+
+impl Display for SanitizerReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            MemoryErrorKind::DoubleFree | MemoryErrorKind::InvalidFree => {
+                write!(f, "{} at 0x{:08x}", self.kind, self.address)?
+            }
+            _ => write!(f, "{}: access of {} bytes at 0x{:08x}", self.kind, self.size, self.address)?,
+        }
+
+        if let Some(block) = self.block {
+            let relation = if self.address < block.start {
+                format!("{} bytes before", block.start - self.address)
+            } else if self.address >= block.start + block.size {
+                format!("{} bytes after", self.address - (block.start + block.size))
+            } else {
+                format!("at offset {} of", self.address - block.start)
+            };
+            write!(
+                f, ", {} the {}-byte block at 0x{:08x} allocated at 0x{:08x}",
+                relation, block.size, block.start, block.allocated_at
+            )?;
+            if let Some(freed_at) = block.freed_at {
+                write!(f, " and freed at 0x{:08x}", freed_at)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The state of a single heap byte
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Shadow {
+    Unallocated,
+    Freed,
+    Uninitialized,
+    Initialized,
+}
+
+/// Tracks the state of every heap byte, so that heap accesses can be checked against the blocks
+/// they belong to. Addresses are guest heap pointers, and `offset` maps them to heap bytes.
+pub(super) struct Sanitizer {
+    shadow: Vec<Shadow>,
+    /// Every live block and the most recently freed block at each address, by start address
+    blocks: BTreeMap<CeWord, HeapBlock>,
+    heap_bit: CeWord,
+}
+
+impl Sanitizer {
+    pub fn new(heap_bit: CeWord) -> Self {
+        Sanitizer { shadow: vec![], blocks: Default::default(), heap_bit }
+    }
+
+    fn offset(&self, address: CeWord) -> usize {
+        (address & !self.heap_bit) as usize
+    }
+
+    fn shadow(&self, address: CeWord) -> Shadow {
+        self.shadow.get(self.offset(address)).copied().unwrap_or(Shadow::Unallocated)
+    }
+
+    fn set_shadow(&mut self, start: CeWord, length: CeWord, shadow: Shadow) {
+        let start = self.offset(start);
+        let end = start + length as usize;
+        if self.shadow.len() < end {
+            self.shadow.resize(end, Shadow::Unallocated);
+        }
+        self.shadow[start..end].fill(shadow);
+    }
+
+    /// The block containing `address`, or else the nearest block before it
+    fn block_near(&self, address: CeWord) -> Option<HeapBlock> {
+        let before = self.blocks.range(..=address).next_back().map(|(_, block)| *block);
+        before.or_else(|| self.blocks.range(address..).next().map(|(_, block)| *block))
+    }
+
+    fn report(&self, kind: MemoryErrorKind, address: CeWord, size: CeWord) -> Trap {
+        Trap::Sanitizer(SanitizerReport { kind, address, size, block: self.block_near(address) })
+    }
+
+    /// Records a block allocated by the instruction at `site`
+    pub fn on_allocate(&mut self, start: CeWord, size: CeWord, site: CeWord) {
+        let overlapping: Vec<CeWord> = self.blocks.range(..start + size)
+            .filter(|(_, block)| block.start + block.size > start)
+            .map(|(&address, _)| address)
+            .collect();
+        for address in overlapping {
+            self.blocks.remove(&address);
+        }
+
+        self.blocks.insert(start, HeapBlock { start, size, allocated_at: site, freed_at: None });
+        self.set_shadow(start, size, Shadow::Uninitialized);
+    }
+
+    /// Records a block that was allocated before the sanitizer started tracking the heap, like
+    /// one restored from a snapshot. Its contents are unknown, so they count as written.
+    pub fn on_existing_block(&mut self, start: CeWord, size: CeWord, site: CeWord) {
+        self.on_allocate(start, size, site);
+        self.set_shadow(start, size, Shadow::Initialized);
+    }
+
+    /// Checks that `address` may be freed, and marks its block as freed by the instruction at
+    /// `site`
+    pub fn on_free(&mut self, address: CeWord, site: CeWord) -> Result<(), Trap> {
+        match self.blocks.get_mut(&address) {
+            Some(block) if block.freed_at.is_none() => {
+                block.freed_at = Some(site);
+                let size = block.size;
+                self.set_shadow(address, size, Shadow::Freed);
+                Ok(())
+            }
+            Some(_) => Err(self.report(MemoryErrorKind::DoubleFree, address, 0)),
+            None => Err(self.report(MemoryErrorKind::InvalidFree, address, 0)),
+        }
+    }
+
+    /// Checks that the `length` bytes at `address` belong to a live block, and for reads that
+    /// they have been written
+    pub fn check(&mut self, address: CeWord, length: CeWord, is_write: bool) -> Result<(), Trap> {
+        for byte in address..address.saturating_add(length) {
+            let kind = match self.shadow(byte) {
+                Shadow::Initialized => continue,
+                Shadow::Uninitialized if is_write => continue,
+                Shadow::Uninitialized => MemoryErrorKind::UninitializedRead,
+                Shadow::Freed => MemoryErrorKind::UseAfterFree,
+                Shadow::Unallocated => MemoryErrorKind::OutOfBounds,
+            };
+            return Err(self.report(kind, address, length));
+        }
+
+        if is_write {
+            self.set_shadow(address, length, Shadow::Initialized);
+        }
+        Ok(())
+    }
+
+    /// Checks a copy from `src` to `dst`, either of which may be outside the heap. Uninitialized
+    /// bytes can be copied, and stay uninitialized.
+    pub fn check_copy(&mut self, src: CeWord, dst: CeWord, length: CeWord) -> Result<(), Trap> {
+        let is_heap = |address: CeWord| address & self.heap_bit != 0;
+        let accessible = |sanitizer: &Self, address: CeWord| {
+            for byte in address..address.saturating_add(length) {
+                let kind = match sanitizer.shadow(byte) {
+                    Shadow::Initialized | Shadow::Uninitialized => continue,
+                    Shadow::Freed => MemoryErrorKind::UseAfterFree,
+                    Shadow::Unallocated => MemoryErrorKind::OutOfBounds,
+                };
+                return Err(sanitizer.report(kind, address, length));
+            }
+            Ok(())
+        };
+
+        if is_heap(src) {
+            accessible(self, src)?;
+        }
+        if !is_heap(dst) {
+            return Ok(());
+        }
+        accessible(self, dst)?;
+
+        if is_heap(src) {
+            let shadow: Vec<Shadow> = (src..src + length).map(|byte| self.shadow(byte)).collect();
+            let start = self.offset(dst);
+            self.shadow[start..start + length as usize].copy_from_slice(&shadow);
+        } else {
+            self.set_shadow(dst, length, Shadow::Initialized);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+//! The snapshot format, which captures the complete state of a guest program so that it can be
+//! resumed later, possibly in another process.
+//!
+//! All integers are big-endian. A snapshot starts with a fixed header:
+//!
+//! ```text
+//! magic           4 bytes   "CESN"
+//! version         u16       must equal SNAPSHOT_VERSION
+//! reserved        u16       written as 0
+//! ```
+//!
+//! followed by the state of the VM, where `bytes` is a `u32` length followed by that many bytes:
+//!
+//! ```text
+//! instruction ptr u32
+//! done            u8        0 or 1
+//! registers       8 × 8 bytes, sp first, in the byte order the VM views them in
+//! call stack      u32 count, then a u32 return address each, outermost first
+//! imports         u32 count, then `name length: u16, name` each
+//! code            bytes
+//! read-only data  bytes
+//! stack           limit: u32, contents: bytes
+//! heap            limit: u32, contents: bytes
+//! allocator       end of heap: u32, block count: u32, then the blocks by ascending start
+//! ```
+//!
+//! Each allocator block is `start: u32, end: u32, free: u8, has previous: u8, previous: u32,
+//! allocated at: u32`, where `previous` is the start of the block before it (and 0 if there is
+//! none) and `allocated at` is the address of the instruction that allocated a used block (and 0
+//! for a free block).
+//!
+//! The I/O state, the host functions and any deadline belong to the host and are not part of a
+//! snapshot. Neither is the state of the sanitizer, so a restored VM that sanitizes treats every
+//! used block as initialized.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"CESN";
+pub const SNAPSHOT_VERSION: u16 = 2;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SnapshotError {
+    /// The data does not start with [`SNAPSHOT_MAGIC`]
+    BadMagic,
+    /// The snapshot was written for a different version of the format
+    UnsupportedVersion(u16),
+    /// The data ended in the middle of the snapshot
+    Truncated,
+    /// The snapshot describes a state the VM could never be in
+    Inconsistent(&'static str),
+    /// The data continues after the end of the snapshot
+    TrailingBytes,
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::BadMagic => write!(f, "not a snapshot (bad magic bytes)"),
+            SnapshotError::UnsupportedVersion(version) => write!(
+                f, "unsupported snapshot version {} (expected {})", version, SNAPSHOT_VERSION
+            ),
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::Inconsistent(reason) => write!(f, "snapshot is inconsistent: {}", reason),
+            SnapshotError::TrailingBytes => write!(f, "trailing bytes after the snapshot"),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+/// Appends big-endian values to a snapshot
+pub(super) struct SnapshotWriter {
+    bytes: Vec<u8>,
+}
+
+impl SnapshotWriter {
+    pub fn new() -> Self {
+        let mut bytes = SNAPSHOT_MAGIC.to_vec();
+        bytes.extend(SNAPSHOT_VERSION.to_be_bytes());
+        bytes.extend(0u16.to_be_bytes());
+        SnapshotWriter { bytes }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend(value.to_be_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend(value.to_be_bytes());
+    }
+
+    /// Appends raw bytes without a length
+    pub fn raw(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    /// Appends bytes preceded by their length
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.raw(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reads big-endian values from a snapshot
+pub(super) struct SnapshotReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> SnapshotReader<'a> {
+    /// Starts reading a snapshot after checking its header
+    pub fn new(bytes: &'a [u8]) -> Result<Self, SnapshotError> {
+        let mut reader = SnapshotReader { bytes, position: 0 };
+        if reader.raw(4).map_err(|_| SnapshotError::BadMagic)? != SNAPSHOT_MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        reader.u16()?;
+        Ok(reader)
+    }
+
+    /// Reads `length` raw bytes
+    pub fn raw(&mut self, length: usize) -> Result<&'a [u8], SnapshotError> {
+        let end = self.position.checked_add(length).ok_or(SnapshotError::Truncated)?;
+        let result = self.bytes.get(self.position..end).ok_or(SnapshotError::Truncated)?;
+        self.position = end;
+        Ok(result)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.raw(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, SnapshotError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SnapshotError::Inconsistent("flag is neither 0 nor 1")),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, SnapshotError> {
+        Ok(u16::from_be_bytes(self.raw(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_be_bytes(self.raw(4)?.try_into().unwrap()))
+    }
+
+    /// Reads bytes preceded by their length
+    pub fn bytes(&mut self) -> Result<&'a [u8], SnapshotError> {
+        let length = self.u32()? as usize;
+        self.raw(length)
+    }
+
+    /// Checks that the whole snapshot has been read
+    pub fn finish(self) -> Result<(), SnapshotError> {
+        if self.position == self.bytes.len() { Ok(()) } else { Err(SnapshotError::TrailingBytes) }
+    }
+}
@@ -0,0 +1,141 @@
+use super::{CeWord, ExecutionObserver, InstructionEvent, OperandValue, Value, VmError};
+use crate::cerium::symbol_map::SymbolMap;
+use std::io::Write;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TraceFormat {
+    /// One aligned line per instruction, like `0x00000010  add i r1 <- r1 + r2  r1=1 r2=2 -> r1=3`
+    Text,
+    /// One JSON object per line, with the fields `ip`, `instruction`, `sources` and
+    /// `destination`, or `ip` and `trap` for an instruction that trapped. With a symbol map, a
+    /// `symbol` field like `"FIB_RECURSE+0x1c"` follows `ip`.
+    JsonLines,
+}
+
+/// An [`ExecutionObserver`] that logs every executed instruction to a writer. Write errors are
+/// ignored, so that a full disk doesn't stop the guest program.
+pub struct Tracer<W: Write> {
+    output: W,
+    format: TraceFormat,
+    /// Shows code addresses relative to symbols, if set
+    symbols: Option<SymbolMap>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(output: W, format: TraceFormat) -> Self {
+        Tracer { output, format, symbols: None }
+    }
+
+    /// Shows code addresses relative to the symbols in a map, like `FIB_RECURSE+0x1c`
+    pub fn with_symbols(mut self, symbols: SymbolMap) -> Self {
+        self.symbols = Some(symbols);
+        self
+    }
+
+    fn describe(&self, address: CeWord) -> String {
+        match &self.symbols {
+            Some(symbols) => symbols.describe(address),
+            None => format!("0x{:08x}", address),
+        }
+    }
+
+    /// The `symbol` field of a JSON line, if there is a symbol map
+    fn json_symbol(&self, address: CeWord) -> String {
+        match &self.symbols {
+            Some(symbols) => format!(",\"symbol\":{}", json_string(&symbols.describe(address))),
+            None => String::new(),
+        }
+    }
+
+    /// Shows direct jump and call targets as absolute addresses
+    fn display_instruction(&self, event: &InstructionEvent) -> String {
+        match event.instruction.jump_target() {
+            Some(target) => event.instruction.to_string_with_target(&self.describe(target.resolve(event.ip))),
+            None => event.instruction.to_string(),
+        }
+    }
+
+    fn write_text(&mut self, event: &InstructionEvent) -> std::io::Result<()> {
+        let operand = |operand: &OperandValue| match operand.value {
+            Some(value) => format!("{}={}", operand.location, value),
+            None => format!("{}=?", operand.location),
+        };
+
+        // Symbols are longer than addresses, so they get a wider column
+        let width = if self.symbols.is_some() { 24 } else { 10 };
+        let mut line = format!("{:<width$}  {:<32}", self.describe(event.ip), self.display_instruction(event));
+        for source in &event.sources {
+            line.push(' ');
+            line.push_str(&operand(source));
+        }
+        if let Some(destination) = &event.destination {
+            line.push_str(" -> ");
+            line.push_str(&operand(destination));
+        }
+        writeln!(self.output, "{}", line.trim_end())
+    }
+
+    fn write_json(&mut self, event: &InstructionEvent) -> std::io::Result<()> {
+        let operand = |operand: &OperandValue| format!(
+            "{{\"operand\":{},\"type\":{},\"value\":{}}}",
+            json_string(&operand.location.to_string()),
+            json_string(&operand.ty.to_string()),
+            operand.value.map_or_else(|| "null".to_owned(), json_value),
+        );
+
+        let sources: Vec<String> = event.sources.iter().map(operand).collect();
+        writeln!(
+            self.output,
+            "{{\"ip\":{}{},\"instruction\":{},\"sources\":[{}],\"destination\":{}}}",
+            event.ip,
+            self.json_symbol(event.ip),
+            json_string(&self.display_instruction(event)),
+            sources.join(","),
+            event.destination.as_ref().map_or_else(|| "null".to_owned(), operand),
+        )
+    }
+}
+
+impl<W: Write> ExecutionObserver for Tracer<W> {
+    fn on_instruction(&mut self, event: &InstructionEvent) {
+        let _ = match self.format {
+            TraceFormat::Text => self.write_text(event),
+            TraceFormat::JsonLines => self.write_json(event),
+        };
+    }
+
+    fn on_trap(&mut self, error: &VmError) {
+        let _ = match self.format {
+            TraceFormat::Text => writeln!(self.output, "{}  trap: {}", self.describe(error.ip), error.trap),
+            TraceFormat::JsonLines => writeln!(
+                self.output,
+                "{{\"ip\":{}{},\"trap\":{}}}",
+                error.ip,
+                self.json_symbol(error.ip),
+                json_string(&error.trap.to_string()),
+            ),
+        };
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut result = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if c.is_control() => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// JSON has no representation for infinities and NaN, so those become `null`
+fn json_value(value: Value) -> String {
+    match value {
+        Value::Float(value) if !value.is_finite() => "null".to_owned(),
+        value => value.to_string(),
+    }
+}
@@ -2,10 +2,15 @@ use std::fmt::{Debug, Formatter};
 use std::ops::{Add, Sub};
 
 pub type CeWord = u32;
+pub type CeInt64 = i64;
 pub type CeInt32 = i32;
 pub type CeInt16 = i16;
 pub type CeInt8 = i8;
+pub type CeUInt32 = u32;
+pub type CeUInt16 = u16;
+pub type CeUInt8 = u8;
 pub type CeFloat = f32;
+pub type CeDouble = f64;
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Default)]
 #[repr(transparent)]
@@ -1,282 +1,642 @@
-// #![allow(arithmetic_overflow)]
-
+use super::arithmetic::Arithmetic;
 use super::register::Register;
-use super::{CeFloat, CeInt16, CeInt32, CeInt8, CeWord, Pointer, RAM};
-use crate::cerium::memory_buffer::{EndianConversion, MemoryBuffer, MemoryBufferPtr};
-use std::hint::unreachable_unchecked;
-use std::ops::*;
-use text_io::read;
+use super::snapshot::{SnapshotError, SnapshotReader, SnapshotWriter};
+use super::{Allocation, AllocatorStats, CeDouble, Collection, CeFloat, CeInt16, CeInt32, CeInt64, CeInt8, CeUInt16, CeUInt32, CeUInt8, CeWord, ExecutionObserver, HostContext, HostFunctions, InstructionEvent, OperandValue, Pointer, StdIo, Trap, Value, VmConfig, VmError, VmIo, RAM};
+use crate::cerium::instruction::instruction_parts::{self, BinOp, Condition, Immediate, Location, Type, UnOp};
+use crate::cerium::instruction::{DecodeError, Instruction};
+use crate::cerium::memory_buffer::{EndianConversion, MemoryBuffer};
+use crate::cerium::program::Program;
+use std::time::Instant;
+
+/// Why [`CeriumVM::run`] stopped executing
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RunOutcome {
+    /// The program executed a `HALT` instruction
+    Halted,
+    /// The instruction limit was reached before the program halted. Calling [`CeriumVM::run`]
+    /// again continues where execution stopped.
+    OutOfFuel,
+    /// The deadline set with [`CeriumVM::set_deadline`] passed before the program halted.
+    /// Execution can be continued after moving or clearing the deadline.
+    TimedOut,
+    /// An instruction trapped, leaving the instruction pointer at the faulting instruction
+    Trapped(VmError),
+}
 
 #[derive(Default)]
-pub struct CeriumVM {
+pub struct CeriumVM<Io: VmIo = StdIo> {
     memory: RAM,
     registers: [Register; 8],
     instruction_ptr: CeWord,
     program: MemoryBuffer,
     done: bool,
+    io: Io,
+    host_functions: HostFunctions,
+    imports: Vec<String>,
+    /// The return addresses pushed by `CALL` instructions that have not returned yet
+    call_stack: Vec<CeWord>,
+    deadline: Option<Instant>,
+    observer: Option<Box<dyn ExecutionObserver>>,
 }
 
-impl CeriumVM {
-    pub fn new() -> CeriumVM { Default::default() }
+impl<Io: VmIo> CeriumVM<Io> {
+    const SP: Location = Location { register: instruction_parts::Register::SP, indirect: false };
+    /// How many instructions [`Self::run`] executes between looks at the clock
+    const DEADLINE_CHECK_INTERVAL: u64 = 1 << 12;
 
-    pub fn load_program(&mut self, program: impl Into<MemoryBuffer>) {
-        self.program = program.into();
-        self.instruction_ptr = 0;
+    pub fn new(io: Io) -> CeriumVM<Io> {
+        Self::with_config(io, VmConfig::default())
+    }
+
+    /// Creates a VM whose memory is limited according to `config`
+    pub fn with_config(io: Io, config: VmConfig) -> CeriumVM<Io> {
+        CeriumVM {
+            memory: RAM::new(&config),
+            registers: Default::default(),
+            instruction_ptr: 0,
+            program: Default::default(),
+            done: false,
+            io,
+            host_functions: Default::default(),
+            imports: vec![],
+            call_stack: vec![],
+            deadline: None,
+            observer: None,
+        }
+    }
+
+    pub fn io(&self) -> &Io {
+        &self.io
+    }
+
+    pub fn io_mut(&mut self) -> &mut Io {
+        &mut self.io
+    }
+
+    /// The functions that the guest can call with `SYSCALL` and `CALLHOST`
+    pub fn host_functions_mut(&mut self) -> &mut HostFunctions {
+        &mut self.host_functions
+    }
+
+    /// Loads the code and read-only data of a program, and moves the instruction pointer to its
+    /// entry point
+    pub fn load_program(&mut self, program: &Program) {
+        self.program = MemoryBuffer::from(program.code.as_slice());
+        self.memory.load_static_data(&program.rodata);
+        self.imports = program.imports.clone();
+        self.instruction_ptr = program.entry_point;
+        self.call_stack.clear();
+        self.done = false;
     }
 
     #[inline(always)]
-    fn get_register<T: EndianConversion>(&mut self, bits: u8) -> MemoryBufferPtr<T> {
-        self.registers.get_mut((bits & 0b111) as usize).unwrap().get()
+    fn read_register<T: EndianConversion>(&mut self, location: Location) -> T {
+        self.registers[location.register as usize].get().get()
     }
 
     #[inline(always)]
-    fn get_memory<T: EndianConversion>(&mut self, bits: u8) -> MemoryBufferPtr<T> {
-        let register_value = self.get_register::<CeInt32>(bits).get() as CeWord;
-        self.memory.at(Pointer::new(register_value)).unwrap()
+    fn address_of(&mut self, location: Location) -> Pointer {
+        Pointer::new(self.read_register::<CeInt32>(location) as CeWord)
     }
 
     #[inline(always)]
-    fn get_location<T: EndianConversion>(&mut self, bits: u8) -> MemoryBufferPtr<T> {
-        if (bits & 0b1000) != 0 {
-            self.get_memory(bits)
+    fn read<T: EndianConversion>(&mut self, location: Location) -> Result<T, Trap> {
+        if location.indirect {
+            let address = self.address_of(location);
+            Ok(self.memory.at::<T>(address)?.get())
         } else {
-            self.get_register(bits)
+            Ok(self.read_register(location))
         }
     }
 
     #[inline(always)]
-    fn get_word_for_location(&mut self, bits: u8) -> CeWord {
-        self.get_location::<CeInt32>(bits).get() as CeWord
+    fn write<T: EndianConversion>(&mut self, location: Location, value: T) -> Result<(), Trap> {
+        unsafe {
+            if location.indirect {
+                let address = self.address_of(location);
+                self.memory.at_mut::<T>(address)?.write(value);
+            } else {
+                self.registers[location.register as usize].get().write(value);
+            }
+        }
+        Ok(())
     }
 
     #[inline(always)]
-    fn get_next_and_inc_ip<T: EndianConversion>(&mut self) -> T {
-        let res = self.program.get::<T>(self.instruction_ptr as usize).get();
-        self.instruction_ptr += size_of::<T>() as CeWord;
-        res
-    }
-
-    pub fn execute_next_instruction(&mut self) {
-        let curr_instruction_byte = self.get_next_and_inc_ip::<u8>();
-
-        if (curr_instruction_byte >> 6) == 3 {
-            // Ternary instructions
-            let instruction_part = curr_instruction_byte & 0b00001111;
-            let type_part = (curr_instruction_byte & 0b00110000) >> 4;
-
-            let b2 = self.get_next_and_inc_ip::<u8>();
-            let b3 = self.get_next_and_inc_ip::<u8>();
-
-            macro_rules! do_instruction {
-                ($op: expr, $type_part: expr, $b2: expr, $b3: expr; $reason: expr) => {
-                    match $type_part {
-                        0b00 => self.do_binop::<CeInt8>($b2, $b3, $op),
-                        0b01 => self.do_binop::<CeInt16>($b2, $b3, $op),
-                        0b10 => self.do_binop::<CeInt32>($b2, $b3, $op),
-                        0b11 => panic!($reason),
-                        _ => unsafe {unreachable_unchecked()}
-                    }
-                };
-                
-                ($op: expr, $type_part: expr, $b2: expr, $b3: expr) => {
-                    match $type_part {
-                        0b00 => self.do_binop::<CeInt8>($b2, $b3, $op),
-                        0b01 => self.do_binop::<CeInt16>($b2, $b3, $op),
-                        0b10 => self.do_binop::<CeInt32>($b2, $b3, $op),
-                        0b11 => self.do_binop::<CeFloat>($b2, $b3, $op),
-                        _ => unsafe {unreachable_unchecked()}
-                    }
-                };
-                (call $method: ident, $type_part: expr, $b2: expr, $b3: expr) => {
-                    match $type_part { // JMP
-                        0b00 => self.$method ::<CeInt8>($b2, $b3),
-                        0b01 => self.$method ::<CeInt16>($b2, $b3),
-                        0b10 => self.$method ::<CeInt32>($b2, $b3),
-                        0b11 => self.$method ::<CeFloat>($b2, $b3),
-                        _ => unsafe {unreachable_unchecked()}
-                    }
-                };
-            }
+    fn read_word(&mut self, location: Location) -> Result<CeWord, Trap> {
+        Ok(self.read::<CeInt32>(location)? as CeWord)
+    }
+
+    pub fn instruction_ptr(&self) -> CeWord {
+        self.instruction_ptr
+    }
+
+    /// The return addresses of the calls currently in progress, innermost last
+    pub fn call_stack(&self) -> &[CeWord] {
+        &self.call_stack
+    }
+
+    /// Reads the value of a register as the given type
+    pub fn register<T: EndianConversion>(&self, register: instruction_parts::Register) -> T {
+        self.registers[register as usize].read()
+    }
+
+    /// A summary of the state of the heap
+    pub fn heap_stats(&self) -> AllocatorStats {
+        self.memory.heap_stats()
+    }
 
-            match instruction_part {
-                0b0000 => (), // NO-OP
-                0b0001 => do_instruction!(BitXor::bitxor, type_part, b2, b3; "Cannot apply XOR to float"),
-                0b0010 => do_instruction!(BitOr::bitor, type_part, b2, b3; "Cannot apply OR to float"),
-                0b0011 => do_instruction!(BitAnd::bitand, type_part, b2, b3; "Cannot apply AND to float"),
-                0b0100 | 0b0101 => (), // NO-OP
-                0b0110 => do_instruction!(Shl::shl, type_part, b2, b3; "Cannot apply SHL to float"),
-                0b0111 => do_instruction!(Shr::shr, type_part, b2, b3; "Cannot apply SHR to float"),
-                0b1000 => (), // NO-OP
-                0b1001 => do_instruction!(Mul::mul, type_part, b2, b3),
-                0b1010 => do_instruction!(Add::add, type_part, b2, b3),
-                0b1011 => do_instruction!(Sub::sub, type_part, b2, b3),
-                0b1100 => do_instruction!(Div::div, type_part, b2, b3),
-                0b1101 => do_instruction!(modulo, type_part, b2, b3),
-                0b1110 => do_instruction!(call cmp_instr, type_part, b2, b3),
-                0b1111 => do_instruction!(call jmp_instr, type_part, b2, b3),
-                _ => unsafe { unreachable_unchecked() }
+    /// The heap blocks that have not been freed, by ascending address
+    pub fn live_allocations(&self) -> Vec<Allocation> {
+        self.memory.live_allocations()
+    }
+
+    /// Reads `length` bytes of guest memory starting at `address`
+    pub fn read_memory(&self, address: CeWord, length: CeWord) -> Result<Vec<u8>, Trap> {
+        self.memory.read_bytes(address, length)
+    }
+
+    /// Decodes the instruction at the instruction pointer, returning it along with its size
+    pub fn fetch(&self) -> Result<(Instruction, usize), Trap> {
+        let program: &[u8] = (&self.program).into();
+        let code = program.get(self.instruction_ptr as usize..).unwrap_or_default();
+
+        Instruction::decode(code).map_err(|err| match err {
+            DecodeError::UnexpectedEnd => Trap::IpOutOfBounds,
+            DecodeError::InvalidOpcode(byte) => Trap::InvalidOpcode(byte),
+        })
+    }
+
+    /// Executes a single instruction. If the instruction traps, the instruction pointer is left
+    /// pointing at the faulting instruction.
+    pub fn execute_next_instruction(&mut self) -> Result<(), VmError> {
+        if self.observer.is_some() {
+            return self.execute_next_instruction_observed();
+        }
+
+        let ip = self.instruction_ptr;
+
+        let (instruction, size) = self.fetch().map_err(|trap| VmError {
+            trap,
+            ip,
+            instruction: None,
+        })?;
+
+        self.instruction_ptr += size as CeWord;
+
+        self.execute(instruction, ip).map_err(|trap| {
+            self.instruction_ptr = ip;
+            VmError { trap, ip, instruction: Some(instruction) }
+        })
+    }
+
+    /// Like [`Self::execute_next_instruction`], but collects the operand values for the observer
+    #[inline(never)]
+    fn execute_next_instruction_observed(&mut self) -> Result<(), VmError> {
+        let ip = self.instruction_ptr;
+        let mut observer = self.observer.take().unwrap();
+
+        let event = self.fetch().ok().map(|(instruction, _)| {
+            let sources = instruction.sources().into_iter()
+                .map(|(location, ty)| self.operand_value(location, ty))
+                .collect();
+            InstructionEvent { ip, instruction, sources, destination: None, next_ip: ip, sp: 0 }
+        });
+
+        let result = self.execute_next_instruction();
+        match (&result, event) {
+            (Ok(()), Some(mut event)) => {
+                event.destination = event.instruction.destination()
+                    .map(|(location, ty)| self.operand_value(location, ty));
+                event.next_ip = self.instruction_ptr;
+                event.sp = self.register::<CeWord>(instruction_parts::Register::SP);
+                observer.on_instruction(&event);
             }
+            (Err(err), _) => observer.on_trap(err),
+            (Ok(()), None) => {}
+        }
+
+        self.observer = Some(observer);
+        result
+    }
+
+    /// Reads an operand for an observer, which must not change the state of the VM, so memory
+    /// that does not exist yet has no value instead of being grown
+    fn operand_value(&self, location: Location, ty: Type) -> OperandValue {
+        let value = match ty {
+            Type::Int8 => self.peek::<CeInt8>(location).map(|value| Value::Int(value.into())),
+            Type::Int16 => self.peek::<CeInt16>(location).map(|value| Value::Int(value.into())),
+            Type::Int32 => self.peek::<CeInt32>(location).map(|value| Value::Int(value.into())),
+            Type::Int64 => self.peek::<CeInt64>(location).map(Value::Int),
+            Type::UInt8 => self.peek::<CeUInt8>(location).map(|value| Value::UInt(value.into())),
+            Type::UInt16 => self.peek::<CeUInt16>(location).map(|value| Value::UInt(value.into())),
+            Type::UInt32 => self.peek::<CeUInt32>(location).map(|value| Value::UInt(value.into())),
+            Type::Float => self.peek::<CeFloat>(location).map(|value| Value::Float(value.into())),
+            Type::Double => self.peek::<CeDouble>(location).map(Value::Float),
+        };
+        OperandValue { location, ty, value }
+    }
+
+    /// Reads a location without growing memory, or `None` if it points outside of guest memory
+    fn peek<T: EndianConversion>(&self, location: Location) -> Option<T> {
+        let register = &self.registers[location.register as usize];
+        if location.indirect {
+            self.memory.peek(Pointer::new(register.read::<CeInt32>() as CeWord))
         } else {
-            let instruction_part = curr_instruction_byte >> 4;
-            match instruction_part {
-                0b0000 => { // MOV
-                    let b2 = self.get_next_and_inc_ip::<u8>();
-                    let src_t = (curr_instruction_byte >> 2) & 0b11;
-                    let dst_t = curr_instruction_byte & 0b11;
-
-                    macro_rules! mov_match_case {
-                        (type = $t: ty, $t2: ident, $b2: ident) => {
-                            unsafe {
-                                let val = self.get_location::<$t>($b2 >> 4).get();
-                                match $t2 {
-                                    0b00 => self.get_location::<CeInt8>($b2).write((val as CeInt8)),
-                                    0b01 => self.get_location::<CeInt16>($b2).write((val as CeInt16)),
-                                    0b10 => self.get_location::<CeInt32>($b2).write((val as CeInt32)),
-                                    0b11 => self.get_location::<CeFloat>($b2).write((val as CeFloat)),
-                                    _ => unreachable_unchecked()
-                                }
-                            }
-                        };
-                    }
-
-                    match src_t {
-                        0b00 => mov_match_case!(type = CeInt8, dst_t, b2),
-                        0b01 => mov_match_case!(type = CeInt16, dst_t, b2),
-                        0b10 => mov_match_case!(type = CeInt32, dst_t, b2),
-                        0b11 => mov_match_case!(type = CeFloat, dst_t, b2),
-                        _ => unsafe { unreachable_unchecked() }
-                    }
-                }
-                0b0001 => { // LOD8
-                    let dat = self.get_next_and_inc_ip::<CeInt8>();
-                    self.lod_instr(curr_instruction_byte, dat);
-                }
-                0b0010 => { // LOD16
-                    let dat = self.get_next_and_inc_ip::<CeInt16>();
-                    self.lod_instr(curr_instruction_byte, dat);
-                }
-                0b0011 => { // LOD32
-                    let dat = self.get_next_and_inc_ip::<CeInt32>();
-                    self.lod_instr(curr_instruction_byte, dat);
-                }
-                0b0100 => {
-                    self.done = true;
-                } // NOOP
-                0b0101 => {
-                    // MEMCPY
-                    let b2 = self.get_next_and_inc_ip::<u8>();
-
-                    let size = self.get_location::<CeInt32>(curr_instruction_byte).get() as CeWord;
-                    let src = self.get_location::<CeInt32>(b2 >> 4).get() as CeWord;
-                    let dest = self.get_location::<CeInt32>(b2).get() as CeWord;
-
-                    self.memory.memcpy(src.into(), dest.into(), size.into()).unwrap();
-                }
-                0b0110 => { // NEW
-                    let b2 = self.get_next_and_inc_ip::<u8>();
-                    let size = self.get_location::<CeInt32>(b2 >> 4).get() as CeWord;
-                    let res = CeWord::from(self.memory.allocate(size).unwrap());
-
-                    unsafe {
-                        self.get_location::<CeInt32>(b2).write(res as CeInt32);
-                    }
-                }
-                0b0111 => { // DEL
-                    let b2 = self.get_next_and_inc_ip::<u8>();
-                    let src = self.get_location::<CeInt32>(b2 >> 4).get() as CeWord;
-                    self.memory.deallocate(src.into()).unwrap();
-                }
-                0b1000 => { // NEG
-                    let type_part = (curr_instruction_byte >> 2) & 0b11;
-                    let b2 = self.get_next_and_inc_ip::<u8>();
-
-                    match type_part {
-                        0b00 => self.do_unop::<CeInt8>(b2, Neg::neg),
-                        0b01 => self.do_unop::<CeInt16>(b2, Neg::neg),
-                        0b10 => self.do_unop::<CeInt32>(b2, Neg::neg),
-                        0b11 => self.do_unop::<CeFloat>(b2, Neg::neg),
-                        _ => unsafe { unreachable_unchecked() }
-                    }
-                }
-                0b1001 => {
-                    // Bitwise negation
-                    let type_part = (curr_instruction_byte >> 2) & 0b11;
-                    let b2 = self.get_next_and_inc_ip::<u8>();
-
-                    match type_part {
-                        0b00 => self.do_unop::<CeInt8>(b2, Not::not),
-                        0b01 => self.do_unop::<CeInt16>(b2, Not::not),
-                        0b10 => self.do_unop::<CeInt32>(b2, Not::not),
-                        0b11 => panic!("Cannot apply bitwise negation to float"),
-                        _ => unsafe { unreachable_unchecked() }
-                    }
+            Some(register.read())
+        }
+    }
+
+    /// Installs an observer that is told about every instruction executed from now on, replacing
+    /// the previous one, or removes it with `None`
+    pub fn set_observer(&mut self, observer: Option<Box<dyn ExecutionObserver>>) {
+        self.observer = observer;
+    }
+
+    /// Removes the observer and gives it back, for example to flush its output
+    pub fn take_observer(&mut self) -> Option<Box<dyn ExecutionObserver>> {
+        self.observer.take()
+    }
+
+    /// Captures the state of the guest program in the format described in
+    /// [`snapshot`](super::snapshot), so that [`Self::restore`] can continue it later
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut writer = SnapshotWriter::new();
+        writer.u32(self.instruction_ptr);
+        writer.u8(self.done as u8);
+        for register in &self.registers {
+            writer.raw(&register.to_bytes());
+        }
+        writer.u32(self.call_stack.len() as u32);
+        for &return_address in &self.call_stack {
+            writer.u32(return_address);
+        }
+        writer.u32(self.imports.len() as u32);
+        for name in &self.imports {
+            writer.u16(name.len() as u16);
+            writer.raw(name.as_bytes());
+        }
+        writer.bytes((&self.program).into());
+        self.memory.save(&mut writer);
+        writer.finish()
+    }
+
+    /// Replaces the state of the guest program with one captured by [`Self::snapshot`]. The I/O,
+    /// host functions, deadline, whether garbage is collected automatically and whether the
+    /// sanitizer is on are kept, while the memory limits are the ones in the snapshot. Nothing
+    /// changes if the snapshot is invalid.
+    pub fn restore(&mut self, snapshot: &[u8]) -> Result<(), SnapshotError> {
+        let mut reader = SnapshotReader::new(snapshot)?;
+        let instruction_ptr = reader.u32()?;
+        let done = reader.bool()?;
+        let mut registers: [Register; 8] = Default::default();
+        for register in &mut registers {
+            *register = Register::from_bytes(reader.raw(8)?.try_into().unwrap());
+        }
+        let call_stack = (0..reader.u32()?)
+            .map(|_| reader.u32())
+            .collect::<Result<Vec<_>, _>>()?;
+        let imports = (0..reader.u32()?)
+            .map(|_| {
+                let length = reader.u16()? as usize;
+                std::str::from_utf8(reader.raw(length)?)
+                    .map(str::to_owned)
+                    .map_err(|_| SnapshotError::Inconsistent("import name is not valid UTF-8"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let program = MemoryBuffer::from(reader.bytes()?);
+        let mut memory = RAM::load(&mut reader)?;
+        reader.finish()?;
+        memory.set_collects_garbage(self.memory.collects_garbage());
+        memory.set_sanitizes(self.memory.sanitizes());
+
+        self.instruction_ptr = instruction_ptr;
+        self.done = done;
+        self.registers = registers;
+        self.call_stack = call_stack;
+        self.imports = imports;
+        self.program = program;
+        self.memory = memory;
+        Ok(())
+    }
+
+    /// Stops [`Self::run`] with [`RunOutcome::TimedOut`] once `deadline` has passed. The clock is
+    /// only checked every few thousand instructions, so a run may overshoot the deadline slightly.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Executes at most `limit` instructions, stopping early if the program halts, traps or runs
+    /// past its deadline
+    pub fn run(&mut self, limit: u64) -> RunOutcome {
+        for step in 0..limit {
+            if self.done {
+                return RunOutcome::Halted;
+            }
+            if step % Self::DEADLINE_CHECK_INTERVAL == 0
+                && self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return RunOutcome::TimedOut;
+            }
+            if let Err(err) = self.execute_next_instruction() {
+                return RunOutcome::Trapped(err);
+            }
+        }
+
+        if self.done { RunOutcome::Halted } else { RunOutcome::OutOfFuel }
+    }
+
+    /// Executes an instruction that was fetched from `ip`
+    fn execute(&mut self, instruction: Instruction, ip: CeWord) -> Result<(), Trap> {
+        self.memory.set_current_ip(ip);
+
+        macro_rules! with_type {
+            ($ty: expr, $method: ident ($($arg: expr),*)) => {
+                match $ty {
+                    Type::Int8 => self.$method::<CeInt8>($($arg),*),
+                    Type::Int16 => self.$method::<CeInt16>($($arg),*),
+                    Type::Int32 => self.$method::<CeInt32>($($arg),*),
+                    Type::Float => self.$method::<CeFloat>($($arg),*),
+                    Type::UInt8 => self.$method::<CeUInt8>($($arg),*),
+                    Type::UInt16 => self.$method::<CeUInt16>($($arg),*),
+                    Type::UInt32 => self.$method::<CeUInt32>($($arg),*),
+                    Type::Int64 => self.$method::<CeInt64>($($arg),*),
+                    Type::Double => self.$method::<CeDouble>($($arg),*),
                 }
-                0b1010 => {
-                    print!("<CeriumVM> Enter a number: ");
-                    unsafe {
-                        self.get_location::<CeInt32>(curr_instruction_byte).write(read!());
-                    }
+            };
+        }
+
+        match instruction {
+            Instruction::Mov { src_ty, dst_ty, src, dst } => {
+                macro_rules! mov_match_case {
+                    (type = $t: ty) => {{
+                        let val = self.read::<$t>(src)?;
+                        match dst_ty {
+                            Type::Int8 => self.write(dst, val as CeInt8),
+                            Type::Int16 => self.write(dst, val as CeInt16),
+                            Type::Int32 => self.write(dst, val as CeInt32),
+                            Type::Float => self.write(dst, val as CeFloat),
+                            Type::UInt8 => self.write(dst, val as CeUInt8),
+                            Type::UInt16 => self.write(dst, val as CeUInt16),
+                            Type::UInt32 => self.write(dst, val as CeUInt32),
+                            Type::Int64 => self.write(dst, val as CeInt64),
+                            Type::Double => self.write(dst, val as CeDouble),
+                        }
+                    }};
                 }
-                0b1011 => {
-                    println!("{}", self.get_location::<CeInt32>(curr_instruction_byte).get());
+
+                match src_ty {
+                    Type::Int8 => mov_match_case!(type = CeInt8),
+                    Type::Int16 => mov_match_case!(type = CeInt16),
+                    Type::Int32 => mov_match_case!(type = CeInt32),
+                    Type::Float => mov_match_case!(type = CeFloat),
+                    Type::UInt8 => mov_match_case!(type = CeUInt8),
+                    Type::UInt16 => mov_match_case!(type = CeUInt16),
+                    Type::UInt32 => mov_match_case!(type = CeUInt32),
+                    Type::Int64 => mov_match_case!(type = CeInt64),
+                    Type::Double => mov_match_case!(type = CeDouble),
                 }
-                _ => unsafe { unreachable_unchecked() }
+            }
+            Instruction::Lod8(dst, dat) => self.write(dst, dat as CeInt8),
+            Instruction::Lod16(dst, dat) => self.write(dst, dat as CeInt16),
+            Instruction::Lod32(dst, dat) => self.write(dst, dat as CeInt32),
+            Instruction::Lod64(dst, dat) => self.write(dst, dat as CeInt64),
+            Instruction::Halt => {
+                self.done = true;
+                Ok(())
+            }
+            Instruction::Memcpy { src, dst, size } => {
+                let size = self.read_word(size)?;
+                let src = self.read_word(src)?;
+                let dst = self.read_word(dst)?;
+
+                self.memory.memcpy(src.into(), dst.into(), size.into())
+            }
+            Instruction::New { size, dst } => {
+                let size = self.read_word(size)?;
+                let res = CeWord::from(self.allocate(size)?);
+
+                self.write(dst, res as CeInt32)
+            }
+            Instruction::Del { src } => {
+                let src = self.read_word(src)?;
+                self.memory.deallocate(src.into())
+            }
+            Instruction::Cmp { ty, src, dst, cnd } => with_type!(ty, cmp_instr(src, dst, cnd)),
+            Instruction::Jmp { ty, src, tgt, cnd } => with_type!(ty, jmp_instr(src, tgt, cnd)),
+            Instruction::BinOp { op, ty, src1, src2, dst } => {
+                with_type!(ty, do_binop(op, src1, src2, dst))
+            }
+            Instruction::UnOp { op, ty, src, dst } => with_type!(ty, do_unop(op, src, dst)),
+            Instruction::BinOpImm { op, ty, src, imm, dst } => {
+                with_type!(ty, do_binop_imm(op, src, imm, dst))
+            }
+            Instruction::CmpImm { ty, src, imm, dst, cnd } => with_type!(ty, cmp_imm_instr(src, imm, dst, cnd)),
+            Instruction::JmpTo { ty, src, target, cnd, imm } => {
+                with_type!(ty, jmp_to_instr(src, target.resolve(ip), cnd, imm))
+            }
+            Instruction::Input(dst) => {
+                let value = self.io.input()?;
+                self.write(dst, value)
+            }
+            Instruction::Output(src) => {
+                let value = self.read::<CeInt32>(src)?;
+                self.io.output(value)
+            }
+            Instruction::Call(tgt) => {
+                let target = self.read_word(tgt)?;
+                self.push(self.instruction_ptr as CeInt32)?;
+                self.call_stack.push(self.instruction_ptr);
+                self.instruction_ptr = target;
+                Ok(())
+            }
+            Instruction::CallTo(target) => {
+                self.push(self.instruction_ptr as CeInt32)?;
+                self.call_stack.push(self.instruction_ptr);
+                self.instruction_ptr = target.resolve(ip);
+                Ok(())
+            }
+            Instruction::Ret => {
+                self.instruction_ptr = self.pop::<CeInt32>()? as CeWord;
+                self.call_stack.pop();
+                Ok(())
+            }
+            Instruction::Gc => {
+                self.collect_garbage();
+                Ok(())
+            }
+            Instruction::Push(ty, src) => with_type!(ty, push_instr(src)),
+            Instruction::Pop(ty, dst) => with_type!(ty, pop_instr(dst)),
+            Instruction::Syscall(number) => {
+                let function = self.host_functions.by_number(number).ok_or(Trap::UnknownSyscall(number))?;
+                function(&mut HostContext { registers: &mut self.registers, memory: &mut self.memory })
+            }
+            Instruction::CallHost(index) => {
+                let function = self.imports.get(index as usize)
+                    .and_then(|name| self.host_functions.by_name(name))
+                    .ok_or(Trap::UnresolvedImport(index))?;
+                function(&mut HostContext { registers: &mut self.registers, memory: &mut self.memory })
             }
         }
     }
 
+    /// Allocates `size` bytes on the heap. When garbage is collected automatically, a collection
+    /// runs first if enough has been allocated since the last one, and again before giving up on
+    /// an allocation that does not fit.
+    fn allocate(&mut self, size: CeWord) -> Result<Pointer, Trap> {
+        if self.memory.should_collect(size) {
+            self.collect_garbage();
+        }
+        match self.memory.allocate(size) {
+            Err(Trap::OutOfMemory { .. }) if self.memory.collects_garbage() => {
+                self.collect_garbage();
+                self.memory.allocate(size)
+            }
+            result => result,
+        }
+    }
+
+    /// Frees the heap blocks that cannot be reached from the registers or the stack, as
+    /// described in [`RAM::collect_garbage`]
+    pub fn collect_garbage(&mut self) -> Collection {
+        let roots: Vec<[u8; 8]> = self.registers.iter().map(Register::to_bytes).collect();
+        let stack_top = self.register::<CeWord>(instruction_parts::Register::SP);
+        self.memory.collect_garbage(&roots, stack_top)
+    }
+
+    /// Compares the value at `src` against `rhs`
     #[inline(always)]
-    fn test_condition<T: EndianConversion + PartialOrd + From<i8>>(&mut self, b2: u8) -> bool {
-        let src: T = self.get_location::<T>(b2 >> 4).get();
-        match b2 & 0b1110 {
-            0b0000 => false,
-            0b0010 => src > T::from(0),
-            0b0100 => src == T::from(0),
-            0b0110 => src >= T::from(0),
-            0b1000 => src < T::from(0),
-            0b1010 => src != T::from(0),
-            0b1100 => src <= T::from(0),
-            0b1110 => true,
-            _ => unsafe { unreachable_unchecked() },
+    fn test_condition<T: Arithmetic>(&mut self, src: Location, cnd: Condition, rhs: T) -> Result<bool, Trap> {
+        if cnd == Condition::ALWAYS {
+            return Ok(true);
         }
+
+        let src: T = self.read(src)?;
+        Ok(match cnd {
+            Condition::GT => src > rhs,
+            Condition::EQ => src == rhs,
+            Condition::GE => src >= rhs,
+            Condition::LT => src < rhs,
+            Condition::NE => src != rhs,
+            Condition::LE => src <= rhs,
+            Condition::ALWAYS => true,
+        })
     }
 
     #[inline(always)]
-    fn do_binop<T: EndianConversion>(&mut self, b2: u8, b3: u8, op: fn(T, T) -> T) {
-        let val1 = self.get_location::<T>(b2 >> 4).get();
-        let val2 = self.get_location::<T>(b2).get();
-        let res = op(val1, val2);
-        unsafe { self.get_location::<T>(b3 >> 4).write(res) }
+    fn do_binop<T: Arithmetic>(
+        &mut self,
+        op: BinOp,
+        src1: Location,
+        src2: Location,
+        dst: Location,
+    ) -> Result<(), Trap> {
+        let val1 = self.read::<T>(src1)?;
+        let val2 = self.read::<T>(src2)?;
+        let res = T::binop(op, val1, val2)?;
+        self.write(dst, res)
     }
 
     #[inline(always)]
-    fn do_unop<T: EndianConversion>(&mut self, b2: u8, op: fn(T) -> T) {
-        let src = self.get_location::<T>(b2 >> 4).get();
-        let mut dst = self.get_location::<T>(b2);
-        unsafe { dst.write(op(src)) }
+    fn do_binop_imm<T: Arithmetic>(
+        &mut self,
+        op: BinOp,
+        src: Location,
+        imm: Immediate,
+        dst: Location,
+    ) -> Result<(), Trap> {
+        let val = self.read::<T>(src)?;
+        let res = T::binop(op, val, T::from_immediate(imm))?;
+        self.write(dst, res)
     }
 
     #[inline(always)]
-    fn jmp_instr<T: EndianConversion + PartialOrd + From<i8>>(&mut self, b2: u8, b3: u8) {
-        if self.test_condition::<T>(b2) {
-            self.instruction_ptr = self.get_word_for_location(b3 >> 4);
+    fn do_unop<T: Arithmetic>(&mut self, op: UnOp, src: Location, dst: Location) -> Result<(), Trap> {
+        let val = self.read::<T>(src)?;
+        let res = T::unop(op, val)?;
+        self.write(dst, res)
+    }
+
+    #[inline(always)]
+    fn jmp_instr<T: Arithmetic>(&mut self, src: Location, tgt: Location, cnd: Condition) -> Result<(), Trap> {
+        if self.test_condition::<T>(src, cnd, T::ZERO)? {
+            self.instruction_ptr = self.read_word(tgt)?;
         }
+        Ok(())
     }
 
     #[inline(always)]
-    fn cmp_instr<T: EndianConversion + PartialOrd + From<i8>>(&mut self, b2: u8, b3: u8) {
-        let result = self.test_condition::<T>(b2) as i8;
-        unsafe { self.get_location::<CeInt8>(b3 >> 4).write(result); }
+    fn jmp_to_instr<T: Arithmetic>(
+        &mut self,
+        src: Location,
+        target: CeWord,
+        cnd: Condition,
+        imm: Option<Immediate>,
+    ) -> Result<(), Trap> {
+        let rhs = imm.map_or(T::ZERO, T::from_immediate);
+        if self.test_condition::<T>(src, cnd, rhs)? {
+            self.instruction_ptr = target;
+        }
+        Ok(())
     }
 
     #[inline(always)]
-    fn lod_instr<T: EndianConversion>(&mut self, loc: u8, dat: T) {
-        unsafe { self.get_location::<T>(loc).write(dat) }
+    fn cmp_instr<T: Arithmetic>(&mut self, src: Location, dst: Location, cnd: Condition) -> Result<(), Trap> {
+        let result = self.test_condition::<T>(src, cnd, T::ZERO)? as CeInt8;
+        self.write(dst, result)
     }
 
-    pub fn is_done(&self) -> bool { self.done }
-}
+    #[inline(always)]
+    fn cmp_imm_instr<T: Arithmetic>(
+        &mut self,
+        src: Location,
+        imm: Immediate,
+        dst: Location,
+        cnd: Condition,
+    ) -> Result<(), Trap> {
+        let result = self.test_condition::<T>(src, cnd, T::from_immediate(imm))? as CeInt8;
+        self.write(dst, result)
+    }
 
-fn modulo<T: Add<Output = T> + Rem<Output = T> + Copy>(x: T, m: T) -> T {
-    (x % m + m) % m
+    /// Writes a value to the top of the stack and moves the stack pointer past it
+    fn push<T: EndianConversion>(&mut self, value: T) -> Result<(), Trap> {
+        let sp = self.register::<CeWord>(instruction_parts::Register::SP);
+        let limit = self.memory.stack_limit();
+        let end = sp.wrapping_add(size_of::<T>() as CeWord);
+        if sp >= limit || end > limit {
+            return Err(Trap::StackOverflow { address: end, limit });
+        }
+
+        unsafe {
+            self.memory.at_mut::<T>(sp.into())?.write(value);
+        }
+        self.write(Self::SP, end as CeInt32)
+    }
+
+    /// Moves the stack pointer back over the value at the top of the stack and returns it
+    fn pop<T: EndianConversion>(&mut self) -> Result<T, Trap> {
+        let sp = self.register::<CeWord>(instruction_parts::Register::SP);
+        let limit = self.memory.stack_limit();
+        if sp > limit {
+            return Err(Trap::StackOverflow { address: sp, limit });
+        }
+        let start = sp.checked_sub(size_of::<T>() as CeWord).ok_or(Trap::StackUnderflow)?;
+
+        let value = self.memory.at::<T>(start.into())?.get();
+        self.write(Self::SP, start as CeInt32)?;
+        Ok(value)
+    }
+
+    #[inline(always)]
+    fn push_instr<T: Arithmetic>(&mut self, src: Location) -> Result<(), Trap> {
+        let value = self.read::<T>(src)?;
+        self.push(value)
+    }
+
+    #[inline(always)]
+    fn pop_instr<T: Arithmetic>(&mut self, dst: Location) -> Result<(), Trap> {
+        let value = self.pop::<T>()?;
+        self.write(dst, value)
+    }
+
+    pub fn is_done(&self) -> bool { self.done }
 }
@@ -0,0 +1,18 @@
+#![allow(clippy::upper_case_acronyms, clippy::module_inception)]
+
+pub mod cerium;
+mod util;
+
+pub use crate::cerium::assembler::CasmAssembler;
+pub use crate::cerium::compiler::CeriumCompiler;
+pub use crate::cerium::debugger::Debugger;
+pub use crate::cerium::disassembler::CasmDisassembler;
+pub use crate::cerium::linker::Linker;
+pub use crate::cerium::object::ObjectFile;
+pub use crate::cerium::profiler::Profiler;
+pub use crate::cerium::program::Program;
+pub use crate::cerium::symbol_map::SymbolMap;
+pub use crate::cerium::vm::{
+    BufferIo, CeriumVM, ExecutionObserver, RunOutcome, ScriptedIo, SnapshotError, StdIo, TraceFormat, Tracer, VmConfig,
+    VmError, VmIo,
+};
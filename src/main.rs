@@ -1,88 +1,565 @@
-pub use crate::cerium::assembler::CasmAssembler;
-pub use crate::cerium::vm::CeriumVM;
+use cerium::cerium::assembler::Diagnostic;
+use cerium::{
+    CasmAssembler, CasmDisassembler, CeriumCompiler, CeriumVM, Debugger, Linker, ObjectFile, Profiler, Program, RunOutcome,
+    StdIo, SymbolMap, TraceFormat, Tracer, VmConfig,
+};
+use cerium::cerium::program::Symbol;
+use cerium::cerium::vm::HostFunctions;
+use std::cell::RefCell;
 use std::env::args;
 use std::fs::File;
-use std::io::{Read, Write};
-use std::path::Path;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-mod util;
-mod cerium;
+/// Options for the commands that run a program
+struct RunOptions {
+    config: VmConfig,
+    max_steps: Option<u64>,
+    timeout: Option<Duration>,
+    /// Where to save the state of a program that stops without halting
+    snapshot_path: Option<String>,
+    trace_path: Option<String>,
+    trace_format: TraceFormat,
+    /// Whether to report the heap blocks a program did not free
+    leak_check: bool,
+    /// The directory that the program may read files from, if any
+    fs_root: Option<PathBuf>,
+    /// Whether `--max-heap` or `--max-stack` was given, which `resume` cannot honor because
+    /// snapshots keep their memory limits
+    sets_memory_limits: bool,
+    /// The symbol map that `assemble` writes, or that the commands that run a program read to
+    /// show code addresses relative to symbols
+    map_path: Option<String>,
+    /// The listing that `assemble` writes
+    listing_path: Option<String>,
+    /// Where `profile` writes folded stacks
+    folded_path: Option<String>,
+}
 
 fn main() {
-    let mut args = args().skip(1);
+    let (options, args) = parse_options(args().skip(1).collect());
+    let command = args.first().map(String::as_str);
+    if options.listing_path.is_some() && command != Some("assemble") {
+        usage_error("--listing can only be used with assemble");
+    }
+    if options.folded_path.is_some() && command != Some("profile") {
+        usage_error("--folded can only be used with profile");
+    }
+    let mut args = args.into_iter();
     match args.next() {
         None => help(),
         Some(first_arg) => {
             match first_arg.as_str() {
-                "assemble" => assemble(
-                    args.next().expect("No input file provided").as_str(),
-                    args.next().expect("No output file provided").as_str(),
-                ),
-                "run-asm" => assemble_and_execute(
-                    args.next().expect("No input file provided").as_str()
-                ),
-                _ => execute_ce_binary(first_arg.as_str()),
+                "assemble" => {
+                    let input_path = required_arg(&mut args, "No input file provided");
+                    let output_path = required_arg(&mut args, "No output file provided");
+                    no_more_args(args);
+                    assemble(&input_path, &output_path, options.listing_path.as_deref(), options.map_path.as_deref())
+                }
+                "link" => {
+                    let mut input_paths = vec![];
+                    let mut output_path = None;
+                    while let Some(arg) = args.next() {
+                        match arg.as_str() {
+                            "-o" => output_path = Some(args.next().unwrap_or_else(|| usage_error("Missing value after -o"))),
+                            _ => input_paths.push(arg),
+                        }
+                    }
+                    if input_paths.is_empty() {
+                        usage_error("No input files provided");
+                    }
+                    let output_path = output_path.unwrap_or_else(|| usage_error("No output file provided (use -o <output-file>)"));
+                    link(&input_paths, &output_path)
+                }
+                "compile" => {
+                    let input_path = required_arg(&mut args, "No input file provided");
+                    let output_path = required_arg(&mut args, "No output file provided");
+                    no_more_args(args);
+                    compile(&input_path, &output_path)
+                }
+                "run-src" => {
+                    let input_path = required_arg(&mut args, "No input file provided");
+                    no_more_args(args);
+                    compile_and_execute(&input_path, &options)
+                }
+                "run-asm" => {
+                    let input_path = required_arg(&mut args, "No input file provided");
+                    no_more_args(args);
+                    assemble_and_execute(&input_path, &options)
+                }
+                "disassemble" => {
+                    let input_path = required_arg(&mut args, "No input file provided");
+                    no_more_args(args);
+                    disassemble(&input_path)
+                }
+                "resume" => {
+                    let snapshot_path = required_arg(&mut args, "No snapshot file provided");
+                    no_more_args(args);
+                    resume(&snapshot_path, &options)
+                }
+                "profile" => {
+                    let input_path = required_arg(&mut args, "No input file provided");
+                    no_more_args(args);
+                    profile(&input_path, &options)
+                }
+                "debug" => {
+                    let input_path = required_arg(&mut args, "No input file provided");
+                    no_more_args(args);
+                    debug(&input_path, &options)
+                }
+                _ => {
+                    no_more_args(args);
+                    execute_ce_binary(first_arg.as_str(), &options)
+                }
             }
         }
     };
 }
 
-fn assemble(input_path: &str, output_path: &str) {
-    let mut input_file = File::open(Path::new(input_path)).expect(
-        &format!("File not found: {}", input_path)
-    );
-    let mut input_file_str: String = String::default();
-    input_file.read_to_string(&mut input_file_str).expect("Unable to read input file");
+/// Removes the options for running programs from the command line arguments
+fn parse_options(args: Vec<String>) -> (RunOptions, Vec<String>) {
+    let mut options = RunOptions {
+        config: VmConfig::new(),
+        max_steps: None,
+        timeout: None,
+        snapshot_path: None,
+        trace_path: None,
+        trace_format: TraceFormat::Text,
+        leak_check: false,
+        fs_root: None,
+        sets_memory_limits: false,
+        map_path: None,
+        listing_path: None,
+        folded_path: None,
+    };
+    let mut rest = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sanitize" => {
+                options.config = options.config.sanitize(true);
+                continue;
+            }
+            "--gc" => {
+                options.config = options.config.garbage_collect(true);
+                continue;
+            }
+            "--leak-check" => {
+                options.leak_check = true;
+                continue;
+            }
+            _ => {}
+        }
+        if !matches!(
+            arg.as_str(),
+            "--max-heap" | "--max-stack" | "--max-steps" | "--timeout" | "--save-snapshot" | "--trace" | "--trace-format"
+                | "--map" | "--allow-fs" | "--listing" | "--folded"
+        ) {
+            rest.push(arg);
+            continue;
+        }
+        let value = args.next().unwrap_or_else(|| usage_error(&format!("Missing value after {}", arg)));
+        let invalid = || -> ! { usage_error(&format!("Invalid value for {}: {}", arg, value)) };
+        match arg.as_str() {
+            "--max-heap" => {
+                options.config = options.config.max_heap(parse_size(&value).unwrap_or_else(|| invalid()));
+                options.sets_memory_limits = true;
+            }
+            "--max-stack" => {
+                options.config = options.config.max_stack(parse_size(&value).unwrap_or_else(|| invalid()));
+                options.sets_memory_limits = true;
+            }
+            "--max-steps" => options.max_steps = Some(value.parse().unwrap_or_else(|_| invalid())),
+            "--timeout" => options.timeout = Some(Duration::from_secs(value.parse().unwrap_or_else(|_| invalid()))),
+            "--trace-format" => options.trace_format = match value.as_str() {
+                "text" => TraceFormat::Text,
+                "jsonl" => TraceFormat::JsonLines,
+                _ => invalid(),
+            },
+            "--trace" => options.trace_path = Some(value),
+            "--map" => options.map_path = Some(value),
+            "--listing" => options.listing_path = Some(value),
+            "--folded" => options.folded_path = Some(value),
+            "--allow-fs" => options.fs_root = Some(PathBuf::from(value)),
+            _ => options.snapshot_path = Some(value),
+        }
+    }
+    (options, rest)
+}
 
-    let result_bytes = CasmAssembler::assemble(input_file_str.as_str());
+/// Reports a mistake in the command line arguments and exits
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    eprintln!("Run cerium without arguments to see its usage");
+    exit(2);
+}
 
-    let mut output_file = File::create(Path::new(output_path)).expect(
-        &format!("File not found: {}", output_path)
-    );
+/// Takes the next command line argument, reporting `message` if there is none
+fn required_arg(args: &mut impl Iterator<Item = String>, message: &str) -> String {
+    args.next().unwrap_or_else(|| usage_error(message))
+}
 
-    output_file.write(&*result_bytes).expect("Unable to write to output file");
+/// Reports the first of any command line arguments that are left over
+fn no_more_args(mut args: impl Iterator<Item = String>) {
+    if let Some(arg) = args.next() {
+        usage_error(&format!("Unexpected argument: {}", arg));
+    }
 }
 
-fn assemble_and_execute(input_path: &str) {
-    let mut input_file = File::open(Path::new(input_path)).expect(
-        &format!("File not found: {}", input_path)
-    );
-    let mut input_file_str: String = String::default();
-    input_file.read_to_string(&mut input_file_str).expect("Unable to read input file");
+/// Reports a file that could not be read or written and exits
+fn file_error(action: &str, path: &str, err: std::io::Error) -> ! {
+    eprintln!("Unable to {} {}: {}", action, path, err);
+    exit(1);
+}
 
-    let result_bytes = CasmAssembler::assemble(input_file_str.as_str());
-    
-    let mut vm = CeriumVM::new();
-    vm.load_program(result_bytes);
+/// Parses a size in bytes, optionally followed by a `K`, `M` or `G` binary suffix
+fn parse_size(size: &str) -> Option<u32> {
+    let (digits, shift) = match size.char_indices().last()? {
+        (index, 'k' | 'K') => (&size[..index], 10),
+        (index, 'm' | 'M') => (&size[..index], 20),
+        (index, 'g' | 'G') => (&size[..index], 30),
+        _ => (size, 0),
+    };
+    digits.parse::<u32>().ok()?.checked_mul(1 << shift)
+}
+
+fn assemble(input_path: &str, output_path: &str, listing_path: Option<&str>, map_path: Option<&str>) {
+    let source = read_source_file(input_path);
+    // An .o output is an object file to link with others, rather than a whole program
+    let (bytes, listing, symbols) = if output_path.ends_with(".o") {
+        let (object, listing) = CasmAssembler::assemble_object_with_listing(input_path, source.as_str())
+            .unwrap_or_else(|diagnostics| report_diagnostics("assemble", input_path, diagnostics));
+        let symbols = object.symbols.iter()
+            .map(|(name, address)| Symbol { name: name.clone(), address: *address })
+            .collect();
+        (object.to_bytes(), listing, symbols)
+    } else {
+        let (program, listing) = CasmAssembler::assemble_with_listing(input_path, source.as_str())
+            .unwrap_or_else(|diagnostics| report_diagnostics("assemble", input_path, diagnostics));
+        (program.to_bytes(), listing, program.symbols)
+    };
 
-    while !vm.is_done() {
-        vm.execute_next_instruction();
+    write_output_file(output_path, &bytes);
+    if let Some(path) = listing_path {
+        write_output_file(path, listing.to_string().as_bytes());
+    }
+    if let Some(path) = map_path {
+        let map = SymbolMap::new(symbols);
+        write_output_file(path, format!("// Symbol map of {}\n{}", input_path, map).as_bytes());
     }
-    println!("Done");
 }
 
-fn execute_ce_binary(path: &str) {
-    let path = path;
-    let mut file = File::open(Path::new(path)).expect(
-        &format!("File not found: {}", path)
-    );
-    let mut buffer: Vec<u8> = Vec::new();
-    file.read_to_end(&mut buffer).expect(
-        "Failed to read file into buffer"
+fn write_output_file(path: &str, bytes: &[u8]) {
+    std::fs::write(path, bytes).unwrap_or_else(|err| file_error("write", path, err));
+}
+
+fn read_symbol_map(path: &str) -> SymbolMap {
+    SymbolMap::parse(&read_source_file(path)).unwrap_or_else(|err| {
+        eprintln!("Invalid symbol map {}: {}", path, err);
+        exit(1);
+    })
+}
+
+fn link(input_paths: &[String], output_path: &str) {
+    let objects: Vec<(String, ObjectFile)> = input_paths.iter()
+        .map(|path| {
+            let object = ObjectFile::from_bytes(&read_binary_file(path)).unwrap_or_else(|err| {
+                eprintln!("Invalid object file {}: {}", path, err);
+                exit(1);
+            });
+            (path.clone(), object)
+        })
+        .collect();
+
+    let program = Linker::link(&objects).unwrap_or_else(|errors| {
+        for error in &errors {
+            eprintln!("error: {}", error);
+        }
+        eprintln!(
+            "Could not link {} due to {} error{}",
+            output_path,
+            errors.len(),
+            if errors.len() == 1 { "" } else { "s" }
+        );
+        exit(1);
+    });
+
+    write_output_file(output_path, &program.to_bytes());
+}
+
+fn read_source_file(input_path: &str) -> String {
+    std::fs::read_to_string(input_path).unwrap_or_else(|err| file_error("read", input_path, err))
+}
+
+fn report_diagnostics(action: &str, input_path: &str, diagnostics: Vec<Diagnostic>) -> ! {
+    for diagnostic in &diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+    eprintln!(
+        "Could not {} {} due to {} error{}",
+        action,
+        input_path,
+        diagnostics.len(),
+        if diagnostics.len() == 1 { "" } else { "s" }
     );
+    exit(1);
+}
+
+fn assemble_file(input_path: &str) -> Program {
+    let source = read_source_file(input_path);
+    CasmAssembler::assemble(input_path, source.as_str())
+        .unwrap_or_else(|diagnostics| report_diagnostics("assemble", input_path, diagnostics))
+}
+
+fn compile_file(input_path: &str) -> Program {
+    let source = read_source_file(input_path);
+    CeriumCompiler::compile(input_path, source.as_str())
+        .unwrap_or_else(|diagnostics| report_diagnostics("compile", input_path, diagnostics))
+}
+
+fn compile(input_path: &str, output_path: &str) {
+    let program = compile_file(input_path);
+
+    write_output_file(output_path, &program.to_bytes());
+}
+
+fn compile_and_execute(input_path: &str, options: &RunOptions) {
+    let program = compile_file(input_path);
+
+    let mut vm = CeriumVM::with_config(StdIo, options.config);
+    register_host_functions(vm.host_functions_mut(), options);
+    vm.load_program(&program);
+
+    run(&mut vm, options);
+}
+
+fn assemble_and_execute(input_path: &str, options: &RunOptions) {
+    let program = assemble_file(input_path);
+
+    let mut vm = CeriumVM::with_config(StdIo, options.config);
+    register_host_functions(vm.host_functions_mut(), options);
+    vm.load_program(&program);
 
-    let mut vm = CeriumVM::new();
-    vm.load_program(buffer.as_slice());
+    run(&mut vm, options);
+}
+
+fn read_binary_file(path: &str) -> Vec<u8> {
+    std::fs::read(path).unwrap_or_else(|err| file_error("read", path, err))
+}
+
+fn read_ce_file(path: &str) -> Program {
+    let buffer = read_binary_file(path);
+
+    Program::from_bytes(&buffer).unwrap_or_else(|err| {
+        eprintln!("Invalid .ce file {}: {}", path, err);
+        exit(1);
+    })
+}
+
+fn disassemble(path: &str) {
+    let program = read_ce_file(path);
+    print!("{}", CasmDisassembler::disassemble(&program));
+}
+
+/// Reads a program from a .ce file, or builds it from a .casm or .cer file
+fn load_any_program(path: &str) -> Program {
+    if path.ends_with(".casm") {
+        assemble_file(path)
+    } else if path.ends_with(".cer") {
+        compile_file(path)
+    } else {
+        read_ce_file(path)
+    }
+}
+
+fn debug(path: &str, options: &RunOptions) {
+    let program = load_any_program(path);
+
+    let mut debugger = Debugger::with_config(program, options.config);
+    register_host_functions(debugger.host_functions_mut(), options);
+    if let Some(path) = &options.map_path {
+        debugger = debugger.with_symbols(read_symbol_map(path));
+    }
+    debugger.run();
+}
+
+fn profile(path: &str, options: &RunOptions) {
+    if options.trace_path.is_some() {
+        eprintln!("Cannot trace a program while profiling it");
+        exit(1);
+    }
+    let program = load_any_program(path);
+
+    let mut vm = CeriumVM::with_config(StdIo, options.config);
+    register_host_functions(vm.host_functions_mut(), options);
+    vm.load_program(&program);
+
+    let profiler = Rc::new(RefCell::new(Profiler::new()));
+    vm.set_observer(Some(Box::new(profiler.clone())));
+    let halted = run_until_stopped(&mut vm, options);
+
+    let profiler = profiler.borrow();
+    print!("\n{}", profiler.report(&program));
+    if let Some(folded_path) = &options.folded_path {
+        write_output_file(folded_path, profiler.folded_stacks(&program).as_bytes());
+    }
+
+    if !halted {
+        exit(1);
+    }
+}
+
+fn execute_ce_binary(path: &str, options: &RunOptions) {
+    let program = read_ce_file(path);
+
+    let mut vm = CeriumVM::with_config(StdIo, options.config);
+    register_host_functions(vm.host_functions_mut(), options);
+    vm.load_program(&program);
+
+    run(&mut vm, options);
+}
+
+fn resume(snapshot_path: &str, options: &RunOptions) {
+    if options.sets_memory_limits {
+        eprintln!("--max-heap and --max-stack cannot be used with resume: a snapshot keeps the memory limits it was saved with");
+        exit(1);
+    }
+    let snapshot = read_binary_file(snapshot_path);
+
+    let mut vm = CeriumVM::with_config(StdIo, options.config);
+    register_host_functions(vm.host_functions_mut(), options);
+    vm.restore(&snapshot).unwrap_or_else(|err| {
+        eprintln!("Invalid snapshot {}: {}", snapshot_path, err);
+        exit(1);
+    });
+
+    run(&mut vm, options);
+}
+
+/// Gives the program the standard services, and the file system if the options allow it
+fn register_host_functions(host_functions: &mut HostFunctions, options: &RunOptions) {
+    host_functions.register_standard_services();
+    if let Some(root) = &options.fs_root {
+        host_functions.register_file_services(root);
+    }
+}
+
+fn run(vm: &mut CeriumVM, options: &RunOptions) {
+    if !run_until_stopped(vm, options) {
+        exit(1);
+    }
+}
+
+/// Runs a program within the limits set by the options and reports how it stopped, returning
+/// whether it halted
+fn run_until_stopped(vm: &mut CeriumVM, options: &RunOptions) -> bool {
+    let symbols = options.map_path.as_deref().map(read_symbol_map);
+    if let Some(path) = &options.trace_path {
+        let file = File::create(Path::new(path)).unwrap_or_else(|err| {
+            eprintln!("Unable to create trace file {}: {}", path, err);
+            exit(1);
+        });
+        let mut tracer = Tracer::new(BufWriter::new(file), options.trace_format);
+        if let Some(symbols) = &symbols {
+            tracer = tracer.with_symbols(symbols.clone());
+        }
+        vm.set_observer(Some(Box::new(tracer)));
+    }
+    vm.set_deadline(options.timeout.map(|timeout| Instant::now() + timeout));
+    let outcome = vm.run(options.max_steps.unwrap_or(u64::MAX));
+    // Flush the trace before the caller exits, which skips destructors
+    drop(vm.take_observer());
+    if outcome != RunOutcome::Halted {
+        if let Some(path) = &options.snapshot_path {
+            File::create(Path::new(path))
+                .and_then(|mut file| file.write_all(&vm.snapshot()))
+                .unwrap_or_else(|err| eprintln!("Unable to save snapshot to {}: {}", path, err));
+        }
+    }
+
+    match outcome {
+        RunOutcome::Halted => {
+            println!("Done");
+            if options.leak_check {
+                return check_leaks(vm, symbols.as_ref());
+            }
+        }
+        RunOutcome::OutOfFuel => {
+            eprintln!("Program did not halt within {} instructions", options.max_steps.unwrap_or(u64::MAX));
+        }
+        RunOutcome::TimedOut => {
+            let seconds = options.timeout.unwrap_or_default().as_secs();
+            eprintln!("Program did not halt within {} second{}", seconds, if seconds == 1 { "" } else { "s" });
+        }
+        RunOutcome::Trapped(err) => match &symbols {
+            Some(symbols) => eprintln!("{}", err.to_string_with_symbols(symbols)),
+            None => eprintln!("{}", err),
+        },
+    }
+    outcome == RunOutcome::Halted
+}
+
+/// Reports the heap blocks that are still allocated, returning whether there were none
+fn check_leaks(vm: &CeriumVM, symbols: Option<&SymbolMap>) -> bool {
+    let leaks = vm.live_allocations();
+    for leak in &leaks {
+        let site = match symbols {
+            Some(symbols) => symbols.describe(leak.allocated_at),
+            None => format!("0x{:08x}", leak.allocated_at),
+        };
+        eprintln!(
+            "Leaked {} byte{} at 0x{:08x}, allocated at {}",
+            leak.size, if leak.size == 1 { "" } else { "s" }, leak.address, site
+        );
+    }
 
-    while !vm.is_done() {
-        vm.execute_next_instruction();
+    let stats = vm.heap_stats();
+    if !leaks.is_empty() {
+        eprintln!(
+            "{} block{} leaked with {} bytes in total (peak heap usage was {} bytes)",
+            leaks.len(), if leaks.len() == 1 { "" } else { "s" }, stats.live_bytes, stats.peak_bytes
+        );
     }
-    println!("Done");
+    leaks.is_empty()
 }
 
 fn help() {
     println!("CeriumVM Usage:");
-    println!("  cerium assemble <input-file> <output-file> | Assembles a .casm file to a .ce file");
+    println!("  cerium assemble <input-file> <output-file> | Assembles a .casm file to a .ce file, or to an object");
+    println!("                                             | file if <output-file> ends in .o");
+    println!("  cerium link <objects> -o <output-file>     | Links .o object files into a .ce file");
     println!("  cerium run-asm <input-file>                | Assembles and runs a .casm file");
+    println!("  cerium compile <input-file> <output-file>  | Compiles a .cer file to a .ce file");
+    println!("  cerium run-src <input-file>                | Compiles and runs a .cer file");
+    println!("  cerium disassemble <input-file>            | Prints the CASM source of a .ce file");
+    println!("  cerium debug <input-file>                  | Debugs a .ce, .casm or .cer file interactively");
+    println!("  cerium profile <input-file>                | Runs a .ce, .casm or .cer file and reports hot spots");
+    println!("  cerium resume <snapshot-file>              | Continues a program from a snapshot");
+    println!("                                             | with the memory limits it was saved with");
     println!("  cerium <input-file>                        | Runs a .ce file");
+    println!();
+    println!("Options for assemble:");
+    println!("  --listing <file>       | Writes each source line with its address and encoded bytes to <file>");
+    println!("  --map <file>           | Writes the address of every label to the symbol map <file>");
+    println!();
+    println!("Options for profile:");
+    println!("  --folded <file>        | Writes folded stacks for flamegraphs to <file>");
+    println!();
+    println!("Options for commands that run a program:");
+    println!("  --max-heap <size>      | Limits the heap to <size> bytes, e.g. 4096, 64K or 16M");
+    println!("  --max-stack <size>     | Limits the stack to <size> bytes");
+    println!("  --max-steps <count>    | Stops the program after <count> instructions");
+    println!("  --timeout <seconds>    | Stops the program after <seconds> seconds");
+    println!("  --save-snapshot <file> | Saves the program's state to <file> if it stops without halting");
+    println!("  --sanitize             | Traps on invalid heap accesses, like use after free");
+    println!("  --gc                   | Frees unreachable heap blocks when the heap fills up");
+    println!("  --leak-check           | Lists the heap blocks that are not freed when the program halts");
+    println!("  --trace <file>         | Logs every executed instruction to <file>");
+    println!("  --trace-format <fmt>   | Writes the trace as `text` (the default) or `jsonl`");
+    println!("  --allow-fs <dir>       | Lets the program read the files in <dir> with the read_file service");
+    println!("  --map <file>           | Reads the symbol map <file>, as written by assemble --map, to show");
+    println!("                         | code addresses in errors and traces like FIB_RECURSE+0x1c");
 }
@@ -0,0 +1,42 @@
+mod common;
+
+use common::{assemble, run};
+
+#[test]
+fn macro_expansions_get_their_own_local_labels() {
+    let program = assemble("
+    .macro COUNT_DOWN from
+        lod r1 <- i \\from
+    LOOP:
+        output <- r1
+        sub i r1 <- r1 - 1
+        jmp LOOP+0 if i r1 != 0
+    .endm
+        COUNT_DOWN 2
+    LOOP:
+        COUNT_DOWN 3
+        halt
+    ");
+    let labels: Vec<_> = program.symbols.iter().map(|symbol| symbol.name.as_str()).collect();
+    assert_eq!(labels, ["LOOP__1", "LOOP", "LOOP__2"]);
+    assert_eq!(run(&program, &[]), [2, 1, 3, 2, 1]);
+}
+
+#[test]
+fn constants_and_label_arithmetic() {
+    let program = assemble("
+    .equ COUNT 2 + 3 * 2
+    .equ TWICE COUNT << 1
+    START:
+        lod r1 <- i COUNT
+        output <- r1
+        lod r1 <- i TWICE - (COUNT + 1) * 2
+        output <- r1
+        lod r1 <- i END - START
+        output <- r1
+    END:
+        halt
+    ");
+    let end = program.symbols.iter().find(|symbol| symbol.name == "END").unwrap();
+    assert_eq!(run(&program, &[]), [8, -2, end.address as i32]);
+}
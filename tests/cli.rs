@@ -0,0 +1,39 @@
+use std::process::{Command, Output};
+
+fn cerium(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_cerium")).args(args).output().unwrap()
+}
+
+#[test]
+fn usage_mistakes_are_reported_without_panicking() {
+    let mistakes: [&[&str]; 12] = [
+        &["assemble", "in.casm"],
+        &["link", "a.o"],
+        &["compile", "in.cer"],
+        &["run-src"],
+        &["run-asm"],
+        &["disassemble"],
+        &["resume"],
+        &["debug"],
+        &["profile"],
+        &["profile", "in.ce", "extra"],
+        &["run-asm", "in.casm", "--folded", "stacks.txt"],
+        &["in.ce", "--max-heap", "lots"],
+    ];
+    for args in mistakes {
+        let output = cerium(args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(output.status.code(), Some(2), "{:?}: {}", args, stderr);
+        assert!(stderr.contains("Run cerium without arguments to see its usage"), "{:?}: {}", args, stderr);
+    }
+}
+
+#[test]
+fn missing_files_are_reported_without_panicking() {
+    for args in [&["run-asm", "missing.casm"][..], &["disassemble", "missing.ce"], &["missing.ce"]] {
+        let output = cerium(args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(output.status.code(), Some(1), "{:?}: {}", args, stderr);
+        assert!(stderr.starts_with("Unable to read missing."), "{:?}: {}", args, stderr);
+    }
+}
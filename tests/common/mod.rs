@@ -0,0 +1,46 @@
+//! Helpers shared by the integration tests. Each test crate uses only some of them.
+#![allow(dead_code)]
+
+use cerium::{CasmAssembler, CeriumCompiler, CeriumVM, Program, RunOutcome, ScriptedIo};
+use std::path::{Path, PathBuf};
+
+pub const EXAMPLES: [&str; 2] = ["examples/collatz/collatz.casm", "examples/fibonacci/fibonacci.casm"];
+
+/// Resolves a path relative to the root of the crate
+pub fn crate_path(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+pub fn assemble_example(path: &str) -> Program {
+    let path = crate_path(path);
+    let source = std::fs::read_to_string(&path).unwrap();
+    CasmAssembler::assemble(path.to_str().unwrap(), &source)
+        .unwrap_or_else(|diagnostics| panic!("{} does not assemble: {:?}", path.display(), diagnostics))
+}
+
+pub fn assemble(source: &str) -> Program {
+    CasmAssembler::assemble("test.casm", source)
+        .unwrap_or_else(|diagnostics| panic!("does not assemble: {:?}", diagnostics))
+}
+
+pub fn compile(source: &str) -> Program {
+    CeriumCompiler::compile("test.cer", source)
+        .unwrap_or_else(|diagnostics| panic!("does not compile: {:?}", diagnostics))
+}
+
+/// Loads `program` into a VM that reads `inputs`
+pub fn load(program: &Program, inputs: &[i32]) -> CeriumVM<ScriptedIo> {
+    let mut vm = CeriumVM::new(ScriptedIo::new(inputs.iter().copied()));
+    vm.load_program(program);
+    vm
+}
+
+/// Runs `program` until it halts, returning its output
+pub fn run(program: &Program, inputs: &[i32]) -> Vec<i32> {
+    let mut vm = load(program, inputs);
+    match vm.run(10_000_000) {
+        RunOutcome::Halted => vm.io().outputs().to_vec(),
+        RunOutcome::Trapped(err) => panic!("trapped: {}", err),
+        _ => panic!("did not halt"),
+    }
+}
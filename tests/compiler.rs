@@ -0,0 +1,62 @@
+mod common;
+
+use cerium::CeriumCompiler;
+use common::{compile, run};
+
+#[test]
+fn comparisons_do_not_overflow() {
+    let source = "
+        int main() {
+            int m = -2147483647;
+            int k = 5;
+            output(m < k);
+            output(k > m);
+            output(m >= k);
+            int a = 2147483647;
+            int b = -10;
+            output(a > b);
+            output(a <= b);
+            output(a == a);
+            float x = 1.5;
+            float y = 2.5;
+            output(x < y);
+            return 0;
+        }
+    ";
+    assert_eq!(run(&compile(source), &[]), [1, 1, 0, 1, 0, 1, 1]);
+}
+
+#[test]
+fn remainders_take_the_sign_of_the_dividend() {
+    let source = "
+        int remainder(int a, int b) {
+            return a % b;
+        }
+
+        int main() {
+            output(-7 % 3);
+            output(7 % -3);
+            output(-7 % -3);
+            output(7 % 3);
+            output(remainder(-7, 3));
+            output(remainder(7, -3));
+            output(remainder(-7, -3));
+            output(remainder(6, -3));
+            output(remainder(-2147483647 - 1, -1));
+            output(remainder(-2147483647 - 1, 2147483647));
+            char c = -100;
+            output(c % 7);
+            return 0;
+        }
+    ";
+    assert_eq!(run(&compile(source), &[]), [-1, 1, -1, 1, -1, 1, -1, 0, 0, -1, -2]);
+}
+
+#[test]
+fn remainders_of_floats_are_rejected() {
+    for expression in ["x % 2", "2 % x", "x % 1.5"] {
+        let source = format!("int main() {{ float x = 1.5; float y = {}; return 0; }}", expression);
+        let diagnostics = CeriumCompiler::compile("test.cer", &source).unwrap_err();
+        assert!(diagnostics[0].to_string().contains("operator `%` cannot be applied"), "{}", diagnostics[0]);
+    }
+}
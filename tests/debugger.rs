@@ -0,0 +1,35 @@
+mod common;
+
+use cerium::cerium::instruction::instruction_parts::Register;
+use cerium::cerium::vm::Trap;
+use cerium::{CeriumVM, RunOutcome, ScriptedIo, VmConfig};
+use common::compile;
+
+#[test]
+fn reading_memory_leaves_the_vm_unchanged() {
+    // Stops at `input()` with a partly initialized heap block
+    let program = compile("
+        int main() {
+            int* p = new int[4];
+            p[0] = 1;
+            output(input());
+            return 0;
+        }
+    ");
+    let mut vm = CeriumVM::with_config(ScriptedIo::new([]), VmConfig::new().sanitize(true));
+    vm.load_program(&program);
+    assert!(matches!(vm.run(1_000), RunOutcome::Trapped(_)));
+    let snapshot = vm.snapshot();
+
+    // Uninitialized bytes can be inspected even though the guest could not read them
+    let block = vm.live_allocations()[0].address;
+    assert_eq!(vm.read_memory(block, 8), Ok(vec![0, 0, 0, 1, 0, 0, 0, 0]));
+
+    // Stack that the program has not used yet is not grown into existence
+    let sp = vm.register::<u32>(Register::SP);
+    assert!(vm.read_memory(sp, 4).is_ok());
+    assert!(matches!(vm.read_memory(sp, 1 << 16), Err(Trap::OutOfBounds { .. })));
+    assert!(matches!(vm.read_memory(u32::MAX, 2), Err(Trap::OutOfBounds { .. })));
+
+    assert_eq!(vm.snapshot(), snapshot);
+}
@@ -0,0 +1,17 @@
+mod common;
+
+use cerium::{CasmAssembler, CasmDisassembler};
+use common::{assemble_example, EXAMPLES};
+
+#[test]
+fn disassembly_reassembles_to_the_same_program() {
+    for path in EXAMPLES {
+        let program = assemble_example(path);
+        let source = CasmDisassembler::disassemble(&program);
+        let reassembled = CasmAssembler::assemble("disassembly.casm", &source)
+            .unwrap_or_else(|diagnostics| panic!("disassembly of {} does not assemble: {:?}", path, diagnostics));
+        assert_eq!(reassembled.entry_point, program.entry_point, "{}", path);
+        assert_eq!(reassembled.code, program.code, "{}", path);
+        assert_eq!(reassembled.rodata, program.rodata, "{}", path);
+    }
+}
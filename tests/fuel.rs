@@ -0,0 +1,53 @@
+mod common;
+
+use cerium::RunOutcome;
+use common::{assemble, assemble_example, load};
+use std::time::{Duration, Instant};
+
+#[test]
+fn running_out_of_fuel_can_be_resumed() {
+    let program = assemble_example("examples/collatz/collatz.casm");
+    let mut vm = load(&program, &[27]);
+    let mut runs = 0;
+    while let RunOutcome::OutOfFuel = vm.run(10) {
+        runs += 1;
+    }
+    assert!(runs > 10);
+    assert!(vm.is_done());
+    assert!(matches!(vm.run(10), RunOutcome::Halted));
+    // The sequence of 27 takes 111 steps to reach 1
+    assert_eq!(vm.io().outputs().len(), 112);
+    assert_eq!(vm.io().outputs().last(), Some(&1));
+}
+
+#[test]
+fn a_run_executes_at_most_its_limit() {
+    let program = assemble("
+        input -> r1
+        output <- r1
+        input -> r1
+        output <- r1
+        halt
+    ");
+    let mut vm = load(&program, &[1, 2]);
+    assert!(matches!(vm.run(0), RunOutcome::OutOfFuel));
+    assert!(matches!(vm.run(2), RunOutcome::OutOfFuel));
+    assert_eq!(vm.io().outputs(), [1]);
+    assert!(matches!(vm.run(3), RunOutcome::Halted));
+    assert_eq!(vm.io().outputs(), [1, 2]);
+}
+
+#[test]
+fn a_passed_deadline_stops_the_run_until_it_is_cleared() {
+    let program = assemble("
+    LOOP:
+        jmp LOOP always
+    ");
+    let mut vm = load(&program, &[]);
+    vm.set_deadline(Some(Instant::now()));
+    assert!(matches!(vm.run(u64::MAX), RunOutcome::TimedOut));
+    vm.set_deadline(None);
+    assert!(matches!(vm.run(1_000), RunOutcome::OutOfFuel));
+    vm.set_deadline(Some(Instant::now() + Duration::from_millis(20)));
+    assert!(matches!(vm.run(u64::MAX), RunOutcome::TimedOut));
+}
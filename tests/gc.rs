@@ -0,0 +1,61 @@
+mod common;
+
+use cerium::{CeriumVM, RunOutcome, ScriptedIo, VmConfig};
+use common::{compile, load};
+
+#[test]
+fn collection_frees_unreachable_blocks_only() {
+    // Stops at `input()` with one block still referenced from the stack
+    let program = compile("
+        int main() {
+            int* kept = new int[4];
+            int* lost = new int[8];
+            lost = null;
+            kept[0] = 7;
+            output(input());
+            return 0;
+        }
+    ");
+    let mut vm = load(&program, &[]);
+    assert!(matches!(vm.run(1_000), RunOutcome::Trapped(_)));
+    let allocations = vm.live_allocations();
+    assert_eq!(allocations.iter().map(|allocation| allocation.size).collect::<Vec<_>>(), [16, 32]);
+
+    let collection = vm.collect_garbage();
+    assert_eq!((collection.freed_blocks, collection.freed_bytes), (1, 32));
+    let kept = vm.live_allocations();
+    assert_eq!(kept.len(), 1);
+    assert_eq!(kept[0].address, allocations[0].address);
+    assert_eq!(vm.read_memory(kept[0].address, 4).unwrap(), 7i32.to_be_bytes());
+
+    assert_eq!(vm.collect_garbage().freed_blocks, 0);
+}
+
+#[test]
+fn automatic_collection_keeps_a_full_heap_usable() {
+    let program = compile("
+        int main() {
+            int* kept = new int;
+            *kept = 5;
+            int i = 0;
+            while (i < 100) {
+                int* block = new int[64];
+                block[0] = i;
+                i = i + 1;
+            }
+            output(*kept);
+            return 0;
+        }
+    ");
+    let run = |config: VmConfig| {
+        let mut vm = CeriumVM::with_config(ScriptedIo::new([]), config);
+        vm.load_program(&program);
+        let outcome = vm.run(1_000_000);
+        (outcome, vm.io().outputs().to_vec())
+    };
+
+    assert!(matches!(run(VmConfig::new().max_heap(4096)).0, RunOutcome::Trapped(_)));
+    let (outcome, outputs) = run(VmConfig::new().max_heap(4096).garbage_collect(true));
+    assert!(matches!(outcome, RunOutcome::Halted));
+    assert_eq!(outputs, [5]);
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use cerium::cerium::vm::Trap;
+use cerium::{BufferIo, CeriumVM, RunOutcome, ScriptedIo, VmIo};
+use common::assemble;
+
+const ECHO: &str = "
+LOOP:
+    input -> r1
+    output <- r1
+    jmp LOOP always
+";
+
+#[test]
+fn buffer_io_reads_words_and_collects_lines() {
+    let mut vm = CeriumVM::new(BufferIo::new(" 12\n-3\t7 "));
+    vm.load_program(&assemble(ECHO));
+    let RunOutcome::Trapped(err) = vm.run(1_000) else { panic!("the echo loop did not stop") };
+    assert_eq!(err.trap, Trap::InputEof);
+    assert_eq!(vm.io().output(), "12\n-3\n7\n");
+}
+
+#[test]
+fn buffer_io_rejects_malformed_input() {
+    let mut io = BufferIo::new("5 five 2147483648");
+    assert_eq!(io.input(), Ok(5));
+    assert_eq!(io.input(), Err(Trap::InvalidInput));
+    assert_eq!(io.input(), Err(Trap::InvalidInput));
+    assert_eq!(io.input(), Err(Trap::InputEof));
+
+    let mut vm = CeriumVM::new(BufferIo::new("1 x"));
+    vm.load_program(&assemble(ECHO));
+    let RunOutcome::Trapped(err) = vm.run(1_000) else { panic!("the echo loop did not stop") };
+    assert_eq!(err.trap, Trap::InvalidInput);
+    assert_eq!(vm.io().output(), "1\n");
+}
+
+#[test]
+fn scripted_io_records_outputs_until_its_inputs_run_out() {
+    let mut vm = CeriumVM::new(ScriptedIo::new([i32::MIN, 0, i32::MAX]));
+    vm.load_program(&assemble(ECHO));
+    let RunOutcome::Trapped(err) = vm.run(1_000) else { panic!("the echo loop did not stop") };
+    assert_eq!(err.trap, Trap::InputEof);
+    assert_eq!(vm.io().outputs(), [i32::MIN, 0, i32::MAX]);
+
+    let mut io = ScriptedIo::new([]);
+    assert_eq!(io.input(), Err(Trap::InputEof));
+    assert_eq!(io.output(4), Ok(()));
+    assert_eq!(io.outputs(), [4]);
+}
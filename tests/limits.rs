@@ -0,0 +1,81 @@
+mod common;
+
+use cerium::cerium::vm::Trap;
+use cerium::{CeriumVM, Program, RunOutcome, ScriptedIo, VmConfig};
+use common::compile;
+use std::process::Command;
+
+/// Allocates 100 blocks of 256 bytes without freeing any of them
+const HOARD: &str = "
+    int main() {
+        int i = 0;
+        while (i < 100) {
+            int* block = new int[64];
+            i = i + 1;
+        }
+        output(i);
+        return 0;
+    }
+";
+
+fn run_with_config(program: &Program, config: VmConfig) -> (RunOutcome, Vec<i32>) {
+    let mut vm = CeriumVM::with_config(ScriptedIo::new([]), config);
+    vm.load_program(program);
+    let outcome = vm.run(1_000_000);
+    (outcome, vm.io().outputs().to_vec())
+}
+
+#[test]
+fn the_heap_limit_causes_out_of_memory() {
+    let program = compile(HOARD);
+    let (outcome, outputs) = run_with_config(&program, VmConfig::new().max_heap(1 << 16));
+    assert!(matches!(outcome, RunOutcome::Halted));
+    assert_eq!(outputs, [100]);
+
+    let (outcome, outputs) = run_with_config(&program, VmConfig::new().max_heap(4096));
+    let RunOutcome::Trapped(err) = outcome else { panic!("the program did not trap") };
+    assert!(matches!(err.trap, Trap::OutOfMemory { limit: 4096, .. }), "{}", err);
+    assert!(outputs.is_empty());
+
+    let (outcome, _) = run_with_config(&program, VmConfig::new().initial_heap(4096).allow_growth(false));
+    assert!(matches!(outcome, RunOutcome::Trapped(err) if matches!(err.trap, Trap::OutOfMemory { .. })));
+}
+
+#[test]
+fn the_stack_limit_causes_stack_overflow() {
+    let program = compile("
+        int depth(int n) {
+            return depth(n + 1);
+        }
+
+        int main() {
+            return depth(0);
+        }
+    ");
+    let (outcome, _) = run_with_config(&program, VmConfig::new().max_stack(1024));
+    let RunOutcome::Trapped(err) = outcome else { panic!("the program did not trap") };
+    assert!(matches!(err.trap, Trap::StackOverflow { limit: 1024, .. }), "{}", err);
+}
+
+#[test]
+fn max_heap_flag_limits_the_heap() {
+    let path = std::env::temp_dir().join(format!("cerium-max-heap-{}.cer", std::process::id()));
+    std::fs::write(&path, HOARD).unwrap();
+    let run = |arguments: &[&str]| Command::new(env!("CARGO_BIN_EXE_cerium"))
+        .arg("run-src")
+        .arg(&path)
+        .args(arguments)
+        .output()
+        .unwrap();
+
+    let output = run(&[]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "100\nDone\n");
+
+    let output = run(&["--max-heap", "4K"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("out of memory") && stderr.contains("4096"), "{}", stderr);
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use cerium::cerium::linker::LinkError;
+use cerium::{CasmAssembler, Linker, ObjectFile};
+use common::run;
+
+const MAIN: &str = "
+    .entry MAIN
+    .extern TWICE, SEVEN
+MAIN:
+    lod r2 <- i SEVEN
+    mov i r1 <- i @r2
+    call TWICE
+    output <- r1
+    halt
+";
+
+const LIBRARY: &str = "
+    .global TWICE, SEVEN
+    .data
+    .int 99
+SEVEN:
+    .int 7
+    .code
+    halt
+TWICE:
+    add i r1 <- r1 + r1
+    ret
+";
+
+fn object(name: &str, source: &str) -> (String, ObjectFile) {
+    let object = CasmAssembler::assemble_object(name, source)
+        .unwrap_or_else(|diagnostics| panic!("{} does not assemble: {:?}", name, diagnostics));
+    (name.to_owned(), object)
+}
+
+#[test]
+fn externs_are_resolved_and_relocated() {
+    let main = object("main.o", MAIN);
+    let library = object("library.o", LIBRARY);
+    assert_eq!(ObjectFile::from_bytes(&main.1.to_bytes()), Ok(main.1.clone()));
+
+    for objects in [[main.clone(), library.clone()], [library, main]] {
+        let program = Linker::link(&objects).unwrap();
+        assert_eq!(run(&program, &[]), [14]);
+    }
+}
+
+#[test]
+fn symbols_are_placed_after_earlier_objects() {
+    let main = object("main.o", MAIN);
+    let library = object("library.o", LIBRARY);
+    let main_code = main.1.code.len() as u32;
+    let program = Linker::link(&[main, library]).unwrap();
+
+    let address = |name: &str| program.symbols.iter().find(|symbol| symbol.name == name).unwrap().address;
+    assert_eq!(address("MAIN"), 0);
+    // The library's code starts with a one-byte `halt`, and its data with a four-byte `.int`
+    assert_eq!(address("TWICE"), main_code + 1);
+    assert_eq!(address("SEVEN"), 0x4000_0004);
+}
+
+#[test]
+fn missing_and_duplicate_symbols_are_reported() {
+    let main = object("main.o", MAIN);
+    let errors = Linker::link(std::slice::from_ref(&main)).unwrap_err();
+    assert!(errors.contains(&LinkError::UndefinedSymbol { object: "main.o".to_owned(), name: "TWICE".to_owned() }));
+    assert!(errors.contains(&LinkError::UndefinedSymbol { object: "main.o".to_owned(), name: "SEVEN".to_owned() }));
+
+    let errors = Linker::link(&[main, object("a.o", LIBRARY), object("b.o", LIBRARY)]).unwrap_err();
+    assert!(errors.contains(&LinkError::DuplicateExport {
+        name: "TWICE".to_owned(),
+        first: "a.o".to_owned(),
+        second: "b.o".to_owned(),
+    }));
+}
@@ -0,0 +1,81 @@
+use cerium::{CasmAssembler, SymbolMap};
+use std::process::Command;
+
+const SOURCE: &str = "// Doubles its input
+START:
+    input -> r1
+    add i r1 <- r1 + r1
+    output <- r1
+    halt
+    .data
+TABLE:
+    .int 1, 2, 3";
+
+const LISTING: &str = "// double.casm
+    1                                       // Doubles its input
+    2                                       START:
+    3  0x00000000  a1                           input -> r1
+    4  0x00000001  ea 11 10                     add i r1 <- r1 + r1
+    5  0x00000004  b1                           output <- r1
+    6  0x00000005  40                           halt
+    7                                           .data
+    8                                       TABLE:
+    9  0x40000000  00 00 00 01 00 00 00 02      .int 1, 2, 3
+       0x40000008  00 00 00 03
+";
+
+#[test]
+fn listing_shows_the_address_and_bytes_of_each_line() {
+    let (program, listing) = CasmAssembler::assemble_with_listing("double.casm", SOURCE).unwrap();
+    assert_eq!(listing.to_string(), LISTING);
+    assert_eq!(program.code, [0xa1, 0xea, 0x11, 0x10, 0xb1, 0x40]);
+}
+
+#[test]
+fn symbol_maps_describe_addresses() {
+    let map = SymbolMap::parse("
+        // Symbol map of double.casm
+        0x00000000 START
+        0x00000004 SHOW   // the output
+        0x00000004 PRINT
+        0x40000000 TABLE
+    ").unwrap();
+    assert_eq!(map.describe(0), "START");
+    assert_eq!(map.describe(3), "START+0x3");
+    assert_eq!(map.describe(5), "PRINT+0x1");
+    assert_eq!(map.describe(0x4000_0008), "TABLE+0x8");
+    assert_eq!(SymbolMap::parse(&map.to_string()), Ok(map));
+
+    let error = SymbolMap::parse("0x0 START\n\n00000004 SHOW").unwrap_err();
+    assert_eq!(error.line, 3);
+    assert!(SymbolMap::parse("0x0 START EXTRA").is_err());
+    assert_eq!(SymbolMap::new(vec![]).describe(0x1c), "0x0000001c");
+}
+
+#[test]
+fn assemble_writes_the_listing_and_map_files() {
+    let directory = std::env::temp_dir().join(format!("cerium-listing-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let source = directory.join("double.casm");
+    std::fs::write(&source, SOURCE).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cerium"))
+        .current_dir(&directory)
+        .args(["assemble", "double.casm", "double.ce", "--listing", "double.lst", "--map", "double.map"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(std::fs::read_to_string(directory.join("double.lst")).unwrap(), LISTING);
+    let map = SymbolMap::parse(&std::fs::read_to_string(directory.join("double.map")).unwrap()).unwrap();
+    let names: Vec<_> = map.symbols().iter().map(|symbol| (symbol.name.as_str(), symbol.address)).collect();
+    assert_eq!(names, [("START", 0), ("TABLE", 0x4000_0000)]);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cerium"))
+        .current_dir(&directory)
+        .args(["run-asm", "double.casm", "--listing", "double.lst"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+
+    std::fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,30 @@
+mod common;
+
+use cerium::cerium::program::FormatError;
+use cerium::Program;
+use common::{assemble_example, crate_path, EXAMPLES};
+
+#[test]
+fn programs_round_trip_through_bytes() {
+    for path in EXAMPLES {
+        let program = assemble_example(path);
+        assert!(!program.symbols.is_empty() && !program.line_info.is_empty());
+        assert_eq!(Program::from_bytes(&program.to_bytes()), Ok(program), "{}", path);
+    }
+}
+
+#[test]
+fn malformed_files_are_rejected() {
+    let bytes = assemble_example(EXAMPLES[0]).to_bytes();
+    assert_eq!(Program::from_bytes(b"ELF\x7f"), Err(FormatError::BadMagic));
+    assert_eq!(Program::from_bytes(&bytes[..10]), Err(FormatError::Truncated));
+    assert!(matches!(Program::from_bytes(&bytes[..bytes.len() - 1]), Err(FormatError::SectionOutOfBounds(_))));
+}
+
+#[test]
+fn committed_examples_match_their_sources() {
+    for (source, binary) in EXAMPLES.iter().zip(["examples/collatz/collatz.ce", "examples/fibonacci/fib.ce"]) {
+        let committed = std::fs::read(crate_path(binary)).unwrap();
+        assert_eq!(assemble_example(source).to_bytes(), committed, "{} is stale, re-assemble {}", binary, source);
+    }
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use cerium::cerium::vm::{MemoryErrorKind, Trap};
+use cerium::{CeriumVM, Program, RunOutcome, ScriptedIo, VmConfig};
+use common::compile;
+
+fn run(program: &Program, sanitize: bool) -> (RunOutcome, Vec<i32>) {
+    let mut vm = CeriumVM::with_config(ScriptedIo::new([]), VmConfig::new().sanitize(sanitize));
+    vm.load_program(program);
+    let outcome = vm.run(100_000);
+    (outcome, vm.io().outputs().to_vec())
+}
+
+/// Runs `source` with the sanitizer on, expecting it to trap with an error of `kind`
+fn assert_reports(source: &str, kind: MemoryErrorKind) {
+    let (outcome, _) = run(&compile(source), true);
+    match outcome {
+        RunOutcome::Trapped(err) => match err.trap {
+            Trap::Sanitizer(report) => assert_eq!(report.kind, kind, "{}", report),
+            trap => panic!("expected {}, got {}", kind, trap),
+        },
+        _ => panic!("expected {}, but the program did not trap", kind),
+    }
+}
+
+#[test]
+fn use_after_free_traps() {
+    let source = "
+        int main() {
+            int* p = new int;
+            *p = 1;
+            del p;
+            output(*p);
+            return 0;
+        }
+    ";
+    assert_reports(source, MemoryErrorKind::UseAfterFree);
+    // Without the sanitizer the freed memory is still readable
+    assert!(matches!(run(&compile(source), false).0, RunOutcome::Halted));
+}
+
+#[test]
+fn double_free_traps() {
+    assert_reports("
+        int main() {
+            int* p = new int;
+            del p;
+            del p;
+            return 0;
+        }
+    ", MemoryErrorKind::DoubleFree);
+}
+
+#[test]
+fn out_of_bounds_and_uninitialized_accesses_trap() {
+    assert_reports("
+        int main() {
+            int* p = new int[2];
+            p[2] = 1;
+            return 0;
+        }
+    ", MemoryErrorKind::OutOfBounds);
+    assert_reports("
+        int main() {
+            int* p = new int[2];
+            p[0] = 1;
+            output(p[1]);
+            return 0;
+        }
+    ", MemoryErrorKind::UninitializedRead);
+}
+
+#[test]
+fn correct_programs_run_unchanged() {
+    let program = compile("
+        int main() {
+            int* p = new int[3];
+            p[0] = 1;
+            p[1] = 2;
+            p[2] = p[0] + p[1];
+            output(p[2]);
+            del p;
+            int* q = new int;
+            *q = 4;
+            output(*q);
+            del q;
+            return 0;
+        }
+    ");
+    let (outcome, outputs) = run(&program, true);
+    assert!(matches!(outcome, RunOutcome::Halted));
+    assert_eq!(outputs, [3, 4]);
+    assert_eq!(run(&program, false).1, outputs);
+}
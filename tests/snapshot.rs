@@ -0,0 +1,81 @@
+mod common;
+
+use cerium::{CeriumVM, RunOutcome, ScriptedIo, SnapshotError, VmConfig};
+use common::{assemble_example, compile, load};
+
+#[test]
+fn restored_snapshot_continues_the_program() {
+    let program = assemble_example("examples/collatz/collatz.casm");
+    let mut vm = load(&program, &[27]);
+    assert!(matches!(vm.run(100), RunOutcome::OutOfFuel));
+    let snapshot = vm.snapshot();
+    let printed = vm.io().outputs().len();
+    assert!(matches!(vm.run(u64::MAX), RunOutcome::Halted));
+
+    let mut resumed = CeriumVM::new(ScriptedIo::new([]));
+    resumed.restore(&snapshot).unwrap();
+    assert_eq!(resumed.snapshot(), snapshot);
+    assert!(matches!(resumed.run(u64::MAX), RunOutcome::Halted));
+    assert_eq!(resumed.io().outputs(), &vm.io().outputs()[printed..]);
+}
+
+#[test]
+fn inconsistent_heaps_are_rejected() {
+    // Stops at `input()` with two used heap blocks, which end the snapshot
+    let program = compile("
+        int main() {
+            int* a = new int;
+            int* b = new int;
+            output(input());
+            return 0;
+        }
+    ");
+    let mut vm = load(&program, &[]);
+    assert!(matches!(vm.run(1_000), RunOutcome::Trapped(_)));
+    let snapshot = vm.snapshot();
+    const BLOCK_SIZE: usize = 18;
+    let blocks_at = snapshot.len() - 2 * BLOCK_SIZE;
+    assert_eq!(snapshot[blocks_at - 4..blocks_at], 2u32.to_be_bytes());
+
+    let mut resumed = CeriumVM::new(ScriptedIo::new([]));
+    assert_eq!(resumed.restore(&snapshot), Ok(()));
+    assert_eq!(resumed.restore(&snapshot[..snapshot.len() - 1]), Err(SnapshotError::Truncated));
+    // Flips the bits `mask` of byte `offset` of a block, where the start is at 0, the free flag at
+    // 8 and the has-previous flag at 9
+    let restore_corrupted = |block: usize, offset: usize, mask: u8| {
+        let mut corrupted = snapshot.clone();
+        corrupted[blocks_at + block * BLOCK_SIZE + offset] ^= mask;
+        CeriumVM::new(ScriptedIo::new([])).restore(&corrupted)
+    };
+    let inconsistent = |message| Err(SnapshotError::Inconsistent(message));
+    assert_eq!(restore_corrupted(1, 3, 4), inconsistent("heap blocks overlap or leave gaps"));
+    assert_eq!(restore_corrupted(1, 9, 1), inconsistent("heap block does not link to the block before it"));
+    assert_eq!(restore_corrupted(0, 9, 1), inconsistent("heap block does not link to the block before it"));
+    assert_eq!(restore_corrupted(1, 8, 1), inconsistent("the last heap block is free"));
+}
+
+#[test]
+fn memory_limits_past_their_address_space_are_rejected() {
+    // Limits that are easy to find in the snapshot
+    const STACK_LIMIT: u32 = 0x0001_2345;
+    const HEAP_LIMIT: u32 = 0x0002_3456;
+    let program = assemble_example("examples/collatz/collatz.casm");
+    let config = VmConfig::new().max_stack(STACK_LIMIT).max_heap(HEAP_LIMIT);
+    let mut vm = CeriumVM::with_config(ScriptedIo::new([27]), config);
+    vm.load_program(&program);
+    vm.run(100);
+    let snapshot = vm.snapshot();
+
+    let restore_with_limit = |limit: u32, replacement: u32| {
+        let position = snapshot.windows(4).position(|bytes| bytes == limit.to_be_bytes()).unwrap();
+        assert_eq!(snapshot.windows(4).filter(|bytes| *bytes == limit.to_be_bytes()).count(), 1);
+        let mut changed = snapshot.clone();
+        changed[position..position + 4].copy_from_slice(&replacement.to_be_bytes());
+        CeriumVM::new(ScriptedIo::new([])).restore(&changed)
+    };
+    let too_large = Err(SnapshotError::Inconsistent("memory limit is larger than the region's address space"));
+    assert_eq!(restore_with_limit(STACK_LIMIT, VmConfig::STACK_ADDRESS_SPACE), Ok(()));
+    assert_eq!(restore_with_limit(STACK_LIMIT, VmConfig::STACK_ADDRESS_SPACE + 1), too_large);
+    assert_eq!(restore_with_limit(HEAP_LIMIT, VmConfig::HEAP_ADDRESS_SPACE), Ok(()));
+    assert_eq!(restore_with_limit(HEAP_LIMIT, VmConfig::HEAP_ADDRESS_SPACE + 1), too_large);
+}